@@ -1,4 +1,4 @@
-use crate::module::ModuleSpec;
+use crate::module::{ModuleSpec, RecordSpec};
 use crate::value::Upvalue;
 use std::cell::RefCell;
 use std::collections::HashMap;
@@ -8,6 +8,9 @@ pub struct Agent {
     pub string_table: Vec<String>,
     pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
     pub modules: HashMap<usize, ModuleSpec>,
+    pub records: Vec<RecordSpec>,
+    // the names of the native modules, by the path they're imported with
+    pub native_modules: HashMap<String, usize>,
 }
 
 impl Agent {
@@ -16,15 +19,23 @@ impl Agent {
             string_table: Vec::new(),
             upvalues: Vec::new(),
             modules: HashMap::new(),
+            records: Vec::new(),
+            native_modules: HashMap::new(),
         }
     }
 
+    pub fn add_record(&mut self, spec: RecordSpec) -> usize {
+        self.records.push(spec);
+        self.records.len() - 1
+    }
+
+    /// The id of a string that has already been interned.
+    pub fn find_string(&self, s: &str) -> Option<usize> {
+        self.string_table.iter().position(|interned| interned == s)
+    }
+
     pub fn intern_string(&mut self, s: &str) -> usize {
-        if let Some(idx) = self
-            .string_table
-            .iter()
-            .position(|ref interned| *interned == s)
-        {
+        if let Some(idx) = self.find_string(s) {
             idx
         } else {
             let idx = self.string_table.len();
@@ -34,6 +45,12 @@ impl Agent {
     }
 }
 
+impl Default for Agent {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,531 @@
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::error::VmError;
+use crate::interpreter::Interpreter;
+use crate::module::NativeModule;
+use crate::value::{sorted_entries, MapKey, Value};
+use crate::vm::Vm;
+
+fn tostring(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    Ok(Value::from(format!(
+        "{}",
+        args[0].display(&interpreter.agent)
+    )))
+}
+
+fn type_of(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    Ok(Value::from(args[0].type_of()))
+}
+
+fn print(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    print!("{}", args[0].display(&interpreter.agent));
+    io::stdout()
+        .flush()
+        .map_err(|_| VmError::NativeError("Failed to flush stdout".into()))?;
+    Ok(Value::Null)
+}
+
+fn println(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    let mut s = String::new();
+
+    for (i, v) in args
+        .iter()
+        .map(|v| format!("{}", v.display(&interpreter.agent)))
+        .enumerate()
+    {
+        s.push_str(&v);
+        if i < args.len() - 1 {
+            s.push(' ');
+        }
+    }
+
+    println!("{}", s);
+
+    Ok(Value::Null)
+}
+
+fn array_new(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let Some(Value::Integer(n)) = args.first() {
+        let len = usize::try_from(*n)
+            .map_err(|_| VmError::RuntimeError("array_new: Length can't be negative".into()))?;
+        interpreter.check_allocation(len)?;
+        Ok(Value::from(vec![Value::Null; len]))
+    } else {
+        Err(VmError::TypeError("array_new: Expected int".into()))
+    }
+}
+
+fn string_chars(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let Some(Value::String(s)) = args.first() {
+        Ok(Value::from(
+            s.chars()
+                .map(|c| Value::from(c.to_string()))
+                .collect::<Vec<_>>(),
+        ))
+    } else {
+        Err(VmError::TypeError("string_chars: Expected string".into()))
+    }
+}
+
+fn string_bytes(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let Some(Value::String(s)) = args.first() {
+        Ok(Value::from(
+            s.bytes()
+                .map(|b| Value::from(i64::from(b)))
+                .collect::<Vec<_>>(),
+        ))
+    } else {
+        Err(VmError::TypeError("string_bytes: Expected string".into()))
+    }
+}
+
+fn string_concat(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    let mut buf = String::new();
+
+    for arg in args {
+        if let Value::String(s) = arg {
+            buf += &s;
+        } else {
+            return Err(VmError::TypeError("string_concat: Expected string".into()));
+        }
+    }
+
+    Ok(Value::String(Rc::new(buf)))
+}
+
+// splitting on an empty separator splits a string into its characters
+fn split(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let (Value::String(s), Value::String(separator)) = (&args[0], &args[1]) {
+        let parts: Vec<Value> = if separator.is_empty() {
+            s.chars().map(|c| Value::from(c.to_string())).collect()
+        } else {
+            s.split(separator.as_str()).map(Value::from).collect()
+        };
+        Ok(Value::from(parts))
+    } else {
+        Err(VmError::TypeError("split: Expected strings".into()))
+    }
+}
+
+fn join(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let (Value::Array(array), Value::String(separator)) = (&args[0], &args[1]) {
+        let mut buf = String::new();
+        for (i, value) in array.borrow().iter().enumerate() {
+            if let Value::String(s) = value {
+                if i > 0 {
+                    buf += separator;
+                }
+                buf += s;
+            } else {
+                return Err(VmError::TypeError(
+                    format!("join: Expected strings, got {}", value.type_of()).into(),
+                ));
+            }
+        }
+        Ok(Value::from(buf))
+    } else {
+        Err(VmError::TypeError("join: Expected array and string".into()))
+    }
+}
+
+// the index in characters, or null if it isn't there
+fn find(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let (Value::String(s), Value::String(needle)) = (&args[0], &args[1]) {
+        Ok(match s.find(needle.as_str()) {
+            Some(offset) => Value::from(s[..offset].chars().count() as i64),
+            None => Value::Null,
+        })
+    } else {
+        Err(VmError::TypeError("find: Expected strings".into()))
+    }
+}
+
+fn replace(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let (Value::String(s), Value::String(from), Value::String(to)) =
+        (&args[0], &args[1], &args[2])
+    {
+        if from.is_empty() {
+            return Err(VmError::RuntimeError(
+                "replace: Can't replace an empty string".into(),
+            ));
+        }
+        Ok(Value::from(s.replace(from.as_str(), to)))
+    } else {
+        Err(VmError::TypeError("replace: Expected strings".into()))
+    }
+}
+
+fn starts_with(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let (Value::String(s), Value::String(prefix)) = (&args[0], &args[1]) {
+        Ok(Value::from(s.starts_with(prefix.as_str())))
+    } else {
+        Err(VmError::TypeError("starts_with: Expected strings".into()))
+    }
+}
+
+fn ends_with(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let (Value::String(s), Value::String(suffix)) = (&args[0], &args[1]) {
+        Ok(Value::from(s.ends_with(suffix.as_str())))
+    } else {
+        Err(VmError::TypeError("ends_with: Expected strings".into()))
+    }
+}
+
+fn trim(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let Value::String(s) = &args[0] {
+        Ok(Value::from(s.trim()))
+    } else {
+        Err(VmError::TypeError("trim: Expected string".into()))
+    }
+}
+
+fn upper(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let Value::String(s) = &args[0] {
+        Ok(Value::from(s.to_uppercase()))
+    } else {
+        Err(VmError::TypeError("upper: Expected string".into()))
+    }
+}
+
+fn lower(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let Value::String(s) = &args[0] {
+        Ok(Value::from(s.to_lowercase()))
+    } else {
+        Err(VmError::TypeError("lower: Expected string".into()))
+    }
+}
+
+fn repeat(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let (Value::String(s), Value::Integer(n)) = (&args[0], &args[1]) {
+        let n = usize::try_from(*n)
+            .map_err(|_| VmError::RuntimeError("repeat: Count can't be negative".into()))?;
+        interpreter.check_allocation(s.len().saturating_mul(n))?;
+        Ok(Value::from(s.repeat(n)))
+    } else {
+        Err(VmError::TypeError(
+            "repeat: Expected string and integer".into(),
+        ))
+    }
+}
+
+// strings are measured in characters
+fn length(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    let len = match &args[0] {
+        Value::String(s) => s.chars().count(),
+        Value::Array(array) => array.borrow().len(),
+        Value::Map(map) => map.borrow().len(),
+        Value::Range(range) => range.len(),
+        value => {
+            return Err(VmError::TypeError(
+                format!(
+                    "length: Expected a string or a collection, got {}",
+                    value.type_of()
+                )
+                .into(),
+            ))
+        }
+    };
+    // a range can be longer than the largest integer
+    i64::try_from(len)
+        .map(Value::from)
+        .map_err(|_| VmError::RuntimeError("length: Length doesn't fit in an integer".into()))
+}
+
+fn ord(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let Some(Value::String(s)) = args.first() {
+        if let Some(c) = s.chars().next() {
+            Ok(Value::from(c as i64))
+        } else {
+            Err(VmError::TypeError(
+                format!("ord: Expected string with length 1, got {:?}", s).into(),
+            ))
+        }
+    } else {
+        Err(VmError::TypeError(
+            "ord: Expected string with length 1".into(),
+        ))
+    }
+}
+
+fn chr(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let Some(Value::Integer(n)) = args.first() {
+        Ok(Value::from((*n as u8 as char).to_string()))
+    } else {
+        Err(VmError::TypeError("chr: Expected integer".into()))
+    }
+}
+
+fn array_length(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let Some(Value::Array(vs)) = args.first() {
+        Ok(Value::from(vs.borrow().len() as i64))
+    } else {
+        Err(VmError::TypeError("array_length: Expected array".into()))
+    }
+}
+
+fn to_index(name: &str, value: &Value) -> Result<usize, VmError> {
+    match value {
+        Value::Integer(i) => usize::try_from(*i).map_err(|_| {
+            VmError::IndexError(format!("{}: Index {} is out of bounds", name, i).into())
+        }),
+        _ => Err(VmError::TypeError(
+            format!("{}: Expected integer index", name).into(),
+        )),
+    }
+}
+
+fn array_push(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let Value::Array(array) = &args[0] {
+        let len = array.borrow().len();
+        interpreter.check_allocation(len + 1)?;
+        interpreter.track_growth(&args[0], 1)?;
+        array.borrow_mut().push(args[1].clone());
+        Ok(Value::Null)
+    } else {
+        Err(VmError::TypeError("array_push: Expected array".into()))
+    }
+}
+
+fn array_pop(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let Value::Array(array) = &args[0] {
+        Ok(array.borrow_mut().pop().unwrap_or(Value::Null))
+    } else {
+        Err(VmError::TypeError("array_pop: Expected array".into()))
+    }
+}
+
+fn array_insert(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let Value::Array(array) = &args[0] {
+        let index = to_index("array_insert", &args[1])?;
+        let len = array.borrow().len();
+        if index > len {
+            return Err(VmError::IndexError(
+                format!("array_insert: Index {} is out of bounds", index).into(),
+            ));
+        }
+        interpreter.check_allocation(len + 1)?;
+        interpreter.track_growth(&args[0], 1)?;
+        array.borrow_mut().insert(index, args[2].clone());
+        Ok(Value::Null)
+    } else {
+        Err(VmError::TypeError("array_insert: Expected array".into()))
+    }
+}
+
+fn array_remove(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let Value::Array(array) = &args[0] {
+        let index = to_index("array_remove", &args[1])?;
+        if index >= array.borrow().len() {
+            return Err(VmError::IndexError(
+                format!("array_remove: Index {} is out of bounds", index).into(),
+            ));
+        }
+        Ok(array.borrow_mut().remove(index))
+    } else {
+        Err(VmError::TypeError("array_remove: Expected array".into()))
+    }
+}
+
+// the elements from `start` up to, but not including, `end`
+fn array_slice(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let Value::Array(array) = &args[0] {
+        let array = array.borrow();
+        let start = to_index("array_slice", &args[1])?;
+        let end = match args.get(2) {
+            Some(end) => to_index("array_slice", end)?,
+            None => array.len(),
+        };
+        match array.get(start..end) {
+            Some(values) => Ok(Value::from(values.to_vec())),
+            None => Err(VmError::IndexError(
+                format!("array_slice: Range {}..{} is out of bounds", start, end).into(),
+            )),
+        }
+    } else {
+        Err(VmError::TypeError("array_slice: Expected array".into()))
+    }
+}
+
+fn array_concat(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    let mut values = Vec::new();
+    for arg in &args {
+        if let Value::Array(array) = arg {
+            interpreter.check_allocation(values.len() + array.borrow().len())?;
+            values.extend(array.borrow().iter().cloned());
+        } else {
+            return Err(VmError::TypeError("array_concat: Expected arrays".into()));
+        }
+    }
+
+    Ok(Value::from(values))
+}
+
+fn array_extend(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let (Value::Array(array), Value::Array(other)) = (&args[0], &args[1]) {
+        // the same array may be passed twice
+        let values = other.borrow().clone();
+        interpreter.check_allocation(array.borrow().len() + values.len())?;
+        interpreter.track_growth(&args[0], values.len())?;
+        array.borrow_mut().extend(values);
+        Ok(Value::Null)
+    } else {
+        Err(VmError::TypeError("array_extend: Expected arrays".into()))
+    }
+}
+
+fn map_key(name: &str, value: &Value) -> Result<MapKey, VmError> {
+    MapKey::from_value(value).ok_or_else(|| {
+        VmError::TypeError(format!("{}: Value can't be used as a map key", name).into())
+    })
+}
+
+// keys, values and entries come out sorted by key
+fn keys(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let Value::Map(map) = &args[0] {
+        let map = map.borrow();
+        Ok(Value::from(
+            sorted_entries(&map)
+                .into_iter()
+                .map(|(key, _)| Value::from(key.clone()))
+                .collect::<Vec<_>>(),
+        ))
+    } else {
+        Err(VmError::TypeError("keys: Expected map".into()))
+    }
+}
+
+fn values(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let Value::Map(map) = &args[0] {
+        let map = map.borrow();
+        Ok(Value::from(
+            sorted_entries(&map)
+                .into_iter()
+                .map(|(_, value)| value.clone())
+                .collect::<Vec<_>>(),
+        ))
+    } else {
+        Err(VmError::TypeError("values: Expected map".into()))
+    }
+}
+
+fn entries(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let Value::Map(map) = &args[0] {
+        let map = map.borrow();
+        let mut entries = Vec::with_capacity(map.len());
+        for (key, value) in sorted_entries(&map) {
+            let entry = Value::from(vec![Value::from(key.clone()), value.clone()]);
+            // only the array that gets returned is tracked on its own
+            interpreter.track(&entry)?;
+            entries.push(entry);
+        }
+        Ok(Value::from(entries))
+    } else {
+        Err(VmError::TypeError("entries: Expected map".into()))
+    }
+}
+
+fn has(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let Value::Map(map) = &args[0] {
+        let key = map_key("has", &args[1])?;
+        Ok(Value::from(map.borrow().contains_key(&key)))
+    } else {
+        Err(VmError::TypeError("has: Expected map".into()))
+    }
+}
+
+// returns whether the key was there
+fn delete(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let Value::Map(map) = &args[0] {
+        let key = map_key("delete", &args[1])?;
+        Ok(Value::from(map.borrow_mut().remove(&key).is_some()))
+    } else {
+        Err(VmError::TypeError("delete: Expected map".into()))
+    }
+}
+
+fn truncate32(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let Some(Value::Integer(i)) = args.first() {
+        Ok(Value::from(i64::from(*i as u32)))
+    } else {
+        Err(VmError::TypeError("truncate32: Expected integer".into()))
+    }
+}
+
+fn read_file(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    if let Some(Value::String(s)) = args.first() {
+        let path = interpreter.permissions().check_read(&**s)?;
+        Ok(Value::from(std::fs::read_to_string(path).map_err(|e| {
+            VmError::NativeError(format!("read_file: Failed to read file: {}", e).into())
+        })?))
+    } else {
+        Err(VmError::TypeError("read_file: Expected string".into()))
+    }
+}
+
+fn to_double(name: &str, value: &Value) -> Result<f64, VmError> {
+    match value {
+        Value::Integer(i) => Ok(*i as f64),
+        Value::Double(d) => Ok(*d),
+        _ => Err(VmError::TypeError(
+            format!("{}: Expected number", name).into(),
+        )),
+    }
+}
+
+fn math_sqrt(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    Ok(Value::from(to_double("sqrt", &args[0])?.sqrt()))
+}
+
+fn math_floor(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+    Ok(Value::from(to_double("floor", &args[0])?.floor() as i64))
+}
+
+/// Registers the functions every program can use without importing anything.
+pub(crate) fn register(vm: &mut Vm) {
+    vm.register_function("print", 1, false, print);
+    vm.register_function("println", 1, true, println);
+    vm.register_function("tostring", 1, false, tostring);
+    vm.register_function("type_of", 1, false, type_of);
+    vm.register_function("array_new", 1, false, array_new);
+    vm.register_function("array_length", 1, false, array_length);
+    vm.register_function("array_push", 2, false, array_push);
+    vm.register_function("array_pop", 1, false, array_pop);
+    vm.register_function("array_insert", 3, false, array_insert);
+    vm.register_function("array_remove", 2, false, array_remove);
+    vm.register_function("array_slice", 2, true, array_slice);
+    vm.register_function("array_concat", 0, true, array_concat);
+    vm.register_function("array_extend", 2, false, array_extend);
+    vm.register_function("keys", 1, false, keys);
+    vm.register_function("values", 1, false, values);
+    vm.register_function("entries", 1, false, entries);
+    vm.register_function("has", 2, false, has);
+    vm.register_function("delete", 2, false, delete);
+    vm.register_function("string_chars", 1, false, string_chars);
+    vm.register_function("string_bytes", 1, false, string_bytes);
+    vm.register_function("string_concat", 0, true, string_concat);
+    vm.register_function("split", 2, false, split);
+    vm.register_function("join", 2, false, join);
+    vm.register_function("find", 2, false, find);
+    vm.register_function("replace", 3, false, replace);
+    vm.register_function("starts_with", 2, false, starts_with);
+    vm.register_function("ends_with", 2, false, ends_with);
+    vm.register_function("trim", 1, false, trim);
+    vm.register_function("upper", 1, false, upper);
+    vm.register_function("lower", 1, false, lower);
+    vm.register_function("repeat", 2, false, repeat);
+    vm.register_function("length", 1, false, length);
+    vm.register_function("chr", 1, false, chr);
+    vm.register_function("ord", 1, false, ord);
+    vm.register_function("truncate32", 1, false, truncate32);
+    vm.register_function("read_file", 1, false, read_file);
+
+    vm.register_module(
+        "math",
+        NativeModule::new("Math")
+            .function("sqrt", 1, false, math_sqrt)
+            .function("floor", 1, false, math_floor)
+            .value("pi", Value::from(std::f64::consts::PI)),
+    );
+}
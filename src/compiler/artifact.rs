@@ -1,61 +1,356 @@
-// use crate::compiler::value::Value;
-
-// type Index = u32;
-// const INDEX_SIZE: usize = std::mem::size_of::<Index>();
-
-struct Artifact {
-    // constant_table: Vec<Value>,
-    code: Vec<u8>,
-}
-
-impl Into<Box<[u8]>> for Artifact {
-    fn into(self) -> Box<[u8]> {
-        // let mut bytes = Vec::new();
-
-        // bytes.extend(
-        //     (self.constant_table.len() as Index)
-        //         .to_le_bytes()
-        //         .into_iter(),
-        // );
-
-        // for constant in self.constant_table {
-        //     let const_bytes: Vec<u8> = constant.into();
-        //     let byte_len = (const_bytes.len() as Index).to_le_bytes();
-        //     bytes.extend(byte_len.into_iter());
-        //     bytes.extend(const_bytes);
-        // }
-
-        // bytes.into_boxed_slice()
-        self.code.into_boxed_slice()
-    }
-}
-
-// macro_rules! as_array {
-//     ($len:expr, $iter:expr) => {{
-//         let mut array = [0; $len];
-//
-//         for (i, n) in $iter.enumerate() {
-//             array[i] = n;
-//         }
-//
-//         array
-//     }};
-// }
-
-impl From<Vec<u8>> for Artifact {
-    fn from(bytes: Vec<u8>) -> Self {
-        // let constant_table_len =
-        //     Index::from_le_bytes(as_array!(INDEX_SIZE, bytes.drain(0..INDEX_SIZE)));
-        // let constant_table = Vec::with_capacity(constant_table_len as usize);
-
-        // for i in 0..constant_table_len {
-        //     let byte_len = Index::from_le_bytes(as_array!(INDEX_SIZE, bytes.drain(0..INDEX_SIZE)));
-        //     let value = Value::from(bytes.drain(0..byte_len));
-        //     constant_table.push(value);
-        // }
+use std::convert::TryFrom;
+
+use crate::agent::Agent;
+use crate::debuginfo::DebugInfo;
+use crate::error::VmError;
+use crate::module::{ModuleSpec, RecordSpec};
+
+const MAGIC: &[u8; 6] = b"RBCVM\0";
+// bump whenever the layout of the artifact or the bytecode changes
+const VERSION: u32 = 1;
+// operands in the code are native usizes, so an artifact only runs on
+// machines with the same pointer width
+const USIZE_SIZE: usize = std::mem::size_of::<usize>();
+
+/// A compiled program that can be run without its sources. The string table
+/// doubles as the constant pool, since every other constant is stored inline
+/// in the code.
+///
+/// Layout, with every number stored as a little endian u64:
+///
+/// ```text
+/// magic, version (u32), usize size (u8)
+/// string table: count, then (length, utf-8 bytes) for each string
+/// modules:      count, then name, export count, exports
+/// records:      count, then name, field count, fields
+/// debug info:   see DebugInfo::write
+/// code:         length, bytes
+/// ```
+#[derive(Debug)]
+pub(crate) struct Artifact {
+    pub(crate) string_table: Vec<String>,
+    pub(crate) modules: Vec<ModuleSpec>,
+    pub(crate) records: Vec<RecordSpec>,
+    pub(crate) debuginfo: DebugInfo,
+    pub(crate) code: Vec<u8>,
+}
+
+impl Artifact {
+    pub(crate) fn new(agent: &Agent, code: Vec<u8>, debuginfo: DebugInfo) -> Self {
+        let mut modules = agent.modules.values().cloned().collect::<Vec<_>>();
+        modules.sort_by_key(|spec| spec.name);
 
         Self {
-            /*constant_table,*/ code: bytes,
+            string_table: agent.string_table.clone(),
+            modules,
+            records: agent.records.clone(),
+            debuginfo,
+            code,
+        }
+    }
+
+    pub(crate) fn is_artifact(bytes: &[u8]) -> bool {
+        bytes.starts_with(MAGIC)
+    }
+
+    /// Moves the tables into the agent and hands back what the interpreter
+    /// needs. The agent must be fresh, so the indices in the code still point
+    /// at the right strings and records.
+    pub(crate) fn load(self, agent: &mut Agent) -> (Vec<u8>, DebugInfo) {
+        assert!(
+            agent.string_table.is_empty(),
+            "Artifacts can only be loaded into a fresh agent"
+        );
+
+        agent.string_table = self.string_table;
+        agent.records = self.records;
+        for spec in self.modules {
+            agent.modules.insert(spec.name, spec);
+        }
+
+        (self.code, self.debuginfo)
+    }
+}
+
+impl From<Artifact> for Box<[u8]> {
+    fn from(val: Artifact) -> Self {
+        let mut writer = Writer::new();
+
+        writer.raw(MAGIC);
+        writer.raw(&VERSION.to_le_bytes());
+        writer.raw(&[USIZE_SIZE as u8]);
+
+        writer.usize(val.string_table.len());
+        for string in &val.string_table {
+            writer.string(string);
+        }
+
+        writer.usize(val.modules.len());
+        for spec in &val.modules {
+            let mut exports = spec.exports().copied().collect::<Vec<_>>();
+            exports.sort_unstable();
+
+            writer.usize(spec.name);
+            writer.usize(exports.len());
+            for export in exports {
+                writer.usize(export);
+            }
+        }
+
+        writer.usize(val.records.len());
+        for spec in &val.records {
+            writer.usize(spec.name);
+            writer.usize(spec.fields.len());
+            for field in &spec.fields {
+                writer.usize(*field);
+            }
+        }
+
+        val.debuginfo.write(&mut writer);
+
+        writer.usize(val.code.len());
+        writer.raw(&val.code);
+
+        writer.bytes.into_boxed_slice()
+    }
+}
+
+impl TryFrom<&[u8]> for Artifact {
+    type Error = VmError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        let mut reader = Reader::new(bytes);
+
+        if reader.raw(MAGIC.len())? != MAGIC {
+            return Err(error("Missing artifact header"));
+        }
+
+        let mut version = [0; 4];
+        version.copy_from_slice(reader.raw(4)?);
+        let version = u32::from_le_bytes(version);
+        if version != VERSION {
+            return Err(error(&format!(
+                "Unsupported artifact version {}, expected {}",
+                version, VERSION
+            )));
+        }
+
+        if reader.raw(1)?[0] as usize != USIZE_SIZE {
+            return Err(error("Artifact was compiled for a different pointer width"));
+        }
+
+        let string_count = reader.usize()?;
+        let mut string_table = Vec::new();
+        for _ in 0..string_count {
+            string_table.push(reader.string()?);
+        }
+
+        let module_count = reader.usize()?;
+        let mut modules = Vec::new();
+        for _ in 0..module_count {
+            let mut spec = ModuleSpec::new(reader.index(string_table.len())?);
+            for _ in 0..reader.usize()? {
+                spec.add_export(reader.index(string_table.len())?);
+            }
+            modules.push(spec);
+        }
+
+        let record_count = reader.usize()?;
+        let mut records = Vec::new();
+        for _ in 0..record_count {
+            let name = reader.index(string_table.len())?;
+            let field_count = reader.usize()?;
+            let mut fields = Vec::new();
+            for _ in 0..field_count {
+                fields.push(reader.index(string_table.len())?);
+            }
+            records.push(RecordSpec::new(name, fields));
+        }
+
+        let debuginfo = DebugInfo::read(&mut reader)?;
+
+        let code_len = reader.usize()?;
+        let code = reader.raw(code_len)?.to_vec();
+
+        if !reader.is_empty() {
+            return Err(error("Unexpected data after the end of the artifact"));
+        }
+
+        Ok(Self {
+            string_table,
+            modules,
+            records,
+            debuginfo,
+            code,
+        })
+    }
+}
+
+fn error(message: &str) -> VmError {
+    VmError::CompileError(format!("Invalid artifact: {}", message).into())
+}
+
+pub(crate) struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn new() -> Self {
+        Self { bytes: Vec::new() }
+    }
+
+    fn raw(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn usize(&mut self, n: usize) {
+        self.raw(&(n as u64).to_le_bytes());
+    }
+
+    pub(crate) fn string(&mut self, s: &str) {
+        self.usize(s.len());
+        self.raw(s.as_bytes());
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    fn raw(&mut self, count: usize) -> Result<&'a [u8], VmError> {
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| error("Unexpected end of file"))?;
+
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub(crate) fn usize(&mut self) -> Result<usize, VmError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.raw(8)?);
+        usize::try_from(u64::from_le_bytes(bytes)).map_err(|_| error("Number out of range"))
+    }
+
+    // an index into a table with the given length
+    pub(crate) fn index(&mut self, len: usize) -> Result<usize, VmError> {
+        let idx = self.usize()?;
+        if idx < len {
+            Ok(idx)
+        } else {
+            Err(error(&format!("Index {} is out of bounds", idx)))
+        }
+    }
+
+    pub(crate) fn string(&mut self) -> Result<String, VmError> {
+        let len = self.usize()?;
+        let bytes = self.raw(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| error("String is not valid UTF-8"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parser::Position;
+    use crate::compiler::Compiler;
+    use crate::error::ErrorInfo;
+    use crate::interpreter::Interpreter;
+    use crate::value::Value;
+    use pretty_assertions::assert_eq;
+
+    fn compile(source: &str) -> Box<[u8]> {
+        let mut agent = Agent::new();
+        let mut compiler = Compiler::new(&mut agent);
+        compiler
+            .compile(".", "test.rbcvm".to_string(), source)
+            .unwrap();
+        let (code, debuginfo) = compiler.end();
+
+        Artifact::new(&agent, code.unwrap(), debuginfo).into()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = compile(
+            r#"
+module Test;
+record Point { x, y }
+export function norm(p) { return p.x * p.x + p.y * p.y; }
+let greeting = "hello";
+throw norm(Point { x = 3, y = 4 });
+"#,
+        );
+        assert!(Artifact::is_artifact(&bytes));
+
+        let artifact = Artifact::try_from(&bytes[..]).unwrap();
+        assert_eq!(artifact.records.len(), 1);
+        assert!(artifact.string_table.contains(&"hello".to_string()));
+
+        let mut agent = Agent::new();
+        let (code, debuginfo) = artifact.load(&mut agent);
+        let test = agent.intern_string("Test");
+        let norm = agent.intern_string("norm");
+        assert!(agent.modules[&test].has_export(norm));
+
+        let mut interpreter = Interpreter::new(agent);
+        interpreter.set_debuginfo(debuginfo);
+        match interpreter._evaluate(code) {
+            Err(error @ VmError::Thrown(..)) => {
+                assert_eq!(
+                    error,
+                    VmError::Thrown(
+                        Value::from(25),
+                        ErrorInfo {
+                            message: "25".to_string(),
+                            position: Some(Position { line: 6, column: 1 }),
+                            module: Some("Test".to_string()),
+                        }
+                    )
+                );
+            }
+            result => panic!("Expected thrown value, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_same_program_same_bytes() {
+        let source = "module A; export let a = 1; export let b = 2; export let c = 3;";
+        assert_eq!(compile(source), compile(source));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let bytes = compile("let a = 1;");
+
+        for len in [0, MAGIC.len(), bytes.len() / 2, bytes.len() - 1] {
+            assert!(Artifact::try_from(&bytes[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_wrong_version() {
+        let mut bytes = compile("let a = 1;").into_vec();
+        bytes[MAGIC.len()] = 0xff;
+
+        match Artifact::try_from(&bytes[..]) {
+            Err(VmError::CompileError(info)) => {
+                assert!(info.message.contains("Unsupported artifact version"))
+            }
+            result => panic!("Expected CompileError, got {:?}", result.map(|_| ())),
         }
     }
 }
@@ -144,6 +144,10 @@ impl Bytecode {
         self.op(OpCode::ConstString).usize(id)
     }
 
+    pub fn const_atom(&mut self, id: usize) -> &mut Bytecode {
+        self.op(OpCode::ConstAtom).usize(id)
+    }
+
     pub fn add(&mut self) -> &mut Bytecode {
         self.op(OpCode::Add)
     }
@@ -184,6 +188,62 @@ impl Bytecode {
         self.op(OpCode::Call).usize(num_args)
     }
 
+    pub fn call_spread(&mut self) -> &mut Bytecode {
+        self.op(OpCode::CallSpread)
+    }
+
+    pub fn push_handler(&mut self, ip: usize) -> &mut Bytecode {
+        self.op(OpCode::PushHandler).usize(ip)
+    }
+
+    pub fn pop_handler(&mut self) -> &mut Bytecode {
+        self.op(OpCode::PopHandler)
+    }
+
+    pub fn throw(&mut self) -> &mut Bytecode {
+        self.op(OpCode::Throw)
+    }
+
+    pub fn interpolate(&mut self, len: usize) -> &mut Bytecode {
+        self.op(OpCode::Interpolate).usize(len)
+    }
+
+    pub fn new_range(&mut self) -> &mut Bytecode {
+        self.op(OpCode::NewRange)
+    }
+
+    pub fn new_inclusive_range(&mut self) -> &mut Bytecode {
+        self.op(OpCode::NewInclusiveRange)
+    }
+
+    pub fn contains(&mut self) -> &mut Bytecode {
+        self.op(OpCode::Contains)
+    }
+
+    pub fn match_array(&mut self, len: usize, rest: bool) -> &mut Bytecode {
+        self.op(OpCode::MatchArray).usize(len).usize(rest as usize)
+    }
+
+    pub fn match_map(&mut self, len: usize) -> &mut Bytecode {
+        self.op(OpCode::MatchMap).usize(len)
+    }
+
+    pub fn match_record(&mut self, spec: usize) -> &mut Bytecode {
+        self.op(OpCode::MatchRecord).usize(spec)
+    }
+
+    pub fn array_rest(&mut self, start: usize) -> &mut Bytecode {
+        self.op(OpCode::ArrayRest).usize(start)
+    }
+
+    pub fn no_match(&mut self) -> &mut Bytecode {
+        self.op(OpCode::NoMatch)
+    }
+
+    pub fn get_iter(&mut self) -> &mut Bytecode {
+        self.op(OpCode::GetIter)
+    }
+
     pub fn ret(&mut self) -> &mut Bytecode {
         self.op(OpCode::Return)
     }
@@ -258,6 +318,18 @@ impl Bytecode {
         self.op(OpCode::NewArrayWithValues).usize(len)
     }
 
+    pub fn new_map(&mut self, len: usize) -> &mut Bytecode {
+        self.op(OpCode::NewMap).usize(len)
+    }
+
+    pub fn new_record(&mut self, spec: usize) -> &mut Bytecode {
+        self.op(OpCode::NewRecord).usize(spec)
+    }
+
+    pub fn array_concat(&mut self) -> &mut Bytecode {
+        self.op(OpCode::ArrayConcat)
+    }
+
     pub fn array_get(&mut self) -> &mut Bytecode {
         self.op(OpCode::ArrayGet)
     }
@@ -334,10 +406,33 @@ impl Bytecode {
         self.op(OpCode::Dup)
     }
 
+    pub fn dup2(&mut self) -> &mut Bytecode {
+        self.op(OpCode::Dup2)
+    }
+
+    pub fn rot3(&mut self) -> &mut Bytecode {
+        self.op(OpCode::Rot3)
+    }
+
+    /// Pushes a copy of the value `depth` values below the top, so `pick(0)`
+    /// is the same as `dup()`.
+    pub fn pick(&mut self, depth: usize) -> &mut Bytecode {
+        self.op(OpCode::Pick).usize(depth)
+    }
+
     pub fn allocate_locals(&mut self, count: usize) -> &mut Self {
         self.op(OpCode::AllocateLocals).usize(count)
     }
 
+    /// Continues after code that was compiled earlier. Its labels are gone,
+    /// so the code can't jump back into it.
+    pub fn resume(instructions: Vec<u8>) -> Bytecode {
+        Bytecode {
+            instructions,
+            ..Bytecode::new()
+        }
+    }
+
     pub fn into<T>(self) -> T
     where
         T: std::convert::From<std::vec::Vec<u8>>,
@@ -1,29 +1,58 @@
 use crate::agent::Agent;
 use crate::compiler::bytecode::Bytecode;
-use crate::compiler::parser::{Expression, ExpressionKind, Statement, StatementKind, TokenType};
+use crate::compiler::parser::Position;
+use crate::compiler::parser::{
+    Expression, ExpressionKind, MatchArm, Pattern, PatternKind, Statement, StatementKind, TokenType,
+};
 use crate::debuginfo::{self, DebugInfo};
-use crate::module::ModuleSpec;
+use crate::error::VmError;
+use crate::module::{ModuleSpec, RecordSpec};
 use crate::opcode::OpCode;
+use std::collections::{HashMap, HashSet};
 
-pub type CompileResult<T> = Result<T, String>;
+pub type CompileResult<T> = Result<T, VmError>;
+
+#[inline]
+fn error_at<T>(position: Position, message: String) -> CompileResult<T> {
+    Err(VmError::CompileError(message.into()).with_position(position))
+}
+
+#[inline]
+fn error<T>(message: &str) -> CompileResult<T> {
+    Err(VmError::CompileError(message.into()))
+}
 
 pub(crate) enum CompilerOutput {
     Module { code: Bytecode, spec: ModuleSpec },
     Script(Bytecode),
 }
 
+// handler_depth is the number of try blocks the loop itself is nested in, so
+// break and continue know how many handlers to pop before jumping
 enum LoopState {
     While {
         start_label: usize,
         end_label: usize,
+        handler_depth: usize,
     },
     For {
         start_label: usize,
         end_label: usize,
         increment_label: usize,
+        handler_depth: usize,
     },
 }
 
+impl LoopState {
+    fn handler_depth(&self) -> usize {
+        match self {
+            LoopState::While { handler_depth, .. } | LoopState::For { handler_depth, .. } => {
+                *handler_depth
+            }
+        }
+    }
+}
+
 struct FunctionState {
     start_label: usize,
     end_label: usize,
@@ -53,6 +82,8 @@ struct Binding {
     typ: BindingType,
     name: usize,
     index: usize,
+    // the record the variable holds, if that's known at compile time
+    record: Option<usize>,
 }
 
 #[derive(Debug)]
@@ -73,9 +104,23 @@ impl<'a> Scope<'a> {
     }
 
     pub fn push_binding(&mut self, typ: BindingType, name: usize) -> usize {
+        self.push_record_binding(typ, name, None)
+    }
+
+    pub fn push_record_binding(
+        &mut self,
+        typ: BindingType,
+        name: usize,
+        record: Option<usize>,
+    ) -> usize {
         let index = self.binding_count[typ as usize];
 
-        self.bindings.push(Binding { name, typ, index });
+        self.bindings.push(Binding {
+            name,
+            typ,
+            index,
+            record,
+        });
         self.binding_count[typ as usize] += 1;
 
         index
@@ -96,6 +141,13 @@ impl<'a> Scope<'a> {
     pub fn get_binding(&self, name: usize) -> Option<&Binding> {
         self.bindings.iter().rev().find(|b| b.name == name)
     }
+
+    pub fn parent_binding(&self, name: usize) -> Option<&Binding> {
+        let parent = self.parent?;
+        parent
+            .get_binding(name)
+            .or_else(|| parent.parent_binding(name))
+    }
 }
 
 struct CompilerState<'a> {
@@ -103,6 +155,9 @@ struct CompilerState<'a> {
     loop_state: Option<LoopState>,
     function_state: Option<FunctionState>,
     scope: Option<Scope<'a>>,
+    // number of try blocks around the code being compiled, within the
+    // current function
+    handler_depth: usize,
 }
 
 impl<'a> CompilerState<'a> {
@@ -112,6 +167,7 @@ impl<'a> CompilerState<'a> {
             loop_state: None,
             function_state: None,
             scope,
+            handler_depth: 0,
         }
     }
 
@@ -119,13 +175,15 @@ impl<'a> CompilerState<'a> {
         if let Some(scope) = &mut self.scope {
             if let Some(binding) = scope.get_binding(id) {
                 Some((*binding).clone())
-            } else if scope.parent_has_binding(id) {
+            } else if let Some(parent) = scope.parent_binding(id) {
+                let record = parent.record;
                 if let Some(function_state) = &mut self.function_state {
                     if let Some(idx) = function_state.free_variables.iter().position(|v| *v == id) {
                         Some(Binding {
                             typ: BindingType::Upvalue,
                             index: scope.binding_count[BindingType::Upvalue as usize] + idx,
                             name: id,
+                            record,
                         })
                     } else {
                         let idx = scope.binding_count[BindingType::Upvalue as usize]
@@ -135,6 +193,7 @@ impl<'a> CompilerState<'a> {
                             typ: BindingType::Upvalue,
                             index: idx,
                             name: id,
+                            record,
                         })
                     }
                 } else {
@@ -149,10 +208,186 @@ impl<'a> CompilerState<'a> {
     }
 }
 
+// where a match arm's variable lives while the arm is compiled: a local
+// reserved for it, or a hidden global at the top level
+#[derive(Clone, Copy)]
+enum ArmVariable {
+    Local(usize),
+    Global(usize),
+}
+
+struct MatchArmState<'p> {
+    guard: Option<&'p Expression>,
+    variables: Vec<(usize, ArmVariable)>,
+    body_label: usize,
+}
+
+// (arm, tests, bindings) of a row, with patterns by address
+type MatchRowKey = (usize, Vec<(usize, usize)>, Vec<(usize, usize)>);
+
+struct MatchState<'p> {
+    arms: Vec<MatchArmState<'p>>,
+    no_match_label: usize,
+    // subtrees compiled so far by stack depth and rows, so that one reached
+    // again is jumped to rather than compiled twice
+    subtrees: HashMap<(usize, Vec<MatchRowKey>), usize>,
+}
+
+// a row of the pattern matrix: what is left to test of an arm's pattern.
+// Each pattern is tested against a value kept on the stack, in a slot
+// counted from the matched value, which is slot 0
+#[derive(Clone)]
+struct MatchRow<'p> {
+    arm: usize,
+    tests: Vec<(usize, &'p Pattern)>,
+    // binding patterns and the slots of the values they bind
+    bindings: Vec<(usize, &'p Pattern)>,
+}
+
+impl<'p> MatchRow<'p> {
+    fn new(arm: usize, pattern: &'p Pattern) -> Self {
+        let mut row = MatchRow {
+            arm,
+            tests: Vec::new(),
+            bindings: Vec::new(),
+        };
+        row.add(0, pattern);
+        row
+    }
+
+    fn add(&mut self, slot: usize, pattern: &'p Pattern) {
+        match pattern.value {
+            PatternKind::Wildcard => {}
+            PatternKind::Binding(_) => self.bindings.push((slot, pattern)),
+            _ => self.tests.push((slot, pattern)),
+        }
+    }
+
+    // replaces the test at `index`, which passed, with the tests of the
+    // parts of its pattern, whose values were pushed from slot `base` on
+    fn expand(&self, index: usize, base: usize, parts: &[(usize, &'p Pattern)]) -> Self {
+        let mut row = MatchRow {
+            arm: self.arm,
+            tests: self.tests[..index].to_vec(),
+            bindings: self.bindings.clone(),
+        };
+        for (offset, pattern) in parts {
+            row.add(base + offset, pattern);
+        }
+        row.tests.extend_from_slice(&self.tests[index + 1..]);
+        row
+    }
+
+    fn key(&self) -> MatchRowKey {
+        let addresses = |patterns: &[(usize, &Pattern)]| {
+            patterns
+                .iter()
+                .map(|(slot, pattern)| (*slot, *pattern as *const Pattern as usize))
+                .collect()
+        };
+        (self.arm, addresses(&self.tests), addresses(&self.bindings))
+    }
+}
+
+// what a pattern other than a wildcard or binding checks about a value
+enum PatternTest<'p> {
+    Value(&'p Expression),
+    Range(&'p Expression, &'p Expression, bool),
+    Array { len: usize, rest: bool },
+    Map(&'p [(Expression, Pattern)]),
+    Record(usize),
+}
+
+impl<'p> PatternTest<'p> {
+    // whether `other` passes exactly when this test does
+    fn same_as(&self, other: &PatternTest) -> bool {
+        match (self, other) {
+            (PatternTest::Value(a), PatternTest::Value(b)) => same_constant(a, b),
+            (PatternTest::Range(a, b, x), PatternTest::Range(c, d, y)) => {
+                x == y && same_constant(a, c) && same_constant(b, d)
+            }
+            (PatternTest::Array { len: a, rest: x }, PatternTest::Array { len: b, rest: y }) => {
+                a == b && x == y
+            }
+            (PatternTest::Map(a), PatternTest::Map(b)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b.iter())
+                        .all(|(a, b)| same_constant(&a.0, &b.0))
+            }
+            (PatternTest::Record(a), PatternTest::Record(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    // whether `other` can't pass once this test has
+    fn excludes(&self, other: &PatternTest) -> bool {
+        match (self, other) {
+            (PatternTest::Value(a), PatternTest::Value(b)) => different_literals(a, b),
+            (PatternTest::Array { len: a, rest: x }, PatternTest::Array { len: b, rest: y }) => {
+                match (x, y) {
+                    (false, false) => a != b,
+                    (false, true) => a < b,
+                    (true, false) => b < a,
+                    (true, true) => false,
+                }
+            }
+            (PatternTest::Record(a), PatternTest::Record(b)) => a != b,
+            (PatternTest::Array { .. }, PatternTest::Map(_) | PatternTest::Record(_))
+            | (PatternTest::Map(_), PatternTest::Array { .. } | PatternTest::Record(_))
+            | (PatternTest::Record(_), PatternTest::Array { .. } | PatternTest::Map(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+// whether two constants in patterns are written the same way, which makes
+// them the same value
+fn same_constant(a: &Expression, b: &Expression) -> bool {
+    match (&a.value, &b.value) {
+        (ExpressionKind::Identifier(a), ExpressionKind::Identifier(b))
+        | (ExpressionKind::String(a), ExpressionKind::String(b))
+        | (ExpressionKind::Atom(a), ExpressionKind::Atom(b)) => a == b,
+        (ExpressionKind::Integer(a), ExpressionKind::Integer(b)) => a == b,
+        (ExpressionKind::Double(a), ExpressionKind::Double(b)) => a.to_bits() == b.to_bits(),
+        (ExpressionKind::Boolean(a), ExpressionKind::Boolean(b)) => a == b,
+        (ExpressionKind::Null, ExpressionKind::Null) => true,
+        (ExpressionKind::UnaryOperation(x, a), ExpressionKind::UnaryOperation(y, b)) => {
+            x == y && same_constant(a, b)
+        }
+        (ExpressionKind::BinaryOperation(a, x, b), ExpressionKind::BinaryOperation(c, y, d)) => {
+            x == y && same_constant(a, c) && same_constant(b, d)
+        }
+        _ => false,
+    }
+}
+
+// whether two literals are of the same type but never equal
+fn different_literals(a: &Expression, b: &Expression) -> bool {
+    match (&a.value, &b.value) {
+        (ExpressionKind::String(a), ExpressionKind::String(b))
+        | (ExpressionKind::Atom(a), ExpressionKind::Atom(b)) => a != b,
+        (ExpressionKind::Integer(a), ExpressionKind::Integer(b)) => a != b,
+        (ExpressionKind::Boolean(a), ExpressionKind::Boolean(b)) => a != b,
+        _ => false,
+    }
+}
+
 pub(crate) struct CodeGen<'a> {
     bytecode: Bytecode,
     agent: &'a mut Agent,
     debuginfo: &'a mut DebugInfo,
+    // records declared in the module being compiled, by name
+    records: HashMap<usize, usize>,
+    // names declared at the top level of the module being compiled
+    globals: HashSet<usize>,
+    // top-level variables known to hold a record, and which record
+    global_records: HashMap<usize, usize>,
+    // top-level names bound by the match arms being compiled, and the hidden
+    // globals that hold their values
+    arm_globals: Vec<(usize, usize)>,
+    arm_global_count: usize,
+    interactive: bool,
 }
 
 impl<'a> CodeGen<'a> {
@@ -169,9 +404,21 @@ impl<'a> CodeGen<'a> {
             agent,
             bytecode,
             debuginfo,
+            records: HashMap::new(),
+            globals: HashSet::new(),
+            global_records: HashMap::new(),
+            arm_globals: Vec::new(),
+            arm_global_count: 0,
+            interactive: false,
         }
     }
 
+    /// Leaves the value of the last statement on the stack if it's an
+    /// expression.
+    pub(crate) fn set_interactive(&mut self) {
+        self.interactive = true;
+    }
+
     pub(crate) fn compile<'b, T>(
         mut self,
         module: ModuleSpec,
@@ -181,11 +428,27 @@ impl<'a> CodeGen<'a> {
         T: Iterator<Item = &'b Statement>,
     {
         let mut state = CompilerState::new(true, None);
+        let statements = statements.collect::<Vec<_>>();
+
+        // records are resolved at compile time, so declare them all up front
+        // to allow functions to use records declared further down the file
+        for statement in &statements {
+            if let StatementKind::Record { name, fields } = &statement.value {
+                self.declare_record(name, fields)?;
+            }
+            self.declare_global(statement);
+        }
 
         self.bytecode.init_module(module.name);
 
-        for statement in statements {
-            self.compile_statement(&mut state, statement)?;
+        let last = statements.len().checked_sub(1);
+        for (i, statement) in statements.into_iter().enumerate() {
+            match &statement.value {
+                StatementKind::Expression(expr) if self.interactive && Some(i) == last => {
+                    self.compile_expression(&mut state, expr)?
+                }
+                _ => self.compile_statement(&mut state, statement)?,
+            }
         }
 
         self.bytecode.end_module();
@@ -198,11 +461,13 @@ impl<'a> CodeGen<'a> {
         state: &mut CompilerState,
         statement: &Statement,
     ) -> CompileResult<()> {
-        match statement.value {
+        let start = self.bytecode.instructions.len();
+        let result = match statement.value {
             StatementKind::Let { .. } => self.compile_let_statement(state, statement),
             StatementKind::Function { .. } => self.compile_function_statement(state, statement),
             StatementKind::If { .. } => self.compile_if_statement(state, statement),
             StatementKind::For { .. } => self.compile_for_statement(state, statement),
+            StatementKind::ForIn { .. } => self.compile_for_in_statement(state, statement),
             StatementKind::While { .. } => self.compile_while_statement(state, statement),
             StatementKind::Break => self.compile_break_statement(state, statement),
             StatementKind::Continue => self.compile_continue_statement(state, statement),
@@ -210,6 +475,97 @@ impl<'a> CodeGen<'a> {
             StatementKind::Return(_) => self.compile_return_statement(state, statement),
             StatementKind::Export(_) => self.compile_export_statement(state, statement),
             StatementKind::Import(_) => unreachable!(), // filtered out by parser
+            StatementKind::Record { .. } => self.compile_record_statement(state, statement),
+            StatementKind::Try { .. } => self.compile_try_statement(state, statement),
+            StatementKind::Throw(_) => self.compile_throw_statement(state, statement),
+            StatementKind::Match { .. } => self.compile_match_statement(state, statement),
+        };
+
+        // covers the instructions of the statement itself, like a throw or
+        // the jumps of a loop
+        self.debuginfo.insert(
+            start..self.bytecode.instructions.len(),
+            debuginfo::Context {
+                position: statement.position,
+                file: self.debuginfo.current_file(),
+            },
+        );
+
+        result
+    }
+
+    fn declare_record(&mut self, name: &Expression, fields: &[Expression]) -> CompileResult<()> {
+        let position = name.position;
+        let name = if let ExpressionKind::Identifier(name) = name.value {
+            name
+        } else {
+            unreachable!();
+        };
+
+        if self.records.contains_key(&name) {
+            return error_at(
+                position,
+                format!("Duplicate record {}", self.agent.string_table[name]),
+            );
+        }
+
+        let mut field_names = Vec::with_capacity(fields.len());
+        for field in fields {
+            if let ExpressionKind::Identifier(field_name) = field.value {
+                if field_names.contains(&field_name) {
+                    return error_at(
+                        field.position,
+                        format!(
+                            "Duplicate field {} in record {}",
+                            self.agent.string_table[field_name], self.agent.string_table[name],
+                        ),
+                    );
+                }
+                field_names.push(field_name);
+            } else {
+                unreachable!();
+            }
+        }
+
+        let spec = self.agent.add_record(RecordSpec::new(name, field_names));
+        self.records.insert(name, spec);
+
+        Ok(())
+    }
+
+    // remembers the name a top-level declaration binds, so that it isn't
+    // mistaken for a module
+    fn declare_global(&mut self, statement: &Statement) {
+        let name = match &statement.value {
+            StatementKind::Export(statement) => return self.declare_global(statement),
+            StatementKind::Let { name, .. }
+            | StatementKind::Function { name, .. }
+            | StatementKind::Record { name, .. } => name,
+            _ => return,
+        };
+
+        if let ExpressionKind::Identifier(name) = name.value {
+            self.globals.insert(name);
+        }
+    }
+
+    fn compile_record_statement(
+        &mut self,
+        state: &mut CompilerState,
+        statement: &Statement,
+    ) -> CompileResult<()> {
+        if let StatementKind::Record { .. } = &statement.value {
+            // already declared before compiling the module body
+            if state.is_global {
+                Ok(())
+            } else {
+                error_at(
+                    statement.position,
+                    "Records must be declared at the top level".to_string(),
+                )
+            }
+        } else {
+            unreachable!();
         }
     }
 
@@ -227,21 +583,24 @@ impl<'a> CodeGen<'a> {
             value,
         } = &statement.value
         {
-            if let Some(expression) = value {
-                self.compile_expression(state, &expression)?;
+            let record = if let Some(expression) = value {
+                let record = self.record_type(state, expression);
+                self.compile_expression(state, expression)?;
+                record
             } else {
                 self.bytecode.const_null();
-            }
+                None
+            };
             if state.is_global {
-                self.bytecode
-                    .declare_global(*name)
-                    .store_global(*name)
-                    .pop();
+                self.define_global(*name).pop();
+                if let Some(record) = record {
+                    self.global_records.insert(*name, record);
+                }
             } else if let Some(scope) = &mut state.scope {
-                let index = scope.push_binding(BindingType::Local, *name);
-                self.bytecode.store_local(index);
+                let index = scope.push_record_binding(BindingType::Local, *name, record);
+                self.bytecode.store_local(index).pop();
             } else {
-                return Err("Binding let value outside global scope with no scope".to_string());
+                return error("Binding let value outside global scope with no scope");
             }
             Ok(())
         } else {
@@ -259,22 +618,39 @@ impl<'a> CodeGen<'a> {
         let start_label = self.bytecode.new_label();
         let end_label = self.bytecode.new_label();
 
+        // a rest parameter is bound like any other argument, it just doesn't
+        // count towards the arity
+        let variadic = matches!(
+            parameters.last(),
+            Some(Expression {
+                value: ExpressionKind::Spread(_),
+                ..
+            })
+        );
+        let arity = parameters.len() - variadic as usize;
+
         self.bytecode
-            .op(OpCode::NewFunction)
-            .usize(name.unwrap_or(std::usize::MAX))
-            .usize(parameters.len()) // FIXME: This probably won't work with varargs
+            .op(if variadic {
+                OpCode::NewVariadicFunction
+            } else {
+                OpCode::NewFunction
+            })
+            .usize(name.unwrap_or(usize::MAX))
+            .usize(arity)
             .address_of_auto(start_label);
 
         let local_index = if let Some(name) = name {
             if state.is_global {
-                self.bytecode.declare_global(name).store_global(name).pop();
+                self.define_global(name).pop();
                 None
             } else {
                 Some(
                     state
                         .scope
                         .as_mut()
-                        .ok_or_else(|| "Missing scope in local scope".to_string())?
+                        .ok_or_else(|| {
+                            VmError::CompileError("Missing scope in local scope".into())
+                        })?
                         .push_binding(BindingType::Local, name),
                 )
             }
@@ -285,14 +661,20 @@ impl<'a> CodeGen<'a> {
         let mut inner_scope = Scope::new(state.scope.as_ref());
 
         for (i, parameter) in parameters.iter().enumerate() {
+            let parameter = match &parameter.value {
+                ExpressionKind::Spread(rest) => rest.as_ref(),
+                _ => parameter,
+            };
+
             if let ExpressionKind::Identifier(id) = parameter.value {
                 inner_scope.bindings.push(Binding {
                     name: id,
                     index: i,
                     typ: BindingType::Argument,
+                    record: None,
                 });
             } else {
-                return Err("Invalid parameter".to_string());
+                return error_at(parameter.position, "Invalid parameter".to_string());
             }
         }
 
@@ -305,7 +687,7 @@ impl<'a> CodeGen<'a> {
         self.bytecode.allocate_locals(0);
 
         for statement in body {
-            self.compile_statement(&mut inner_state, &statement)?;
+            self.compile_statement(&mut inner_state, statement)?;
         }
 
         self.bytecode.update_usize(
@@ -351,7 +733,7 @@ impl<'a> CodeGen<'a> {
         }
 
         if let Some(index) = local_index {
-            self.bytecode.store_local(index);
+            self.bytecode.store_local(index).pop();
         }
 
         Ok(())
@@ -411,7 +793,7 @@ impl<'a> CodeGen<'a> {
             let else_label = self.bytecode.new_label();
             let end_label = self.bytecode.new_label();
 
-            self.compile_expression(state, &predicate)?;
+            self.compile_expression(state, predicate)?;
 
             self.bytecode.op(OpCode::JumpIfFalse);
 
@@ -422,7 +804,7 @@ impl<'a> CodeGen<'a> {
             }
 
             for statement in then_body {
-                self.compile_statement(state, &statement)?;
+                self.compile_statement(state, statement)?;
             }
 
             if let Some(else_body) = else_body {
@@ -430,7 +812,7 @@ impl<'a> CodeGen<'a> {
                 self.bytecode.mark_label(else_label);
 
                 for statement in else_body {
-                    self.compile_statement(state, &statement)?;
+                    self.compile_statement(state, statement)?;
                 }
             }
 
@@ -462,10 +844,11 @@ impl<'a> CodeGen<'a> {
                 start_label,
                 end_label,
                 increment_label,
+                handler_depth: state.handler_depth,
             };
 
             if let Some(initializer) = initializer {
-                self.compile_statement(state, &initializer)?;
+                self.compile_statement(state, initializer)?;
             }
 
             // Only enter the loop state after compiling the initializer to
@@ -477,14 +860,14 @@ impl<'a> CodeGen<'a> {
             self.bytecode.mark_label(start_label);
 
             if let Some(predicate) = predicate {
-                self.compile_expression(state, &predicate)?;
+                self.compile_expression(state, predicate)?;
                 self.bytecode
                     .op(OpCode::JumpIfFalse)
                     .address_of_auto(end_label);
             }
 
             for statement in body {
-                self.compile_statement(state, &statement)?;
+                self.compile_statement(state, statement)?;
             }
 
             self.bytecode.mark_label(increment_label);
@@ -505,6 +888,110 @@ impl<'a> CodeGen<'a> {
         }
     }
 
+    fn compile_for_in_statement(
+        &mut self,
+        state: &mut CompilerState,
+        statement: &Statement,
+    ) -> CompileResult<()> {
+        if let StatementKind::ForIn {
+            key,
+            value,
+            iterable,
+            body,
+        } = &statement.value
+        {
+            let start_label = self.bytecode.new_label();
+            let end_label = self.bytecode.new_label();
+
+            // the iterator stays on the stack for the whole loop, so break
+            // and continue can jump like they do in a while loop
+            let loop_state = LoopState::While {
+                start_label,
+                end_label,
+                handler_depth: state.handler_depth,
+            };
+
+            self.compile_expression(state, iterable)?;
+            self.bytecode.get_iter();
+
+            let old_loop_state = state.loop_state.take();
+            state.loop_state = Some(loop_state);
+
+            self.bytecode.mark_label(start_label);
+            self.bytecode
+                .op(OpCode::IterNext)
+                .address_of_auto(end_label);
+
+            // the key is pushed before the value
+            self.bind_loop_variable(state, value)?;
+            if let Some(key) = key {
+                self.bind_loop_variable(state, key)?;
+            } else {
+                self.bytecode.pop();
+            }
+
+            for statement in body {
+                self.compile_statement(state, statement)?;
+            }
+
+            state.loop_state = old_loop_state;
+
+            self.bytecode.op(OpCode::Jump).address_of_auto(start_label);
+            self.bytecode.mark_label(end_label);
+            self.bytecode.pop().pop();
+
+            Ok(())
+        } else {
+            unreachable!();
+        }
+    }
+
+    fn bind_loop_variable(
+        &mut self,
+        state: &mut CompilerState,
+        name: &Expression,
+    ) -> CompileResult<()> {
+        if let ExpressionKind::Identifier(name) = name.value {
+            self.bind_name(state, name)
+        } else {
+            unreachable!();
+        }
+    }
+
+    // pops the top of the stack into a new variable
+    fn bind_name(&mut self, state: &mut CompilerState, name: usize) -> CompileResult<()> {
+        if state.is_global {
+            self.define_global(name);
+        } else if let Some(scope) = &mut state.scope {
+            let index = scope.push_binding(BindingType::Local, name);
+            self.bytecode.store_local(index);
+        } else {
+            return error("Binding variable outside global scope with no scope");
+        }
+        self.bytecode.pop();
+        Ok(())
+    }
+
+    // stores the top of the stack in a new top-level variable, which hides a
+    // match arm's variable of the same name for the rest of the arm
+    fn define_global(&mut self, name: usize) -> &mut Bytecode {
+        self.global_records.remove(&name);
+        if !self.arm_globals.is_empty() {
+            self.arm_globals.push((name, name));
+        }
+        self.bytecode.declare_global(name).store_global(name)
+    }
+
+    // the hidden global holding the value of a match arm's variable while
+    // the arm is compiled
+    fn arm_global(&self, name: usize) -> Option<usize> {
+        self.arm_globals
+            .iter()
+            .rev()
+            .find(|(arm_name, _)| *arm_name == name)
+            .map(|(_, global)| *global)
+    }
+
     fn compile_while_statement(
         &mut self,
         state: &mut CompilerState,
@@ -517,6 +1004,7 @@ impl<'a> CodeGen<'a> {
             let loop_state = LoopState::While {
                 start_label,
                 end_label,
+                handler_depth: state.handler_depth,
             };
 
             // Only enter the loop state after compiling the initializer to
@@ -527,13 +1015,13 @@ impl<'a> CodeGen<'a> {
 
             self.bytecode.mark_label(start_label);
 
-            self.compile_expression(state, &predicate)?;
+            self.compile_expression(state, predicate)?;
             self.bytecode
                 .op(OpCode::JumpIfFalse)
                 .address_of_auto(end_label);
 
             for statement in body {
-                self.compile_statement(state, &statement)?;
+                self.compile_statement(state, statement)?;
             }
 
             state.loop_state = old_loop_state;
@@ -559,11 +1047,17 @@ impl<'a> CodeGen<'a> {
                     LoopState::For { end_label, .. } => end_label,
                 };
 
+                for _ in loop_state.handler_depth()..state.handler_depth {
+                    self.bytecode.pop_handler();
+                }
                 self.bytecode.op(OpCode::Jump).address_of_auto(end_label);
 
                 Ok(())
             } else {
-                Err("Unexpected break outside of loop context".to_string())
+                error_at(
+                    statement.position,
+                    "Unexpected break outside of loop context".to_string(),
+                )
             }
         } else {
             unreachable!();
@@ -584,12 +1078,108 @@ impl<'a> CodeGen<'a> {
                     } => increment_label,
                 };
 
+                for _ in loop_state.handler_depth()..state.handler_depth {
+                    self.bytecode.pop_handler();
+                }
                 self.bytecode.op(OpCode::Jump).address_of_auto(start_label);
 
                 Ok(())
             } else {
-                Err("Unexpected break outside of loop context".to_string())
+                error_at(
+                    statement.position,
+                    "Unexpected continue outside of loop context".to_string(),
+                )
+            }
+        } else {
+            unreachable!();
+        }
+    }
+
+    fn compile_try_statement(
+        &mut self,
+        state: &mut CompilerState,
+        statement: &Statement,
+    ) -> CompileResult<()> {
+        if let StatementKind::Try {
+            body,
+            binding:
+                Expression {
+                    value: ExpressionKind::Identifier(name),
+                    ..
+                },
+            handler,
+        } = &statement.value
+        {
+            let catch_label = self.bytecode.new_label();
+            let end_label = self.bytecode.new_label();
+
+            self.bytecode
+                .op(OpCode::PushHandler)
+                .address_of_auto(catch_label);
+
+            state.handler_depth += 1;
+            for statement in body {
+                self.compile_statement(state, statement)?;
+            }
+            state.handler_depth -= 1;
+
+            self.bytecode
+                .pop_handler()
+                .op(OpCode::Jump)
+                .address_of_auto(end_label);
+
+            // the interpreter pops the handler and pushes the error before
+            // jumping here
+            self.bytecode.mark_label(catch_label);
+            if state.is_global {
+                self.define_global(*name);
+            } else if let Some(scope) = &mut state.scope {
+                let index = scope.push_binding(BindingType::Local, *name);
+                self.bytecode.store_local(index);
+            } else {
+                return error("Binding catch value outside global scope with no scope");
+            }
+            self.bytecode.pop();
+
+            for statement in handler {
+                self.compile_statement(state, statement)?;
             }
+
+            self.bytecode.mark_label(end_label);
+
+            Ok(())
+        } else {
+            unreachable!();
+        }
+    }
+
+    fn compile_throw_statement(
+        &mut self,
+        state: &mut CompilerState,
+        statement: &Statement,
+    ) -> CompileResult<()> {
+        if let StatementKind::Throw(expr) = &statement.value {
+            self.compile_expression(state, expr)?;
+            self.bytecode.throw();
+
+            Ok(())
+        } else {
+            unreachable!();
+        }
+    }
+
+    fn compile_match_statement(
+        &mut self,
+        state: &mut CompilerState,
+        statement: &Statement,
+    ) -> CompileResult<()> {
+        if let StatementKind::Match { value, arms } = &statement.value {
+            self.compile_match(state, value, arms, |codegen, state, body| {
+                for statement in body {
+                    codegen.compile_statement(state, statement)?;
+                }
+                Ok(())
+            })
         } else {
             unreachable!();
         }
@@ -629,15 +1219,20 @@ impl<'a> CodeGen<'a> {
         state: &mut CompilerState,
         expression: &Expression,
     ) -> CompileResult<()> {
-        let start = self.bytecode.position();
+        let start = self.bytecode.instructions.len();
         let result = match expression.value {
             ExpressionKind::Identifier(_) => self.compile_identifier_expression(state, expression),
             ExpressionKind::Integer(_) => self.compile_integer_expression(state, expression),
             ExpressionKind::Double(_) => self.compile_double_expression(state, expression),
             ExpressionKind::String(_) => self.compile_string_expression(state, expression),
+            ExpressionKind::Interpolation(_) => {
+                self.compile_interpolation_expression(state, expression)
+            }
+            ExpressionKind::Atom(_) => self.compile_atom_expression(state, expression),
             ExpressionKind::Null => self.compile_null_expression(state, expression),
             ExpressionKind::Boolean(_) => self.compile_boolean_expression(state, expression),
             ExpressionKind::Array(_) => self.compile_array_expression(state, expression),
+            ExpressionKind::Map(_) => self.compile_map_expression(state, expression),
             ExpressionKind::Function { .. } => self.compile_function_expression(state, expression),
             ExpressionKind::UnaryOperation(..) => {
                 self.compile_unary_operation_expression(state, expression)
@@ -647,12 +1242,19 @@ impl<'a> CodeGen<'a> {
             }
             ExpressionKind::Call(..) => self.compile_call_expression(state, expression),
             ExpressionKind::Index(..) => self.compile_index_expression(state, expression),
+            ExpressionKind::Record { .. } => self.compile_record_expression(state, expression),
+            ExpressionKind::Match(..) => self.compile_match_expression(state, expression),
+            ExpressionKind::Spread(_) => error_at(
+                expression.position,
+                "Spread is only allowed in call arguments".to_string(),
+            ),
         };
 
         self.debuginfo.insert(
-            start..self.bytecode.position(),
+            start..self.bytecode.instructions.len(),
             debuginfo::Context {
                 position: expression.position,
+                file: self.debuginfo.current_file(),
             },
         );
 
@@ -677,14 +1279,13 @@ impl<'a> CodeGen<'a> {
                         if state.function_state.is_some() {
                             self.bytecode.load_upvalue(binding.index);
                         } else {
-                            return Err(
-                                "Attempting to load upvalue when not in function".to_string()
-                            );
+                            return error("Attempting to load upvalue when not in function");
                         }
                     }
                 }
             } else {
-                self.bytecode.load_global(id);
+                let global = self.arm_global(id).unwrap_or(id);
+                self.bytecode.load_global(global);
             }
 
             Ok(())
@@ -735,13 +1336,27 @@ impl<'a> CodeGen<'a> {
         }
     }
 
-    fn compile_boolean_expression(
+    fn compile_atom_expression(
         &mut self,
         _state: &mut CompilerState,
         expression: &Expression,
     ) -> CompileResult<()> {
-        if let ExpressionKind::Boolean(b) = expression.value {
-            if b {
+        if let ExpressionKind::Atom(id) = expression.value {
+            self.bytecode.const_atom(id);
+
+            Ok(())
+        } else {
+            unreachable!();
+        }
+    }
+
+    fn compile_boolean_expression(
+        &mut self,
+        _state: &mut CompilerState,
+        expression: &Expression,
+    ) -> CompileResult<()> {
+        if let ExpressionKind::Boolean(b) = expression.value {
+            if b {
                 self.bytecode.const_true();
             } else {
                 self.bytecode.const_false();
@@ -767,6 +1382,44 @@ impl<'a> CodeGen<'a> {
         }
     }
 
+    // all the pieces are joined at once, instead of one concatenation each
+    fn compile_interpolation_expression(
+        &mut self,
+        state: &mut CompilerState,
+        expression: &Expression,
+    ) -> CompileResult<()> {
+        if let ExpressionKind::Interpolation(parts) = &expression.value {
+            for part in parts {
+                self.compile_expression(state, part)?;
+            }
+
+            self.bytecode.interpolate(parts.len());
+
+            Ok(())
+        } else {
+            unreachable!();
+        }
+    }
+
+    fn compile_map_expression(
+        &mut self,
+        state: &mut CompilerState,
+        expression: &Expression,
+    ) -> CompileResult<()> {
+        if let ExpressionKind::Map(entries) = &expression.value {
+            for (key, value) in entries {
+                self.compile_expression(state, key)?;
+                self.compile_expression(state, value)?;
+            }
+
+            self.bytecode.new_map(entries.len());
+
+            Ok(())
+        } else {
+            unreachable!();
+        }
+    }
+
     fn compile_array_expression(
         &mut self,
         state: &mut CompilerState,
@@ -803,6 +1456,17 @@ impl<'a> CodeGen<'a> {
         expression: &Expression,
     ) -> CompileResult<()> {
         if let ExpressionKind::Call(func, args) = &expression.value {
+            if args
+                .iter()
+                .any(|arg| matches!(arg.value, ExpressionKind::Spread(_)))
+            {
+                self.compile_spread_arguments(state, args)?;
+                self.compile_expression(state, func)?;
+                self.bytecode.call_spread();
+
+                return Ok(());
+            }
+
             for arg in args.iter().rev() {
                 self.compile_expression(state, arg)?;
             }
@@ -816,6 +1480,45 @@ impl<'a> CodeGen<'a> {
         }
     }
 
+    /// Builds a single array holding all of the arguments, left to right,
+    /// by concatenating runs of plain arguments with the spread arrays.
+    fn compile_spread_arguments(
+        &mut self,
+        state: &mut CompilerState,
+        args: &[Expression],
+    ) -> CompileResult<()> {
+        let mut has_array = false;
+        let mut pending = 0;
+
+        for arg in args {
+            if let ExpressionKind::Spread(inner) = &arg.value {
+                if pending > 0 {
+                    self.bytecode.new_array_with_values(pending);
+                    if has_array {
+                        self.bytecode.array_concat();
+                    }
+                    has_array = true;
+                    pending = 0;
+                }
+
+                self.compile_expression(state, inner)?;
+                if has_array {
+                    self.bytecode.array_concat();
+                }
+                has_array = true;
+            } else {
+                self.compile_expression(state, arg)?;
+                pending += 1;
+            }
+        }
+
+        if pending > 0 {
+            self.bytecode.new_array_with_values(pending).array_concat();
+        }
+
+        Ok(())
+    }
+
     fn compile_binary_operation_expression(
         &mut self,
         state: &mut CompilerState,
@@ -824,6 +1527,10 @@ impl<'a> CodeGen<'a> {
         if let ExpressionKind::BinaryOperation(left, op, right) = &expression.value {
             match op {
                 TokenType::Equal => {
+                    if let ExpressionKind::Identifier(_) = left.value {
+                        self.check_record_assignment(state, left, Some(right))?;
+                    }
+
                     self.compile_expression(state, right)?;
                     match &left.as_ref().value {
                         ExpressionKind::Identifier(id) => {
@@ -838,19 +1545,43 @@ impl<'a> CodeGen<'a> {
                                     }
                                 };
                             } else {
-                                self.bytecode.store_global(*id);
+                                let global = self.arm_global(*id).unwrap_or(*id);
+                                self.bytecode.store_global(global);
                             }
                         }
 
                         ExpressionKind::Index(arr, index) => {
-                            self.compile_expression(state, &arr)?;
-                            self.compile_expression(state, &index)?;
+                            self.compile_expression(state, arr)?;
+                            self.compile_expression(state, index)?;
+                            self.bytecode.array_set();
+                        }
+
+                        ExpressionKind::BinaryOperation(object, TokenType::Dot, field)
+                            if self.module_reference(state, object).is_none() =>
+                        {
+                            self.compile_record_field(state, object, field)?;
                             self.bytecode.array_set();
                         }
 
-                        _ => unimplemented!(),
+                        _ => {
+                            return error_at(left.position, "Invalid assignment target".to_string())
+                        }
                     }
                 }
+                TokenType::PlusEqual
+                | TokenType::MinusEqual
+                | TokenType::StarEqual
+                | TokenType::SlashEqual
+                | TokenType::PercentEqual
+                | TokenType::StarStarEqual
+                | TokenType::AndEqual
+                | TokenType::PipeEqual
+                | TokenType::CaretEqual
+                | TokenType::LessLessEqual
+                | TokenType::GreaterGreaterEqual => {
+                    self.compile_compound_assignment(state, left, *op, right)?;
+                }
+
                 TokenType::EqualEqual
                 | TokenType::BangEqual
                 | TokenType::Star
@@ -865,46 +1596,41 @@ impl<'a> CodeGen<'a> {
                 | TokenType::GreaterThanEqual
                 | TokenType::And
                 | TokenType::Pipe
-                | TokenType::Caret => {
+                | TokenType::Caret
+                | TokenType::LessLess
+                | TokenType::GreaterGreater
+                | TokenType::DotDot
+                | TokenType::DotDotEqual
+                | TokenType::In => {
                     self.compile_expression(state, left)?;
                     self.compile_expression(state, right)?;
-                    match op {
-                        TokenType::EqualEqual => self.bytecode.equal(),
-                        TokenType::BangEqual => self.bytecode.not_equal(),
-                        TokenType::Star => self.bytecode.mul(),
-                        TokenType::Minus => self.bytecode.sub(),
-                        TokenType::Plus => self.bytecode.add(),
-                        TokenType::StarStar => self.bytecode.exp(),
-                        TokenType::Percent => self.bytecode.rem(),
-                        TokenType::Slash => self.bytecode.div(),
-                        TokenType::LessThan => self.bytecode.less_than(),
-                        TokenType::LessThanEqual => self.bytecode.less_than_equal(),
-                        TokenType::GreaterThan => self.bytecode.greater_than(),
-                        TokenType::GreaterThanEqual => self.bytecode.greater_than_equal(),
-                        TokenType::And => self.bytecode.bitwise_and(),
-                        TokenType::Pipe => self.bytecode.bitwise_or(),
-                        TokenType::Caret => self.bytecode.bitwise_xor(),
-                        _ => unreachable!(),
-                    };
+                    self.compile_binary_operator(*op);
                 }
 
                 TokenType::Dot => {
-                    if let ExpressionKind::Identifier(module_name) = left.value {
+                    if let Some(module_name) = self.module_reference(state, left) {
                         if let ExpressionKind::Identifier(export_name) = right.value {
                             if self.agent.modules[&module_name].has_export(export_name) {
                                 self.bytecode.load_from_module(module_name, export_name);
                             } else {
-                                return Err(format!(
-                                    "Module {} has no export {}",
-                                    self.agent.string_table[module_name],
-                                    self.agent.string_table[export_name]
-                                ));
+                                return error_at(
+                                    right.position,
+                                    format!(
+                                        "Module {} has no export {}",
+                                        self.agent.string_table[module_name],
+                                        self.agent.string_table[export_name]
+                                    ),
+                                );
                             }
                         } else {
-                            return Err("Expected export name to be an identifier".to_string());
+                            return error_at(
+                                right.position,
+                                "Expected export name to be an identifier".to_string(),
+                            );
                         }
                     } else {
-                        return Err("Expected module name to be an identifier".to_string());
+                        self.compile_record_field(state, left, right)?;
+                        self.bytecode.array_get();
                     }
                 }
 
@@ -935,6 +1661,769 @@ impl<'a> CodeGen<'a> {
         }
     }
 
+    fn compile_binary_operator(&mut self, op: TokenType) {
+        match op {
+            TokenType::EqualEqual => self.bytecode.equal(),
+            TokenType::BangEqual => self.bytecode.not_equal(),
+            TokenType::Star | TokenType::StarEqual => self.bytecode.mul(),
+            TokenType::Minus | TokenType::MinusEqual => self.bytecode.sub(),
+            TokenType::Plus | TokenType::PlusEqual => self.bytecode.add(),
+            TokenType::StarStar | TokenType::StarStarEqual => self.bytecode.exp(),
+            TokenType::Percent | TokenType::PercentEqual => self.bytecode.rem(),
+            TokenType::Slash | TokenType::SlashEqual => self.bytecode.div(),
+            TokenType::LessThan => self.bytecode.less_than(),
+            TokenType::LessThanEqual => self.bytecode.less_than_equal(),
+            TokenType::GreaterThan => self.bytecode.greater_than(),
+            TokenType::GreaterThanEqual => self.bytecode.greater_than_equal(),
+            TokenType::And | TokenType::AndEqual => self.bytecode.bitwise_and(),
+            TokenType::Pipe | TokenType::PipeEqual => self.bytecode.bitwise_or(),
+            TokenType::Caret | TokenType::CaretEqual => self.bytecode.bitwise_xor(),
+            TokenType::LessLess | TokenType::LessLessEqual => self.bytecode.shift_left(),
+            TokenType::GreaterGreater | TokenType::GreaterGreaterEqual => {
+                self.bytecode.shift_right()
+            }
+            TokenType::DotDot => self.bytecode.new_range(),
+            TokenType::DotDotEqual => self.bytecode.new_inclusive_range(),
+            TokenType::In => self.bytecode.contains(),
+            _ => unreachable!(),
+        };
+    }
+
+    fn compile_compound_assignment(
+        &mut self,
+        state: &mut CompilerState,
+        target: &Expression,
+        op: TokenType,
+        value: &Expression,
+    ) -> CompileResult<()> {
+        match &target.value {
+            ExpressionKind::Identifier(id) => {
+                self.check_record_assignment(state, target, None)?;
+                let binding = state.resolve_binding(*id);
+
+                self.compile_identifier_expression(state, target)?;
+                self.compile_expression(state, value)?;
+                self.compile_binary_operator(op);
+
+                if let Some(binding) = binding {
+                    match binding.typ {
+                        BindingType::Argument => self.bytecode.store_argument(binding.index),
+                        BindingType::Local => self.bytecode.store_local(binding.index),
+                        BindingType::Upvalue => self.bytecode.store_upvalue(binding.index),
+                    };
+                } else {
+                    let global = self.arm_global(*id).unwrap_or(*id);
+                    self.bytecode.store_global(global);
+                }
+            }
+
+            ExpressionKind::Index(arr, index) => {
+                // evaluate the array and index once, keeping a copy of each
+                // around for the ArraySet at the end
+                self.compile_expression(state, arr)?;
+                self.compile_expression(state, index)?;
+                self.bytecode.dup2().array_get();
+                self.compile_expression(state, value)?;
+                self.compile_binary_operator(op);
+                self.bytecode.rot3().array_set();
+            }
+
+            ExpressionKind::BinaryOperation(object, TokenType::Dot, field)
+                if self.module_reference(state, object).is_none() =>
+            {
+                self.compile_record_field(state, object, field)?;
+                self.bytecode.dup2().array_get();
+                self.compile_expression(state, value)?;
+                self.compile_binary_operator(op);
+                self.bytecode.rot3().array_set();
+            }
+
+            _ => return error_at(target.position, "Invalid assignment target".to_string()),
+        }
+
+        Ok(())
+    }
+
+    /// Returns the module name if `expression` refers to an imported module
+    /// rather than a variable.
+    fn module_reference(
+        &self,
+        state: &mut CompilerState,
+        expression: &Expression,
+    ) -> Option<usize> {
+        if let ExpressionKind::Identifier(id) = expression.value {
+            if state.resolve_binding(id).is_none()
+                && self.arm_global(id).is_none()
+                && self.agent.modules.contains_key(&id)
+            {
+                return Some(id);
+            }
+        }
+        None
+    }
+
+    /// The record `expression` evaluates to, if that's known at compile time:
+    /// for a record literal, or a variable declared with one.
+    fn record_type(&self, state: &mut CompilerState, expression: &Expression) -> Option<usize> {
+        match &expression.value {
+            ExpressionKind::Record { name, .. } => self.records.get(name).copied(),
+            ExpressionKind::Identifier(id) => match state.resolve_binding(*id) {
+                Some(binding) => binding.record,
+                // a match arm's variable never has a known record
+                None if self.arm_global(*id).unwrap_or(*id) == *id => {
+                    self.global_records.get(id).copied()
+                }
+                None => None,
+            },
+            _ => None,
+        }
+    }
+
+    // a variable known to hold a record is only assigned that record, so
+    // that field accesses compiled for it stay right. `value` is None for a
+    // compound assignment, which never produces a record
+    fn check_record_assignment(
+        &self,
+        state: &mut CompilerState,
+        target: &Expression,
+        value: Option<&Expression>,
+    ) -> CompileResult<()> {
+        let record = match self.record_type(state, target) {
+            Some(record) => record,
+            None => return Ok(()),
+        };
+
+        if value.and_then(|value| self.record_type(state, value)) == Some(record) {
+            return Ok(());
+        }
+
+        let name = match target.value {
+            ExpressionKind::Identifier(name) => name,
+            _ => unreachable!(),
+        };
+        error_at(
+            target.position,
+            format!(
+                "{} holds a {} record, so it can only be assigned another one",
+                self.agent.string_table[name],
+                self.agent.string_table[self.agent.records[record].name]
+            ),
+        )
+    }
+
+    /// Pushes the record and the slot of `field` so that an ArrayGet or
+    /// ArraySet can follow. The slot is looked up in the record the object
+    /// is known to be at compile time. Failing that, it is looked up in the
+    /// records of this module with the field, which have to agree on it.
+    fn compile_record_field(
+        &mut self,
+        state: &mut CompilerState,
+        object: &Expression,
+        field: &Expression,
+    ) -> CompileResult<()> {
+        let name = if let ExpressionKind::Identifier(name) = field.value {
+            name
+        } else {
+            return error_at(
+                field.position,
+                "Expected field name to be an identifier".to_string(),
+            );
+        };
+
+        let slot = if let Some(spec_id) = self.record_type(state, object) {
+            let spec = &self.agent.records[spec_id];
+            match spec.field_index(name) {
+                Some(slot) => slot,
+                None => {
+                    return error_at(
+                        field.position,
+                        format!(
+                            "Record {} has no field {}",
+                            self.agent.string_table[spec.name], self.agent.string_table[name]
+                        ),
+                    )
+                }
+            }
+        } else {
+            let mut specs = self.records.values().copied().collect::<Vec<_>>();
+            specs.sort_unstable();
+
+            let mut slot = None;
+            for spec in specs {
+                let spec = &self.agent.records[spec];
+                match (slot, spec.field_index(name)) {
+                    (None, Some(index)) => slot = Some((index, spec.name)),
+                    (Some((other_index, other_name)), Some(index)) if other_index != index => {
+                        return error_at(
+                            field.position,
+                            format!(
+                                "Can't tell which record field {} belongs to, since it is in a different position in records {} and {}",
+                                self.agent.string_table[name],
+                                self.agent.string_table[other_name],
+                                self.agent.string_table[spec.name]
+                            ),
+                        );
+                    }
+                    _ => {}
+                }
+            }
+
+            match (slot, &object.value) {
+                (Some((slot, _)), _) => slot,
+                // `Foo.bar` where nothing is called Foo was most likely
+                // meant to read from a module
+                (None, ExpressionKind::Identifier(id))
+                    if state.resolve_binding(*id).is_none()
+                        && self.arm_global(*id).is_none()
+                        && !self.globals.contains(id) =>
+                {
+                    return error_at(
+                        object.position,
+                        format!("Unknown module {}", self.agent.string_table[*id]),
+                    )
+                }
+                (None, _) => {
+                    return error_at(
+                        field.position,
+                        format!("Unknown record field {}", self.agent.string_table[name]),
+                    )
+                }
+            }
+        };
+
+        self.compile_expression(state, object)?;
+        self.bytecode.const_int(slot as i64);
+        Ok(())
+    }
+
+    fn compile_record_expression(
+        &mut self,
+        state: &mut CompilerState,
+        expression: &Expression,
+    ) -> CompileResult<()> {
+        if let ExpressionKind::Record { name, fields } = &expression.value {
+            let spec_id = match self.records.get(name) {
+                Some(spec_id) => *spec_id,
+                None => {
+                    return error_at(
+                        expression.position,
+                        format!("Unknown record {}", self.agent.string_table[*name]),
+                    )
+                }
+            };
+            let spec = self.agent.records[spec_id].clone();
+
+            let mut values = vec![None; spec.fields.len()];
+            for (field, value) in fields {
+                let field_name = if let ExpressionKind::Identifier(field_name) = field.value {
+                    field_name
+                } else {
+                    unreachable!();
+                };
+
+                match spec.field_index(field_name) {
+                    Some(index) if values[index].is_none() => values[index] = Some(value),
+                    Some(_) => {
+                        return error_at(
+                            field.position,
+                            format!(
+                                "Field {} specified more than once",
+                                self.agent.string_table[field_name]
+                            ),
+                        )
+                    }
+                    None => {
+                        return error_at(
+                            field.position,
+                            format!(
+                                "Record {} has no field {}",
+                                self.agent.string_table[*name], self.agent.string_table[field_name]
+                            ),
+                        )
+                    }
+                }
+            }
+
+            // fields are evaluated in declaration order, and ones that were
+            // left out default to null
+            for value in values {
+                if let Some(value) = value {
+                    self.compile_expression(state, value)?;
+                } else {
+                    self.bytecode.const_null();
+                }
+            }
+
+            self.bytecode.new_record(spec_id);
+
+            Ok(())
+        } else {
+            unreachable!();
+        }
+    }
+
+    fn compile_match_expression(
+        &mut self,
+        state: &mut CompilerState,
+        expression: &Expression,
+    ) -> CompileResult<()> {
+        if let ExpressionKind::Match(value, arms) = &expression.value {
+            self.compile_match(state, value, arms, Self::compile_expression)
+        } else {
+            unreachable!();
+        }
+    }
+
+    /// The arms' patterns are compiled into a decision tree, so a test shared
+    /// by several arms runs once on the way to any of them. The matched value
+    /// and the parts of it tested so far are kept on the stack. An arm's
+    /// variables are stored in temporaries that only exist within the arm,
+    /// so a guard that fails leaves the variables outside the match alone.
+    fn compile_match<T>(
+        &mut self,
+        state: &mut CompilerState,
+        value: &Expression,
+        arms: &[MatchArm<T>],
+        compile_body: fn(&mut Self, &mut CompilerState, &T) -> CompileResult<()>,
+    ) -> CompileResult<()> {
+        let end_label = self.bytecode.new_label();
+
+        let mut match_state = MatchState {
+            arms: Vec::new(),
+            no_match_label: self.bytecode.new_label(),
+            subtrees: HashMap::new(),
+        };
+        let mut rows = Vec::new();
+        for (i, arm) in arms.iter().enumerate() {
+            let mut names = Vec::new();
+            self.check_pattern(&arm.pattern, &mut names)?;
+
+            let mut variables = Vec::new();
+            for name in names {
+                variables.push((name, self.arm_variable(state, name)?));
+            }
+
+            match_state.arms.push(MatchArmState {
+                guard: arm.guard.as_ref(),
+                variables,
+                body_label: self.bytecode.new_label(),
+            });
+            rows.push(MatchRow::new(i, &arm.pattern));
+        }
+
+        self.compile_expression(state, value)?;
+        self.compile_decision(state, &mut match_state, rows, 1)?;
+
+        for (arm, arm_state) in arms.iter().zip(&match_state.arms) {
+            self.bytecode.mark_label(arm_state.body_label);
+            let globals = self.enter_arm(state, &arm_state.variables);
+            compile_body(self, state, &arm.body)?;
+            self.leave_arm(state, &arm_state.variables, globals);
+            self.bytecode.op(OpCode::Jump).address_of_auto(end_label);
+        }
+
+        // the matched value is the only thing left on the stack here
+        self.bytecode.mark_label(match_state.no_match_label);
+        self.bytecode.no_match();
+        self.bytecode.mark_label(end_label);
+
+        Ok(())
+    }
+
+    /// Emits the tests that pick the first of `rows` to match, with `depth`
+    /// values on the stack for the match. Every path ends by popping them
+    /// and jumping to an arm's body, or to the NoMatch with just the matched
+    /// value left.
+    fn compile_decision<'p>(
+        &mut self,
+        state: &mut CompilerState,
+        match_state: &mut MatchState<'p>,
+        rows: Vec<MatchRow<'p>>,
+        depth: usize,
+    ) -> CompileResult<()> {
+        let key = (depth, rows.iter().map(MatchRow::key).collect());
+        if let Some(label) = match_state.subtrees.get(&key) {
+            self.bytecode.op(OpCode::Jump).address_of_auto(*label);
+            return Ok(());
+        }
+        let label = self.bytecode.new_label();
+        self.bytecode.mark_label(label);
+        match_state.subtrees.insert(key, label);
+
+        let first = match rows.first() {
+            Some(first) => first,
+            None => {
+                for _ in 1..depth {
+                    self.bytecode.pop();
+                }
+                self.bytecode
+                    .op(OpCode::Jump)
+                    .address_of_auto(match_state.no_match_label);
+                return Ok(());
+            }
+        };
+
+        if first.tests.is_empty() {
+            return self.compile_match_leaf(state, match_state, rows, depth);
+        }
+
+        let (slot, pattern) = first.tests[0];
+        let test = self.pattern_test(pattern)?;
+        let fail_label = self.bytecode.new_label();
+
+        self.bytecode.pick(depth - 1 - slot);
+        self.compile_pattern_test(state, &test)?;
+        self.bytecode
+            .op(OpCode::JumpIfFalse)
+            .address_of_auto(fail_label);
+        let parts = self.compile_pattern_parts(state, &test, depth - 1 - slot)?;
+
+        // rows testing the same thing go on with the tests of their parts
+        // if it passed, and are dropped if it didn't
+        let mut passed = Vec::new();
+        let mut failed = Vec::new();
+        for row in &rows {
+            let index = row
+                .tests
+                .iter()
+                .position(|(other_slot, _)| *other_slot == slot);
+            let other = match index {
+                Some(index) => Some(self.pattern_test(row.tests[index].1)?),
+                None => None,
+            };
+
+            match (index, other) {
+                (Some(index), Some(other)) if other.same_as(&test) => {
+                    let parts = self.pattern_parts(row.tests[index].1)?;
+                    passed.push(row.expand(index, depth, &parts));
+                }
+                (_, other) => {
+                    if !other.is_some_and(|other| test.excludes(&other)) {
+                        passed.push(row.clone());
+                    }
+                    failed.push(row.clone());
+                }
+            }
+        }
+
+        self.compile_decision(state, match_state, passed, depth + parts)?;
+        self.bytecode.mark_label(fail_label);
+        self.compile_decision(state, match_state, failed, depth)
+    }
+
+    // the first row has nothing left to test, so its arm is chosen once the
+    // guard passes
+    fn compile_match_leaf<'p>(
+        &mut self,
+        state: &mut CompilerState,
+        match_state: &mut MatchState<'p>,
+        rows: Vec<MatchRow<'p>>,
+        depth: usize,
+    ) -> CompileResult<()> {
+        let arm = &match_state.arms[rows[0].arm];
+        let guard = arm.guard;
+        let variables = arm.variables.clone();
+        let body_label = arm.body_label;
+
+        for (slot, pattern) in rows[0].bindings.clone() {
+            let variable = variables
+                .iter()
+                .find(|(name, _)| pattern.value == PatternKind::Binding(*name))
+                .map(|(_, variable)| *variable)
+                .ok_or_else(|| VmError::CompileError("Unknown match arm variable".into()))?;
+
+            self.bytecode.pick(depth - 1 - slot);
+            match variable {
+                ArmVariable::Local(index) => self.bytecode.store_local(index),
+                ArmVariable::Global(global) => {
+                    self.bytecode.declare_global(global).store_global(global)
+                }
+            };
+            self.bytecode.pop();
+        }
+
+        let fail_label = match guard {
+            Some(guard) => {
+                let fail_label = self.bytecode.new_label();
+                let globals = self.enter_arm(state, &variables);
+                self.compile_expression(state, guard)?;
+                self.leave_arm(state, &variables, globals);
+                self.bytecode
+                    .op(OpCode::JumpIfFalse)
+                    .address_of_auto(fail_label);
+                Some(fail_label)
+            }
+            None => None,
+        };
+
+        for _ in 0..depth {
+            self.bytecode.pop();
+        }
+        self.bytecode.op(OpCode::Jump).address_of_auto(body_label);
+
+        // the arms after this one are tried if the guard fails
+        if let Some(fail_label) = fail_label {
+            self.bytecode.mark_label(fail_label);
+            self.compile_decision(state, match_state, rows[1..].to_vec(), depth)?;
+        }
+
+        Ok(())
+    }
+
+    // reserves where an arm's variable is kept, out of sight of the code
+    // outside the arm
+    fn arm_variable(
+        &mut self,
+        state: &mut CompilerState,
+        name: usize,
+    ) -> CompileResult<ArmVariable> {
+        if state.is_global {
+            // no identifier contains a '#', so nothing else can name it
+            let global = format!(
+                "{}#{}",
+                self.agent.string_table[name], self.arm_global_count
+            );
+            self.arm_global_count += 1;
+            Ok(ArmVariable::Global(self.agent.intern_string(&global)))
+        } else if let Some(scope) = &mut state.scope {
+            let index = scope.push_binding(BindingType::Local, name);
+            scope.bindings.pop();
+            Ok(ArmVariable::Local(index))
+        } else {
+            error("Binding variable outside global scope with no scope")
+        }
+    }
+
+    // makes an arm's variables visible by their names, returning what to
+    // pass to leave_arm
+    fn enter_arm(
+        &mut self,
+        state: &mut CompilerState,
+        variables: &[(usize, ArmVariable)],
+    ) -> usize {
+        let globals = self.arm_globals.len();
+        for (name, variable) in variables {
+            match variable {
+                ArmVariable::Local(index) => {
+                    if let Some(scope) = &mut state.scope {
+                        scope.bindings.push(Binding {
+                            typ: BindingType::Local,
+                            name: *name,
+                            index: *index,
+                            record: None,
+                        });
+                    }
+                }
+                ArmVariable::Global(global) => self.arm_globals.push((*name, *global)),
+            }
+        }
+        globals
+    }
+
+    fn leave_arm(
+        &mut self,
+        state: &mut CompilerState,
+        variables: &[(usize, ArmVariable)],
+        globals: usize,
+    ) {
+        for (name, variable) in variables {
+            if let (ArmVariable::Local(index), Some(scope)) = (variable, &mut state.scope) {
+                // variables declared in the arm's body come after these and
+                // stay, as they would in any other block
+                if let Some(position) = scope.bindings.iter().rposition(|binding| {
+                    binding.name == *name
+                        && matches!(binding.typ, BindingType::Local)
+                        && binding.index == *index
+                }) {
+                    scope.bindings.remove(position);
+                }
+            }
+        }
+        self.arm_globals.truncate(globals);
+    }
+
+    // reports unknown records and fields in `pattern`, and collects the
+    // names it binds
+    fn check_pattern(&self, pattern: &Pattern, names: &mut Vec<usize>) -> CompileResult<()> {
+        match &pattern.value {
+            PatternKind::Binding(name) => {
+                if names.contains(name) {
+                    return error_at(
+                        pattern.position,
+                        format!(
+                            "Variable {} is bound more than once in the pattern",
+                            self.agent.string_table[*name]
+                        ),
+                    );
+                }
+                names.push(*name);
+            }
+            PatternKind::Wildcard | PatternKind::Value(_) | PatternKind::Range { .. } => {}
+            _ => {
+                self.pattern_test(pattern)?;
+                for (_, part) in self.pattern_parts(pattern)? {
+                    self.check_pattern(part, names)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn pattern_test<'p>(&self, pattern: &'p Pattern) -> CompileResult<PatternTest<'p>> {
+        Ok(match &pattern.value {
+            PatternKind::Value(value) => PatternTest::Value(value),
+            PatternKind::Range {
+                start,
+                end,
+                inclusive,
+            } => PatternTest::Range(start, end, *inclusive),
+            PatternKind::Array(elements, rest) => PatternTest::Array {
+                len: elements.len(),
+                rest: rest.is_some(),
+            },
+            PatternKind::Map(entries) => PatternTest::Map(entries),
+            PatternKind::Record { name, .. } => {
+                PatternTest::Record(self.record_spec(pattern.position, *name)?)
+            }
+            PatternKind::Wildcard | PatternKind::Binding(_) => unreachable!(),
+        })
+    }
+
+    // the patterns for the parts of a value `pattern` matches, by where they
+    // are among the values compile_pattern_parts pushes
+    fn pattern_parts<'p>(&self, pattern: &'p Pattern) -> CompileResult<Vec<(usize, &'p Pattern)>> {
+        match &pattern.value {
+            PatternKind::Array(elements, rest) => {
+                let mut parts = elements.iter().enumerate().collect::<Vec<_>>();
+                if let Some(rest) = rest {
+                    parts.push((elements.len(), rest));
+                }
+                Ok(parts)
+            }
+            PatternKind::Map(entries) => {
+                Ok(entries.iter().map(|(_, value)| value).enumerate().collect())
+            }
+            PatternKind::Record { name, fields } => {
+                let spec_id = self.record_spec(pattern.position, *name)?;
+                let mut parts = Vec::new();
+                for (field, value) in fields {
+                    let field_name = if let ExpressionKind::Identifier(field_name) = field.value {
+                        field_name
+                    } else {
+                        unreachable!();
+                    };
+
+                    match self.agent.records[spec_id].field_index(field_name) {
+                        Some(index) => parts.push((index, value)),
+                        None => {
+                            return error_at(
+                                field.position,
+                                format!(
+                                    "Record {} has no field {}",
+                                    self.agent.string_table[*name],
+                                    self.agent.string_table[field_name]
+                                ),
+                            )
+                        }
+                    }
+                }
+                Ok(parts)
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn record_spec(&self, position: Position, name: usize) -> CompileResult<usize> {
+        match self.records.get(&name) {
+            Some(spec_id) => Ok(*spec_id),
+            None => error_at(
+                position,
+                format!("Unknown record {}", self.agent.string_table[name]),
+            ),
+        }
+    }
+
+    // replaces the value on top of the stack with whether it passes `test`
+    fn compile_pattern_test(
+        &mut self,
+        state: &mut CompilerState,
+        test: &PatternTest,
+    ) -> CompileResult<()> {
+        match test {
+            PatternTest::Value(value) => {
+                self.compile_expression(state, value)?;
+                self.bytecode.equal();
+            }
+            PatternTest::Range(start, end, inclusive) => {
+                self.compile_expression(state, start)?;
+                self.compile_expression(state, end)?;
+                if *inclusive {
+                    self.bytecode.new_inclusive_range();
+                } else {
+                    self.bytecode.new_range();
+                }
+                self.bytecode.contains();
+            }
+            PatternTest::Array { len, rest } => {
+                self.bytecode.match_array(*len, *rest);
+            }
+            PatternTest::Map(entries) => {
+                for (key, _) in entries.iter() {
+                    self.compile_expression(state, key)?;
+                }
+                self.bytecode.match_map(entries.len());
+            }
+            PatternTest::Record(spec_id) => {
+                self.bytecode.match_record(*spec_id);
+            }
+        }
+        Ok(())
+    }
+
+    // pushes the parts of the value `distance` below the top, which passed
+    // `test`, and returns how many there are. A record's fields are all
+    // pushed, so that every pattern for it finds them in the same place
+    fn compile_pattern_parts(
+        &mut self,
+        state: &mut CompilerState,
+        test: &PatternTest,
+        distance: usize,
+    ) -> CompileResult<usize> {
+        match test {
+            PatternTest::Value(_) | PatternTest::Range(..) => Ok(0),
+            PatternTest::Array { len, rest } => {
+                for i in 0..*len {
+                    self.bytecode
+                        .pick(distance + i)
+                        .const_int(i as i64)
+                        .array_get();
+                }
+                if *rest {
+                    self.bytecode.pick(distance + len).array_rest(*len);
+                }
+                Ok(len + *rest as usize)
+            }
+            PatternTest::Map(entries) => {
+                for (i, (key, _)) in entries.iter().enumerate() {
+                    self.bytecode.pick(distance + i);
+                    self.compile_expression(state, key)?;
+                    self.bytecode.array_get();
+                }
+                Ok(entries.len())
+            }
+            PatternTest::Record(spec_id) => {
+                let count = self.agent.records[*spec_id].fields.len();
+                for i in 0..count {
+                    self.bytecode
+                        .pick(distance + i)
+                        .const_int(i as i64)
+                        .array_get();
+                }
+                Ok(count)
+            }
+        }
+    }
+
     fn compile_unary_operation_expression(
         &mut self,
         state: &mut CompilerState,
@@ -990,7 +2479,7 @@ mod tests {
                 let input = $input;
                 let lexer = Lexer::new("test", input);
                 let parser = Parser::new("test", &mut agent, lexer);
-                parser.collect::<Result<Vec<Statement>, String>>()?
+                parser.collect::<Result<Vec<Statement>, VmError>>()?
             };
             let name = agent.intern_string("test");
 
@@ -1012,7 +2501,7 @@ mod tests {
     }
 
     #[test]
-    fn test_let_declaration_without_value() -> Result<(), String> {
+    fn test_let_declaration_without_value() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1027,7 +2516,7 @@ mod tests {
     }
 
     #[test]
-    fn test_let_declaration_with_value() -> Result<(), String> {
+    fn test_let_declaration_with_value() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1042,13 +2531,14 @@ mod tests {
     }
 
     #[test]
-    fn test_function_declaration() -> Result<(), String> {
+    fn test_function_declaration() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
         let mut bc = Bytecode::new();
         bc.init_module(ident_test)
             .op(OpCode::NewFunction)
+            .usize(ident_test)
             .usize(0)
             .address_of("start")
             .declare_global(ident_test)
@@ -1066,7 +2556,34 @@ mod tests {
     }
 
     #[test]
-    fn test_if_statement_no_else() -> Result<(), String> {
+    fn test_variadic_function_declaration() -> Result<(), VmError> {
+        let mut agent = Agent::new();
+        let ident_test = agent.intern_string("test");
+
+        let mut bc = Bytecode::new();
+        bc.init_module(ident_test)
+            .op(OpCode::NewVariadicFunction)
+            .usize(ident_test)
+            .usize(1)
+            .address_of("start")
+            .declare_global(ident_test)
+            .store_global(ident_test)
+            .pop()
+            .op(OpCode::Jump)
+            .address_of("end")
+            .label("start")
+            .allocate_locals(0)
+            .load_argument(1)
+            .ret()
+            .const_null()
+            .ret()
+            .label("end")
+            .end_module();
+        test_statement!("function test(a, ...rest) { return rest; }", bc, agent)
+    }
+
+    #[test]
+    fn test_if_statement_no_else() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1081,7 +2598,7 @@ mod tests {
     }
 
     #[test]
-    fn test_if_statement() -> Result<(), String> {
+    fn test_if_statement() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1099,7 +2616,7 @@ mod tests {
     }
 
     #[test]
-    fn test_if_statement_else_if() -> Result<(), String> {
+    fn test_if_statement_else_if() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1120,7 +2637,7 @@ mod tests {
     }
 
     #[test]
-    fn test_if_statement_else_if_else() -> Result<(), String> {
+    fn test_if_statement_else_if_else() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1137,56 +2654,100 @@ mod tests {
             .op(OpCode::JumpIfFalse)
             .address_of("else_body2")
             .op(OpCode::Jump)
-            .address_of("end")
-            .label("else_body2")
+            .address_of("end")
+            .label("else_body2")
+            .label("end")
+            .end_module();
+        test_statement!("if null {} else if null {} else {}", bc, agent)
+    }
+
+    #[test]
+    fn test_for_statement_no_stuff() -> Result<(), VmError> {
+        let mut agent = Agent::new();
+        let ident_test = agent.intern_string("test");
+
+        let mut bc = Bytecode::new();
+
+        bc.init_module(ident_test)
+            .label("start")
+            .op(OpCode::Jump)
+            .address_of("start")
+            .end_module();
+        test_statement!("for ;; {}", bc, agent)
+    }
+
+    #[test]
+    fn test_for_statement() -> Result<(), VmError> {
+        let mut agent = Agent::new();
+        let ident_a = agent.intern_string("a");
+        let ident_test = agent.intern_string("test");
+
+        let mut bc = Bytecode::new();
+        bc.init_module(ident_test)
+            .const_null()
+            .declare_global(ident_a)
+            .store_global(ident_a)
+            .pop()
+            .label("start")
+            .const_null()
+            .op(OpCode::JumpIfFalse)
+            .address_of("end")
+            .label("inc")
+            .const_null()
+            .pop()
+            .op(OpCode::Jump)
+            .address_of("start")
             .label("end")
             .end_module();
-        test_statement!("if null {} else if null {} else {}", bc, agent)
+        test_statement!("for let a; null; null {}", bc, agent)
     }
 
     #[test]
-    fn test_for_statement_no_stuff() -> Result<(), String> {
+    fn test_try_statement() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
+        let ident_e = agent.intern_string("e");
 
         let mut bc = Bytecode::new();
-
         bc.init_module(ident_test)
-            .label("start")
+            .op(OpCode::PushHandler)
+            .address_of("catch")
+            .const_int(1)
+            .throw()
+            .pop_handler()
             .op(OpCode::Jump)
-            .address_of("start")
+            .address_of("end")
+            .label("catch")
+            .declare_global(ident_e)
+            .store_global(ident_e)
+            .pop()
+            .load_global(ident_e)
+            .pop()
+            .label("end")
             .end_module();
-        test_statement!("for ;; {}", bc, agent)
+        test_statement!("try { throw 1; } catch (e) { e; }", bc, agent)
     }
 
     #[test]
-    fn test_for_statement() -> Result<(), String> {
+    fn test_while_statement() -> Result<(), VmError> {
         let mut agent = Agent::new();
-        let ident_a = agent.intern_string("a");
         let ident_test = agent.intern_string("test");
 
         let mut bc = Bytecode::new();
         bc.init_module(ident_test)
-            .const_null()
-            .declare_global(ident_a)
-            .store_global(ident_a)
-            .pop()
             .label("start")
             .const_null()
             .op(OpCode::JumpIfFalse)
             .address_of("end")
-            .label("inc")
-            .const_null()
-            .pop()
             .op(OpCode::Jump)
             .address_of("start")
             .label("end")
             .end_module();
-        test_statement!("for let a; null; null {}", bc, agent)
+        test_statement!("while null {}", bc, agent)
     }
 
     #[test]
-    fn test_while_statement() -> Result<(), String> {
+    fn test_break_statement_valid() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1197,16 +2758,19 @@ mod tests {
             .op(OpCode::JumpIfFalse)
             .address_of("end")
             .op(OpCode::Jump)
+            .address_of("end")
+            .op(OpCode::Jump)
             .address_of("start")
             .label("end")
             .end_module();
-        test_statement!("while null {}", bc, agent)
+        test_statement!("while null { break; }", bc, agent)
     }
 
     #[test]
-    fn test_break_statement_valid() -> Result<(), String> {
+    fn test_break_statement_in_try() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
+        let ident_e = agent.intern_string("e");
 
         let mut bc = Bytecode::new();
         bc.init_module(ident_test)
@@ -1214,16 +2778,27 @@ mod tests {
             .const_null()
             .op(OpCode::JumpIfFalse)
             .address_of("end")
+            .op(OpCode::PushHandler)
+            .address_of("catch")
+            .pop_handler()
             .op(OpCode::Jump)
             .address_of("end")
+            .pop_handler()
+            .op(OpCode::Jump)
+            .address_of("end_try")
+            .label("catch")
+            .declare_global(ident_e)
+            .store_global(ident_e)
+            .pop()
+            .label("end_try")
             .op(OpCode::Jump)
             .address_of("start")
             .label("end")
             .end_module();
-        test_statement!("while null { break; }", bc, agent)
+        test_statement!("while null { try { break; } catch (e) {} }", bc, agent)
     }
 
-    fn break_statement_invalid() -> Result<(), String> {
+    fn break_statement_invalid() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1238,7 +2813,7 @@ mod tests {
     }
 
     #[test]
-    fn test_continue_statement_while() -> Result<(), String> {
+    fn test_continue_statement_while() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1258,7 +2833,7 @@ mod tests {
     }
 
     #[test]
-    fn test_continue_statement_for() -> Result<(), String> {
+    fn test_continue_statement_for() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1278,7 +2853,7 @@ mod tests {
     }
 
     #[test]
-    fn test_expression_statement() -> Result<(), String> {
+    fn test_expression_statement() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1288,13 +2863,14 @@ mod tests {
     }
 
     #[test]
-    fn test_identifier_expression_local() -> Result<(), String> {
+    fn test_identifier_expression_local() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
         let mut bc = Bytecode::new();
         bc.init_module(ident_test)
             .op(OpCode::NewFunction)
+            .usize(ident_test)
             .usize(0)
             .address_of("test")
             .declare_global(ident_test)
@@ -1306,6 +2882,7 @@ mod tests {
             .allocate_locals(1)
             .const_null()
             .store_local(0)
+            .pop()
             .load_local(0)
             .ret()
             .const_null()
@@ -1316,7 +2893,7 @@ mod tests {
     }
 
     #[test]
-    fn test_identifier_expression_global() -> Result<(), String> {
+    fn test_identifier_expression_global() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1333,13 +2910,14 @@ mod tests {
     }
 
     #[test]
-    fn test_identifier_expression_argument() -> Result<(), String> {
+    fn test_identifier_expression_argument() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
         let mut bc = Bytecode::new();
         bc.init_module(ident_test)
             .op(OpCode::NewFunction)
+            .usize(ident_test)
             .usize(1)
             .address_of("test")
             .declare_global(ident_test)
@@ -1359,13 +2937,14 @@ mod tests {
     }
 
     #[test]
-    fn test_identifier_expression_upvalue() -> Result<(), String> {
+    fn test_identifier_expression_upvalue() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
         let mut bc = Bytecode::new();
         bc.init_module(ident_test)
             .op(OpCode::NewFunction)
+            .usize(ident_test)
             .usize(0)
             .address_of("test")
             .declare_global(ident_test)
@@ -1377,7 +2956,9 @@ mod tests {
             .allocate_locals(1)
             .const_null()
             .store_local(0)
+            .pop()
             .op(OpCode::NewFunction)
+            .usize(usize::MAX)
             .usize(0)
             .address_of("inner")
             .op(OpCode::Jump)
@@ -1408,7 +2989,7 @@ mod tests {
     }
 
     #[test]
-    fn test_integer_expression() -> Result<(), String> {
+    fn test_integer_expression() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1418,7 +2999,7 @@ mod tests {
     }
 
     #[test]
-    fn test_double_expression() -> Result<(), String> {
+    fn test_double_expression() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1431,7 +3012,7 @@ mod tests {
     }
 
     #[test]
-    fn test_string_expression() -> Result<(), String> {
+    fn test_string_expression() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1445,7 +3026,18 @@ mod tests {
     }
 
     #[test]
-    fn test_null_expression() -> Result<(), String> {
+    fn test_atom_expression() -> Result<(), VmError> {
+        let mut agent = Agent::new();
+        let ident_test = agent.intern_string("test");
+
+        let a = agent.intern_string("hello");
+        let mut bc = Bytecode::new();
+        bc.init_module(ident_test).const_atom(a).pop().end_module();
+        test_statement!(":hello;", bc, agent)
+    }
+
+    #[test]
+    fn test_null_expression() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1455,7 +3047,7 @@ mod tests {
     }
 
     #[test]
-    fn test_boolean_expression() -> Result<(), String> {
+    fn test_boolean_expression() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1470,7 +3062,7 @@ mod tests {
     }
 
     #[test]
-    fn test_array_expression() -> Result<(), String> {
+    fn test_array_expression() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1486,13 +3078,14 @@ mod tests {
     }
 
     #[test]
-    fn test_function_expression() -> Result<(), String> {
+    fn test_function_expression() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
         let mut bc = Bytecode::new();
         bc.init_module(ident_test)
             .op(OpCode::NewFunction)
+            .usize(usize::MAX)
             .usize(0)
             .address_of("func")
             .op(OpCode::Jump)
@@ -1508,7 +3101,7 @@ mod tests {
     }
 
     #[test]
-    fn test_binary_operation_expression() -> Result<(), String> {
+    fn test_binary_operation_expression() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1525,7 +3118,7 @@ mod tests {
     }
 
     #[test]
-    fn test_assignment_expression() -> Result<(), String> {
+    fn test_assignment_expression() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let a = agent.intern_string("a");
         let ident_test = agent.intern_string("test");
@@ -1543,7 +3136,299 @@ mod tests {
     }
 
     #[test]
-    fn test_call_expression() -> Result<(), String> {
+    fn test_compound_assignment_global() -> Result<(), VmError> {
+        let mut agent = Agent::new();
+        let a = agent.intern_string("a");
+        let ident_test = agent.intern_string("test");
+        let mut bc = Bytecode::new();
+        bc.init_module(ident_test)
+            .const_int(1)
+            .declare_global(a)
+            .store_global(a)
+            .pop()
+            .load_global(a)
+            .const_int(2)
+            .shift_left()
+            .store_global(a)
+            .pop()
+            .end_module();
+        test_statement!("let a = 1; a <<= 2;", bc, agent)
+    }
+
+    #[test]
+    fn test_compound_assignment_argument() -> Result<(), VmError> {
+        let mut agent = Agent::new();
+        let ident_test = agent.intern_string("test");
+
+        let mut bc = Bytecode::new();
+        bc.init_module(ident_test)
+            .op(OpCode::NewFunction)
+            .usize(ident_test)
+            .usize(2)
+            .address_of("test")
+            .declare_global(ident_test)
+            .store_global(ident_test)
+            .pop()
+            .op(OpCode::Jump)
+            .address_of("end")
+            .label("test")
+            .allocate_locals(0)
+            .load_argument(1)
+            .const_int(3)
+            .mul()
+            .store_argument(1)
+            .pop()
+            .const_null()
+            .ret()
+            .label("end")
+            .end_module();
+        test_statement!("function test(a, b) { b *= 3; }", bc, agent)
+    }
+
+    #[test]
+    fn test_compound_assignment_index() -> Result<(), VmError> {
+        let mut agent = Agent::new();
+        let a = agent.intern_string("a");
+        let ident_test = agent.intern_string("test");
+        let mut bc = Bytecode::new();
+        bc.init_module(ident_test)
+            .const_null()
+            .declare_global(a)
+            .store_global(a)
+            .pop()
+            .load_global(a)
+            .const_int(0)
+            .dup2()
+            .array_get()
+            .const_int(1)
+            .add()
+            .rot3()
+            .array_set()
+            .pop()
+            .end_module();
+        test_statement!("let a; a[0] += 1;", bc, agent)
+    }
+
+    fn compound_assignment_invalid_target() -> Result<(), VmError> {
+        let mut agent = Agent::new();
+        let ident_test = agent.intern_string("test");
+
+        let mut bc = Bytecode::new();
+        bc.init_module(ident_test).end_module();
+        test_statement!("1 += 1;", bc, agent)
+    }
+
+    #[test]
+    fn test_compound_assignment_invalid_target() {
+        assert!(compound_assignment_invalid_target().is_err());
+    }
+
+    #[test]
+    fn test_record_expression() -> Result<(), VmError> {
+        let mut agent = Agent::new();
+        let ident_test = agent.intern_string("test");
+
+        let mut bc = Bytecode::new();
+        bc.init_module(ident_test)
+            .const_null()
+            .const_int(1)
+            .new_record(0)
+            .pop()
+            .end_module();
+        test_statement!("record P { x, y } P { y = 1 };", bc, agent)
+    }
+
+    fn record_expression_unknown_field() -> Result<(), VmError> {
+        let mut agent = Agent::new();
+        let ident_test = agent.intern_string("test");
+
+        let mut bc = Bytecode::new();
+        bc.init_module(ident_test).end_module();
+        test_statement!("record P { x } P { y = 1 };", bc, agent)
+    }
+
+    #[test]
+    fn test_record_expression_unknown_field() {
+        assert!(record_expression_unknown_field().is_err());
+    }
+
+    #[test]
+    fn test_record_field_access() -> Result<(), VmError> {
+        let mut agent = Agent::new();
+        let p = agent.intern_string("p");
+        let ident_test = agent.intern_string("test");
+
+        let mut bc = Bytecode::new();
+        bc.init_module(ident_test)
+            .const_null()
+            .declare_global(p)
+            .store_global(p)
+            .pop()
+            .load_global(p)
+            .const_int(1)
+            .array_get()
+            .pop()
+            .end_module();
+        test_statement!("let p; p.y; record P { x, y }", bc, agent)
+    }
+
+    #[test]
+    fn test_record_field_assignment() -> Result<(), VmError> {
+        let mut agent = Agent::new();
+        let p = agent.intern_string("p");
+        let ident_test = agent.intern_string("test");
+
+        let mut bc = Bytecode::new();
+        bc.init_module(ident_test)
+            .const_null()
+            .declare_global(p)
+            .store_global(p)
+            .pop()
+            .const_int(2)
+            .load_global(p)
+            .const_int(1)
+            .array_set()
+            .pop()
+            .load_global(p)
+            .const_int(1)
+            .dup2()
+            .array_get()
+            .const_int(3)
+            .add()
+            .rot3()
+            .array_set()
+            .pop()
+            .end_module();
+        test_statement!("record P { x, y } let p; p.y = 2; p.y += 3;", bc, agent)
+    }
+
+    fn record_field_unknown() -> Result<(), VmError> {
+        let mut agent = Agent::new();
+        let ident_test = agent.intern_string("test");
+
+        let mut bc = Bytecode::new();
+        bc.init_module(ident_test).end_module();
+        test_statement!("record P { x } let p; p.y;", bc, agent)
+    }
+
+    #[test]
+    fn test_record_field_unknown() {
+        assert!(record_field_unknown().is_err());
+    }
+
+    fn record_field_ambiguous() -> Result<(), VmError> {
+        let mut agent = Agent::new();
+        let ident_test = agent.intern_string("test");
+
+        let mut bc = Bytecode::new();
+        bc.init_module(ident_test).end_module();
+        test_statement!("record P { x, y } record Q { y } let p; p.y;", bc, agent)
+    }
+
+    #[test]
+    fn test_record_field_ambiguous() {
+        assert!(record_field_ambiguous().is_err());
+    }
+
+    #[test]
+    fn test_record_field_in_different_slots() -> Result<(), VmError> {
+        let mut agent = Agent::new();
+        let p = agent.intern_string("p");
+        let ident_test = agent.intern_string("test");
+
+        let mut bc = Bytecode::new();
+        bc.init_module(ident_test)
+            .const_int(1)
+            .new_record(1)
+            .declare_global(p)
+            .store_global(p)
+            .pop()
+            .load_global(p)
+            .const_int(0)
+            .array_get()
+            .pop()
+            .end_module();
+        test_statement!(
+            "record P { x, y } record Q { y } let p = Q { y = 1 }; p.y;",
+            bc,
+            agent
+        )
+    }
+
+    #[test]
+    fn test_record_field_unknown_for_record() {
+        let mut agent = Agent::new();
+        let mut compiler = crate::compiler::Compiler::new(&mut agent);
+        let error = compiler
+            .compile(
+                ".",
+                "test".to_string(),
+                "record P { x } record Q { z } let p = P {}; p.z;",
+            )
+            .unwrap_err();
+        assert_eq!(error.message(), "Record P has no field z");
+    }
+
+    #[test]
+    fn test_record_variable_assignment() {
+        let mut agent = Agent::new();
+        let mut compiler = crate::compiler::Compiler::new(&mut agent);
+        let error = compiler
+            .compile(
+                ".",
+                "test".to_string(),
+                "record P { x } record Q { x } function f() { let p = P {}; p = Q {}; }",
+            )
+            .unwrap_err();
+        assert_eq!(
+            error.message(),
+            "p holds a P record, so it can only be assigned another one"
+        );
+    }
+
+    #[test]
+    fn test_unknown_module() {
+        let mut agent = Agent::new();
+        let mut compiler = crate::compiler::Compiler::new(&mut agent);
+        let error = compiler
+            .compile(".", "test".to_string(), "Foo.bar;")
+            .unwrap_err();
+        assert_eq!(error.message(), "Unknown module Foo");
+    }
+
+    #[test]
+    fn test_match_duplicate_binding() {
+        let mut agent = Agent::new();
+        let mut compiler = crate::compiler::Compiler::new(&mut agent);
+        let error = compiler
+            .compile(
+                ".",
+                "test".to_string(),
+                "match [1, 2] { [a, a] => a, _ => 0 };",
+            )
+            .unwrap_err();
+        assert_eq!(
+            error.message(),
+            "Variable a is bound more than once in the pattern"
+        );
+    }
+
+    fn record_declaration_not_top_level() -> Result<(), VmError> {
+        let mut agent = Agent::new();
+        let ident_test = agent.intern_string("test");
+
+        let mut bc = Bytecode::new();
+        bc.init_module(ident_test).end_module();
+        test_statement!("function f() { record P { x } }", bc, agent)
+    }
+
+    #[test]
+    fn test_record_declaration_not_top_level() {
+        assert!(record_declaration_not_top_level().is_err());
+    }
+
+    #[test]
+    fn test_call_expression() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1558,7 +3443,44 @@ mod tests {
     }
 
     #[test]
-    fn test_index_expression() -> Result<(), String> {
+    fn test_spread_call_expression() -> Result<(), VmError> {
+        let mut agent = Agent::new();
+        let ident_test = agent.intern_string("test");
+
+        let mut bc = Bytecode::new();
+        bc.init_module(ident_test)
+            .const_int(1)
+            .new_array_with_values(1)
+            .new_array_with_values(0)
+            .array_concat()
+            .const_int(2)
+            .const_int(3)
+            .new_array_with_values(2)
+            .array_concat()
+            .const_null()
+            .call_spread()
+            .pop()
+            .end_module();
+        test_statement!("null(1, ...[], 2, 3);", bc, agent)
+    }
+
+    #[test]
+    fn test_spread_call_expression_single() -> Result<(), VmError> {
+        let mut agent = Agent::new();
+        let ident_test = agent.intern_string("test");
+
+        let mut bc = Bytecode::new();
+        bc.init_module(ident_test)
+            .new_array_with_values(0)
+            .const_null()
+            .call_spread()
+            .pop()
+            .end_module();
+        test_statement!("null(...[]);", bc, agent)
+    }
+
+    #[test]
+    fn test_index_expression() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1573,7 +3495,7 @@ mod tests {
     }
 
     #[test]
-    fn test_unary_operation_expression() -> Result<(), String> {
+    fn test_unary_operation_expression() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1592,7 +3514,7 @@ mod tests {
     }
 
     #[test]
-    fn test_andand() -> Result<(), String> {
+    fn test_andand() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1612,7 +3534,7 @@ mod tests {
     }
 
     #[test]
-    fn test_pipepipe() -> Result<(), String> {
+    fn test_pipepipe() -> Result<(), VmError> {
         let mut agent = Agent::new();
         let ident_test = agent.intern_string("test");
 
@@ -1,7 +1,9 @@
 use crate::agent::Agent;
+use crate::error::VmError;
 use crate::opcode::OpCode;
+use std::convert::TryFrom;
 
-pub fn disassemble(agent: &Agent, code: &[u8]) -> Result<(), String> {
+pub fn disassemble(agent: &Agent, code: &[u8]) -> Result<(), VmError> {
     let mut ip = 0;
 
     macro_rules! next {
@@ -17,7 +19,8 @@ pub fn disassemble(agent: &Agent, code: &[u8]) -> Result<(), String> {
             let mut arr = [0u8; $count];
 
             for i in 0..$count {
-                let result = next!().ok_or_else(|| "Unexpected end of bytecode".to_string());
+                let result = next!()
+                    .ok_or_else(|| VmError::RuntimeError("Unexpected end of bytecode".into()));
                 arr[i] = result?;
             }
 
@@ -27,7 +30,8 @@ pub fn disassemble(agent: &Agent, code: &[u8]) -> Result<(), String> {
 
     while let Some(instruction) = next!() {
         print!("{}: ", ip - 1);
-        let instruction = OpCode::from(instruction);
+        let instruction = OpCode::try_from(instruction)
+            .map_err(|byte| VmError::RuntimeError(format!("Invalid opcode {}", byte).into()))?;
         match instruction {
             OpCode::ConstInt => {
                 println!("{:?}({:?})", instruction, i64::from_le_bytes(next!(usize)));
@@ -56,7 +60,14 @@ pub fn disassemble(agent: &Agent, code: &[u8]) -> Result<(), String> {
             | OpCode::StoreArgument
             | OpCode::NewArray
             | OpCode::NewArrayWithValues
-            | OpCode::AllocateLocals => {
+            | OpCode::NewMap
+            | OpCode::AllocateLocals
+            | OpCode::PushHandler
+            | OpCode::IterNext
+            | OpCode::Interpolate
+            | OpCode::MatchMap
+            | OpCode::ArrayRest
+            | OpCode::Pick => {
                 println!(
                     "{:?}({:?})",
                     instruction,
@@ -68,6 +79,7 @@ pub fn disassemble(agent: &Agent, code: &[u8]) -> Result<(), String> {
             | OpCode::DeclareGlobal
             | OpCode::StoreGlobal
             | OpCode::ConstString
+            | OpCode::ConstAtom
             | OpCode::InitModule => {
                 let idx = usize::from_le_bytes(next!(usize));
                 println!("{:?}({} ({}))", instruction, agent.string_table[idx], idx,);
@@ -82,7 +94,24 @@ pub fn disassemble(agent: &Agent, code: &[u8]) -> Result<(), String> {
                 );
             }
 
-            OpCode::NewFunction => {
+            OpCode::NewRecord | OpCode::MatchRecord => {
+                let idx = usize::from_le_bytes(next!(usize));
+                println!(
+                    "{:?}({} ({}))",
+                    instruction, agent.string_table[agent.records[idx].name], idx,
+                );
+            }
+
+            OpCode::MatchArray => {
+                println!(
+                    "{:?}({:?}, {:?})",
+                    instruction,
+                    usize::from_le_bytes(next!(usize)),
+                    usize::from_le_bytes(next!(usize)),
+                );
+            }
+
+            OpCode::NewFunction | OpCode::NewVariadicFunction => {
                 println!(
                     "{:?}({:?}, {:?}, {:?})",
                     instruction,
@@ -121,7 +150,18 @@ pub fn disassemble(agent: &Agent, code: &[u8]) -> Result<(), String> {
             | OpCode::RightShift
             | OpCode::Neg
             | OpCode::EndModule
-            | OpCode::Dup => println!("{:?}", instruction),
+            | OpCode::Dup
+            | OpCode::Dup2
+            | OpCode::Rot3
+            | OpCode::ArrayConcat
+            | OpCode::CallSpread
+            | OpCode::PopHandler
+            | OpCode::Throw
+            | OpCode::GetIter
+            | OpCode::NewRange
+            | OpCode::NewInclusiveRange
+            | OpCode::Contains
+            | OpCode::NoMatch => println!("{:?}", instruction),
         }
     }
 
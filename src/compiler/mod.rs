@@ -12,16 +12,54 @@ use std::path::Path;
 
 use crate::agent::Agent;
 use crate::debuginfo::DebugInfo;
+use crate::error::VmError;
+use crate::module::ModuleSpec;
+use crate::permissions::Permissions;
 
 use bytecode::Bytecode;
 
-type Result = std::result::Result<(), Box<dyn std::error::Error>>;
+type Result = std::result::Result<(), VmError>;
+
+// not a valid identifier, so it can't clash with a real module
+const ENTRY_MODULE_NAME: &str = "<main>";
 
 pub(crate) struct Compiler<'a> {
     agent: &'a mut Agent,
     bytecode: Option<Bytecode>,
     compiled_modules: HashSet<String>,
     pub(crate) debuginfo: DebugInfo,
+    // keep the value of a trailing expression statement in the entry file
+    interactive: bool,
+    // what imported files may be read, if the program isn't trusted
+    permissions: Option<Permissions>,
+}
+
+/// A program that more code can be compiled into. Code is only ever added to
+/// the end, so the functions that were already compiled keep their addresses.
+#[derive(Clone)]
+pub(crate) struct Program {
+    pub(crate) code: Vec<u8>,
+    pub(crate) debuginfo: DebugInfo,
+    compiled_modules: HashSet<String>,
+}
+
+impl Program {
+    pub(crate) fn new() -> Self {
+        Self {
+            code: Vec::new(),
+            debuginfo: DebugInfo::new(),
+            compiled_modules: HashSet::new(),
+        }
+    }
+
+    // code that was compiled somewhere else, like a loaded artifact
+    pub(crate) fn from_code(code: Vec<u8>, debuginfo: DebugInfo) -> Self {
+        Self {
+            code,
+            debuginfo,
+            compiled_modules: HashSet::new(),
+        }
+    }
 }
 
 impl<'a> Compiler<'a> {
@@ -31,14 +69,62 @@ impl<'a> Compiler<'a> {
             bytecode: Some(Bytecode::new()),
             compiled_modules: HashSet::new(),
             debuginfo: DebugInfo::new(),
+            interactive: false,
+            permissions: None,
         }
     }
 
+    pub(crate) fn resume(agent: &'a mut Agent, program: Program) -> Self {
+        Self {
+            agent,
+            bytecode: Some(Bytecode::resume(program.code)),
+            compiled_modules: program.compiled_modules,
+            debuginfo: program.debuginfo,
+            interactive: false,
+            permissions: None,
+        }
+    }
+
+    /// Leaves the value of the entry file's last statement on the stack if
+    /// it's an expression, so the interpreter returns it.
+    pub(crate) fn set_interactive(&mut self) {
+        self.interactive = true;
+    }
+
+    /// Only lets the program import files that `permissions` allows reading,
+    /// or that are below the directory of the entry file.
+    pub(crate) fn set_permissions(&mut self, permissions: Permissions) {
+        self.permissions = Some(permissions);
+    }
+
     pub(crate) fn end(self) -> (Option<Vec<u8>>, DebugInfo) {
         (self.bytecode.map(Bytecode::into), self.debuginfo)
     }
 
+    /// Ends the code with a halt, so running it stops before whatever gets
+    /// compiled into the program next.
+    pub(crate) fn into_program(self) -> Program {
+        let code = self.bytecode.map(|mut bytecode| {
+            bytecode.halt();
+            bytecode.into()
+        });
+
+        Program {
+            code: code.unwrap_or_default(),
+            debuginfo: self.debuginfo,
+            compiled_modules: self.compiled_modules,
+        }
+    }
+
     pub(crate) fn compile_file<T, U>(&mut self, pwd: U, path: T) -> Result
+    where
+        T: AsRef<Path>,
+        U: AsRef<Path>,
+    {
+        self.compile_file_inner(pwd, path, false)
+    }
+
+    fn compile_file_inner<T, U>(&mut self, pwd: U, path: T, is_import: bool) -> Result
     where
         T: AsRef<Path>,
         U: AsRef<Path>,
@@ -51,13 +137,33 @@ impl<'a> Compiler<'a> {
             path = joined.as_ref();
         }
 
-        let path = path.canonicalize()?;
+        let read_error = |e: std::io::Error| {
+            VmError::CompileError(format!("Could not read file: {}", e).into())
+                .with_module(path.to_string_lossy())
+        };
+
+        let path = match &self.permissions {
+            // the entry file is picked by the host, but imports come from the
+            // program
+            Some(permissions) if is_import => permissions
+                .check_read(path)
+                .map_err(|e| e.with_module(path.to_string_lossy()))?,
+            _ => path.canonicalize().map_err(read_error)?,
+        };
         let pwd = path.parent().unwrap();
         let name = path.to_string_lossy().into_owned();
+        if !is_import {
+            self.allow_imports_from(pwd);
+        }
+
+        // a file that's imported more than once is only compiled once
+        if !self.compiled_modules.insert(name.clone()) {
+            return Ok(());
+        }
 
-        let text = fs::read_to_string(&name)?;
+        let text = fs::read_to_string(&name).map_err(read_error)?;
 
-        self.compile(pwd, name, text)
+        self.compile_source(pwd, name, text, is_import)
     }
 
     pub(crate) fn compile<T, P>(&mut self, pwd: P, name: String, text: T) -> Result
@@ -65,32 +171,110 @@ impl<'a> Compiler<'a> {
         T: AsRef<str>,
         P: AsRef<Path> + Clone,
     {
-        if self.compiled_modules.contains(&name) {
-            return Ok(());
-        }
+        self.allow_imports_from(pwd.as_ref());
+        self.compile_source(pwd, name, text, false)
+    }
+
+    // The entry file's own directory, which the standard library usually
+    // sits in too, can always be imported from. That only affects compiling,
+    // the running program still needs permission to read anything.
+    fn allow_imports_from(&mut self, dir: &Path) {
+        self.permissions = self
+            .permissions
+            .take()
+            .map(|permissions| permissions.allow_read(dir));
+    }
 
+    fn compile_source<T, P>(&mut self, pwd: P, name: String, text: T, is_import: bool) -> Result
+    where
+        T: AsRef<str>,
+        P: AsRef<Path> + Clone,
+    {
         let text = text.as_ref();
 
         let lexer = parser::Lexer::new(&name, text);
         let mut parser = parser::Parser::new(&name, self.agent, lexer);
         let parsed_module = parser.parse()?;
 
+        // only the entry file may leave out its module header, since there is
+        // no way to refer to it by name anyway
+        let spec = match parsed_module.spec {
+            Some(spec) => spec,
+            None if is_import => {
+                return Err(VmError::CompileError(
+                    "Imported file is missing a module header".into(),
+                )
+                .with_module(name))
+            }
+            None => ModuleSpec::new(self.agent.intern_string(ENTRY_MODULE_NAME)),
+        };
+
         for import in parsed_module.imports {
-            self.compile_file(pwd.clone(), import)?;
+            match import.strip_prefix("native:") {
+                // already set up by whoever registered it
+                Some(path) if self.agent.native_modules.contains_key(path) => {}
+                Some(path) => {
+                    return Err(VmError::CompileError(
+                        format!("Unknown native module {}", path).into(),
+                    )
+                    .with_module(name))
+                }
+                None => self.compile_file_inner(pwd.clone(), import, true)?,
+            }
         }
 
-        self.agent
-            .modules
-            .insert(parsed_module.spec.name, parsed_module.spec.clone());
-        let gen = codegen::CodeGen::with_bytecode(
+        self.agent.modules.insert(spec.name, spec.clone());
+        self.debuginfo.add_file(name.clone(), text.to_string());
+        let mut gen = codegen::CodeGen::with_bytecode(
             self.agent,
             &mut self.debuginfo,
             self.bytecode.take().unwrap(),
         );
+        if self.interactive && !is_import {
+            gen.set_interactive();
+        }
 
-        self.bytecode
-            .replace(gen.compile(parsed_module.spec, parsed_module.statements.iter())?);
+        self.bytecode.replace(
+            gen.compile(spec, parsed_module.statements.iter())
+                .map_err(|e| e.with_module(name))?,
+        );
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_without_module_header() {
+        let mut agent = Agent::new();
+        let mut compiler = Compiler::new(&mut agent);
+
+        assert!(compiler
+            .compile(".", "entry".to_string(), "let a = 1;")
+            .is_ok());
+        assert!(agent
+            .modules
+            .values()
+            .any(|m| agent.string_table[m.name] == ENTRY_MODULE_NAME));
+    }
+
+    #[test]
+    fn test_import_without_module_header() {
+        let dir = std::env::temp_dir().join(format!("rbcvm-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("nohead.rbcvm"), "let a = 1;").unwrap();
+
+        let mut agent = Agent::new();
+        let mut compiler = Compiler::new(&mut agent);
+        let result = compiler.compile(&dir, "entry".to_string(), r#"import "nohead.rbcvm";"#);
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("nohead.rbcvm"));
+        assert!(message.contains("missing a module header"));
+    }
+}
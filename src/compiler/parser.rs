@@ -1,7 +1,10 @@
 use std::iter::Peekable;
 use std::str::Chars;
 
+use std::collections::HashSet;
+
 use crate::agent::Agent;
+use crate::error::VmError;
 use crate::module::ModuleSpec;
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -10,6 +13,12 @@ pub enum TokenType {
     Integer,
     Double,
     String,
+    // the pieces of a string with interpolations, around the expressions
+    // in it: "start ${a} middle ${b} end"
+    InterpolationStart,
+    InterpolationMiddle,
+    InterpolationEnd,
+    Atom,
     Null,
     LeftBracket,
     RightBracket,
@@ -31,6 +40,7 @@ pub enum TokenType {
     Semicolon,
     Equal,
     EqualEqual,
+    FatArrow,
     Tilde,
     Bang,
     BangEqual,
@@ -41,11 +51,27 @@ pub enum TokenType {
     GreaterGreater,
     GreaterThanEqual,
     Comma,
+    Colon,
     Dot,
+    DotDotDot,
+    DotDot,
+    DotDotEqual,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+    PercentEqual,
+    StarStarEqual,
+    AndEqual,
+    PipeEqual,
+    CaretEqual,
+    LessLessEqual,
+    GreaterGreaterEqual,
 
     Return,
     Function,
     For,
+    In,
     If,
     Else,
     While,
@@ -57,12 +83,17 @@ pub enum TokenType {
     Module,
     Export,
     Import,
+    Record,
+    Try,
+    Catch,
+    Throw,
+    Match,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Position {
-    line: usize,
-    column: usize,
+    pub line: usize,
+    pub column: usize,
 }
 
 impl std::fmt::Display for Position {
@@ -90,15 +121,34 @@ impl Token {
         }
     }
 
-    pub fn lbp(&self) -> Result<usize, String> {
+    pub fn typ(&self) -> TokenType {
+        self.typ
+    }
+
+    pub fn lbp(&self) -> ParseResult<usize> {
         Ok(match self.typ {
             TokenType::Identifier => 0,
             TokenType::Integer => 0,
             TokenType::Double => 0,
             TokenType::String => 0,
+            TokenType::InterpolationStart => 0,
+            TokenType::InterpolationMiddle => 0,
+            TokenType::InterpolationEnd => 0,
+            TokenType::Atom => 0,
             TokenType::Null => 0,
 
-            TokenType::Equal => 1,
+            TokenType::Equal
+            | TokenType::PlusEqual
+            | TokenType::MinusEqual
+            | TokenType::StarEqual
+            | TokenType::SlashEqual
+            | TokenType::PercentEqual
+            | TokenType::StarStarEqual
+            | TokenType::AndEqual
+            | TokenType::PipeEqual
+            | TokenType::CaretEqual
+            | TokenType::LessLessEqual
+            | TokenType::GreaterGreaterEqual => 1,
             TokenType::PipePipe => 2,
             TokenType::AndAnd => 3,
             TokenType::Pipe => 4,
@@ -110,25 +160,32 @@ impl Token {
             TokenType::LessThanEqual => 8,
             TokenType::GreaterThan => 8,
             TokenType::GreaterThanEqual => 8,
-            TokenType::LessLess => 9,
-            TokenType::GreaterGreater => 9,
-            TokenType::Plus => 10,
-            TokenType::Minus => 10,
-            TokenType::Star => 11,
-            TokenType::Slash => 12,
-            TokenType::Percent => 13,
-            TokenType::StarStar => 14,
-            TokenType::LeftParen => 16,
-            TokenType::LeftBracket => 16,
-            TokenType::Dot => 17,
+            TokenType::In => 8,
+            TokenType::DotDot => 9,
+            TokenType::DotDotEqual => 9,
+            TokenType::LessLess => 10,
+            TokenType::GreaterGreater => 10,
+            TokenType::Plus => 11,
+            TokenType::Minus => 11,
+            TokenType::Star => 12,
+            TokenType::Slash => 13,
+            TokenType::Percent => 14,
+            TokenType::StarStar => 15,
+            TokenType::LeftParen => 17,
+            TokenType::LeftBracket => 17,
+            TokenType::Dot => 18,
+            TokenType::DotDotDot => 0,
 
             TokenType::Semicolon => 0,
             TokenType::RightParen => 0,
             TokenType::RightBracket => 0,
             TokenType::LeftBrace => 0,
+            TokenType::RightBrace => 0,
             TokenType::Comma => 0,
+            TokenType::Colon => 0,
+            TokenType::FatArrow => 0,
 
-            _ => return Err(format!("Trying to get lbp of {:?}", self)),
+            _ => return error_at(self.position, format!("Unexpected token {:?}", self.typ)),
         })
     }
 
@@ -136,9 +193,9 @@ impl Token {
         match self.typ {
             TokenType::LeftParen => 0,
             TokenType::LeftBracket => 0,
-            TokenType::Bang => 11,
-            TokenType::Minus => 11,
-            TokenType::Tilde => 11,
+            TokenType::Bang => 12,
+            TokenType::Minus => 12,
+            TokenType::Tilde => 12,
 
             _ => unreachable!(),
         }
@@ -152,6 +209,11 @@ pub struct Lexer<'a> {
     input: &'a str,
     chars: Peekable<Chars<'a>>,
     pub(crate) filename: &'a str,
+    // the number of braces opened in each interpolation the lexer is in, so
+    // that the brace closing the interpolation can be told apart
+    interpolations: Vec<usize>,
+    // the last token that was lexed
+    previous: Option<TokenType>,
 }
 
 impl<'a> Lexer<'a> {
@@ -163,18 +225,23 @@ impl<'a> Lexer<'a> {
             input,
             chars: input.chars().peekable(),
             filename,
+            interpolations: Vec::new(),
+            previous: None,
         }
     }
 
-    fn error(&self, msg: String) -> String {
-        format!("Error in {}: {}", self.filename, msg)
+    fn error(&self, msg: String, line: usize, column: usize) -> VmError {
+        VmError::LexError(msg.into())
+            .with_position(Position { line, column })
+            .with_module(self.filename)
     }
 
     fn next_char(&mut self) -> Option<char> {
         let next = self.chars.next();
-        if next.is_some() {
+        if let Some(c) = next {
             self.column += 1;
-            self.position += 1;
+            // a byte offset, for slicing the input
+            self.position += c.len_utf8();
         }
         next
     }
@@ -183,7 +250,14 @@ impl<'a> Lexer<'a> {
         self.chars.peek()
     }
 
-    pub fn next_token(&mut self) -> Option<Result<Token, String>> {
+    // the character after the one peek_char returns
+    fn peek_second_char(&self) -> Option<char> {
+        let mut chars = self.chars.clone();
+        chars.next();
+        chars.next()
+    }
+
+    pub fn next_token(&mut self) -> Option<Result<Token, VmError>> {
         let mut start = self.position;
         let mut start_line = self.line;
         let mut start_column = self.column;
@@ -201,7 +275,7 @@ impl<'a> Lexer<'a> {
 
         macro_rules! error {
             ($message:expr $(, $stuff:expr)* $(,)?) => {
-                Some(Err(self.error(format!($message, $($stuff)*))))
+                Some(Err(self.error(format!($message, $($stuff)*), start_line, start_column)))
             };
         }
 
@@ -216,6 +290,15 @@ impl<'a> Lexer<'a> {
                     }
                     token!($typ);
                 }};
+                ($char:expr, $char2:expr, $typ:expr, $typ2:expr, $typ3:expr) => {{
+                    if let Some(c) = self.peek_char() {
+                        if *c == $char2 {
+                            self.next_char();
+                            token!($typ3);
+                        }
+                    }
+                    or2!($char, $typ, $typ2);
+                }};
             }
 
             match c {
@@ -243,36 +326,105 @@ impl<'a> Lexer<'a> {
                     start += len;
                 }
 
-                '+' => token!(TokenType::Plus),
-                '-' => token!(TokenType::Minus),
-                '/' => token!(TokenType::Slash),
-                '^' => token!(TokenType::Caret),
+                '+' => or2!('=', TokenType::Plus, TokenType::PlusEqual),
+                '-' => or2!('=', TokenType::Minus, TokenType::MinusEqual),
+                '/' => or2!('=', TokenType::Slash, TokenType::SlashEqual),
+                '^' => or2!('=', TokenType::Caret, TokenType::CaretEqual),
                 '[' => token!(TokenType::LeftBracket),
                 ']' => token!(TokenType::RightBracket),
-                '{' => token!(TokenType::LeftBrace),
-                '}' => token!(TokenType::RightBrace),
+                '{' => {
+                    if let Some(depth) = self.interpolations.last_mut() {
+                        *depth += 1;
+                    }
+                    token!(TokenType::LeftBrace);
+                }
+                '}' => match self.interpolations.last_mut() {
+                    Some(0) => {
+                        self.interpolations.pop();
+                        return Some(self.string(start_line, start_column, true));
+                    }
+                    Some(depth) => {
+                        *depth -= 1;
+                        token!(TokenType::RightBrace);
+                    }
+                    None => token!(TokenType::RightBrace),
+                },
                 '(' => token!(TokenType::LeftParen),
                 ')' => token!(TokenType::RightParen),
-                '%' => token!(TokenType::Percent),
+                '%' => or2!('=', TokenType::Percent, TokenType::PercentEqual),
                 ';' => token!(TokenType::Semicolon),
                 ',' => token!(TokenType::Comma),
                 '~' => token!(TokenType::Tilde),
-                '.' => token!(TokenType::Dot),
-                '*' => or2!('*', TokenType::Star, TokenType::StarStar),
-                '&' => or2!('&', TokenType::And, TokenType::AndAnd),
-                '|' => or2!('|', TokenType::Pipe, TokenType::PipePipe),
-                '=' => or2!('=', TokenType::Equal, TokenType::EqualEqual),
-                '<' => or2!('=', TokenType::LessThan, TokenType::LessThanEqual),
-                '>' => or2!('=', TokenType::GreaterThan, TokenType::GreaterThanEqual),
+                '.' => {
+                    if self.peek_char() == Some(&'.') {
+                        self.next_char();
+                        if self.peek_char() == Some(&'.') {
+                            self.next_char();
+                            token!(TokenType::DotDotDot);
+                        }
+                        or2!('=', TokenType::DotDot, TokenType::DotDotEqual);
+                    }
+                    token!(TokenType::Dot);
+                }
+                '*' => {
+                    if self.peek_char() == Some(&'*') {
+                        self.next_char();
+                        or2!('=', TokenType::StarStar, TokenType::StarStarEqual);
+                    }
+                    or2!('=', TokenType::Star, TokenType::StarEqual);
+                }
+                '&' => {
+                    or2!(
+                        '&',
+                        '=',
+                        TokenType::And,
+                        TokenType::AndAnd,
+                        TokenType::AndEqual
+                    )
+                }
+                '|' => or2!(
+                    '|',
+                    '=',
+                    TokenType::Pipe,
+                    TokenType::PipePipe,
+                    TokenType::PipeEqual
+                ),
+                '=' => or2!(
+                    '=',
+                    '>',
+                    TokenType::Equal,
+                    TokenType::EqualEqual,
+                    TokenType::FatArrow
+                ),
+                '<' => {
+                    if self.peek_char() == Some(&'<') {
+                        self.next_char();
+                        or2!('=', TokenType::LessLess, TokenType::LessLessEqual);
+                    }
+                    or2!('=', TokenType::LessThan, TokenType::LessThanEqual);
+                }
+                '>' => {
+                    if self.peek_char() == Some(&'>') {
+                        self.next_char();
+                        or2!(
+                            '=',
+                            TokenType::GreaterGreater,
+                            TokenType::GreaterGreaterEqual
+                        );
+                    }
+                    or2!('=', TokenType::GreaterThan, TokenType::GreaterThanEqual);
+                }
                 '!' => or2!('=', TokenType::Bang, TokenType::BangEqual),
 
                 '0'..='9' => {
                     let mut is_double = false;
-                    while let Some(c) = self.peek_char() {
+                    while let Some(&c) = self.peek_char() {
                         match c {
                             '0'..='9' => {
                                 self.next_char();
                             }
+                            // the start of a range like 1..2
+                            '.' if self.peek_second_char() == Some('.') => break,
                             '.' => {
                                 self.next_char();
                                 if is_double {
@@ -306,6 +458,7 @@ impl<'a> Lexer<'a> {
                     match &self.input[start..self.position] {
                         "return" => token!(TokenType::Return),
                         "for" => token!(TokenType::For),
+                        "in" => token!(TokenType::In),
                         "while" => token!(TokenType::While),
                         "function" => token!(TokenType::Function),
                         "let" => token!(TokenType::Let),
@@ -319,60 +472,141 @@ impl<'a> Lexer<'a> {
                         "module" => token!(TokenType::Module),
                         "export" => token!(TokenType::Export),
                         "import" => token!(TokenType::Import),
+                        "record" => token!(TokenType::Record),
+                        "try" => token!(TokenType::Try),
+                        "catch" => token!(TokenType::Catch),
+                        "throw" => token!(TokenType::Throw),
+                        "match" => token!(TokenType::Match),
                         _ => token!(TokenType::Identifier),
                     }
                 }
 
-                '"' => {
-                    let mut buf = String::new();
+                ':' => {
+                    // Right after an operand, like the key in `{:a:x}`, there
+                    // can't be an atom. Otherwise an atom's name has to follow
+                    // right away, like `:ok`.
+                    if matches!(
+                        self.previous,
+                        Some(
+                            TokenType::Identifier
+                                | TokenType::Integer
+                                | TokenType::Double
+                                | TokenType::String
+                                | TokenType::Atom
+                                | TokenType::InterpolationEnd
+                                | TokenType::Null
+                                | TokenType::True
+                                | TokenType::False
+                                | TokenType::RightParen
+                                | TokenType::RightBracket
+                        )
+                    ) {
+                        token!(TokenType::Colon);
+                    }
+                    match self.peek_char() {
+                        Some('a'..='z') | Some('A'..='Z') | Some('_') => {}
+                        _ => token!(TokenType::Colon),
+                    }
+
                     while let Some(c) = self.peek_char() {
                         match c {
-                            '\\' => {
-                                self.next_char();
-                                if let Some(c) = self.next_char() {
-                                    match c {
-                                        'n' => buf.push('\n'),
-                                        'r' => buf.push('\r'),
-                                        't' => buf.push('\t'),
-                                        '"' => buf.push('"'),
-                                        _ => return error!("unrecognized escape sequence"),
-                                    }
-                                } else {
-                                    return error!("unterminated escape sequence");
-                                }
-                            }
-                            '"' => {
-                                self.next_char();
-                                break;
-                            }
-                            _ => {
-                                buf.push(*c);
+                            'a'..='z' | 'A'..='Z' | '_' | '0'..='9' => {
                                 self.next_char();
                             }
+                            _ => break,
                         }
                     }
 
                     return Some(Ok(Token::new(
-                        TokenType::String,
+                        TokenType::Atom,
                         start_line,
                         start_column,
-                        buf.as_str(),
+                        &self.input[start + 1..self.position],
                     )));
                 }
 
+                '"' => return Some(self.string(start_line, start_column, false)),
+
                 _ => return error!("Unexpected character '{}'", c),
             }
         }
 
         None
     }
+
+    // Lexes a string literal up to its closing quote, or up to the next
+    // interpolation. `resumed` is set when the string picks up again after
+    // an interpolation.
+    fn string(&mut self, line: usize, column: usize, resumed: bool) -> Result<Token, VmError> {
+        let mut buf = String::new();
+        while let Some(&c) = self.peek_char() {
+            match c {
+                '\\' => {
+                    self.next_char();
+                    if let Some(c) = self.next_char() {
+                        match c {
+                            'n' => buf.push('\n'),
+                            'r' => buf.push('\r'),
+                            't' => buf.push('\t'),
+                            '"' => buf.push('"'),
+                            '$' => buf.push('$'),
+                            _ => {
+                                return Err(self.error(
+                                    "unrecognized escape sequence".to_string(),
+                                    line,
+                                    column,
+                                ))
+                            }
+                        }
+                    } else {
+                        return Err(self.error(
+                            "unterminated escape sequence".to_string(),
+                            line,
+                            column,
+                        ));
+                    }
+                }
+                '"' => {
+                    self.next_char();
+                    break;
+                }
+                '$' if self.peek_second_char() == Some('{') => {
+                    self.next_char();
+                    self.next_char();
+                    self.interpolations.push(0);
+                    let typ = if resumed {
+                        TokenType::InterpolationMiddle
+                    } else {
+                        TokenType::InterpolationStart
+                    };
+                    return Ok(Token::new(typ, line, column, buf.as_str()));
+                }
+                _ => {
+                    buf.push(c);
+                    self.next_char();
+                }
+            }
+        }
+
+        let typ = if resumed {
+            TokenType::InterpolationEnd
+        } else {
+            TokenType::String
+        };
+        Ok(Token::new(typ, line, column, buf.as_str()))
+    }
 }
 
 impl<'a> Iterator for Lexer<'a> {
-    type Item = Result<Token, String>;
+    type Item = Result<Token, VmError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.next_token()
+        let token = self.next_token();
+        self.previous = match &token {
+            Some(Ok(token)) => Some(token.typ),
+            _ => None,
+        };
+        token
     }
 }
 
@@ -409,12 +643,33 @@ pub enum StatementKind {
         increment: Option<Expression>,
         body: Vec<Statement>,
     },
+    ForIn {
+        key: Option<Expression>,
+        value: Expression,
+        iterable: Expression,
+        body: Vec<Statement>,
+    },
     Break,
     Continue,
     Return(Option<Expression>),
     Expression(Expression),
     Export(Box<Statement>),
     Import(String),
+    Record {
+        name: Expression,
+        fields: Vec<Expression>,
+    },
+    Try {
+        body: Vec<Statement>,
+        binding: Expression,
+        handler: Vec<Statement>,
+    },
+    Throw(Expression),
+    // a match whose arms are blocks, or expressions whose values are dropped
+    Match {
+        value: Expression,
+        arms: Vec<MatchArm<Vec<Statement>>>,
+    },
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -429,9 +684,14 @@ pub enum ExpressionKind {
     Integer(i64),
     Double(f64),
     String(usize),
+    // the strings and expressions of a string with interpolations, in order
+    Interpolation(Vec<Expression>),
+    Atom(usize),
     Boolean(bool),
     Null,
     Array(Vec<Expression>),
+    // key and value pairs
+    Map(Vec<(Expression, Expression)>),
     Function {
         parameters: Vec<Expression>,
         body: Vec<Statement>,
@@ -439,22 +699,70 @@ pub enum ExpressionKind {
     UnaryOperation(TokenType, Box<Expression>),
     BinaryOperation(Box<Expression>, TokenType, Box<Expression>),
     Call(Box<Expression>, Vec<Expression>),
+    // only valid as the last parameter of a function or as a call argument
+    Spread(Box<Expression>),
     Index(Box<Expression>, Box<Expression>),
+    Record {
+        name: usize,
+        fields: Vec<(Expression, Expression)>,
+    },
+    Match(Box<Expression>, Vec<MatchArm<Expression>>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MatchArm<T> {
+    pub pattern: Pattern,
+    pub guard: Option<Expression>,
+    pub body: T,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Pattern {
+    pub position: Position,
+    pub value: PatternKind,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PatternKind {
+    // `_`, which matches anything
+    Wildcard,
+    Binding(usize),
+    // a literal, or a constant like `OK` or `Result.OK`, compared with ==
+    Value(Expression),
+    Range {
+        start: Expression,
+        end: Expression,
+        inclusive: bool,
+    },
+    // the elements, and what the ones after them are bound to if the pattern
+    // ends with a `...rest`
+    Array(Vec<Pattern>, Option<Box<Pattern>>),
+    // keys that must be in the map and the patterns for their values
+    Map(Vec<(Expression, Pattern)>),
+    Record {
+        name: usize,
+        fields: Vec<(Expression, Pattern)>,
+    },
 }
 
-pub type ParseResult<T> = Result<T, String>;
+pub type ParseResult<T> = Result<T, VmError>;
+
+#[inline]
+fn error_at<T>(position: Position, message: String) -> ParseResult<T> {
+    Err(VmError::ParseError(message.into()).with_position(position))
+}
 
 #[inline]
 fn assert_ident(ident: &Expression) -> ParseResult<()> {
     if let ExpressionKind::Identifier(_) = ident.value {
         Ok(())
     } else {
-        Err(format!("Expected identifier at {}", ident.position))
+        error_at(ident.position, "Expected identifier".to_string())
     }
 }
 
 pub(crate) struct ParsedModule {
-    pub(crate) spec: ModuleSpec,
+    pub(crate) spec: Option<ModuleSpec>,
     pub(crate) imports: Vec<String>,
     pub(crate) statements: Vec<Statement>,
 }
@@ -465,6 +773,9 @@ pub struct Parser<'a> {
     current_token: Option<Token>,
     module: Option<ModuleSpec>,
     filename: &'a str,
+    // record names declared so far, so that `Name {` can be parsed as a
+    // record literal
+    records: HashSet<usize>,
 }
 
 impl<'a> Parser<'a> {
@@ -475,21 +786,20 @@ impl<'a> Parser<'a> {
             current_token: None,
             module: None,
             filename,
+            records: HashSet::new(),
         }
     }
 
     pub(crate) fn parse(&mut self) -> ParseResult<ParsedModule> {
         let (imports, statements): (Vec<ParseResult<Statement>>, Vec<ParseResult<Statement>>) =
             self.partition(|s| {
-                if let Ok(Statement {
-                    value: StatementKind::Import(_),
-                    ..
-                }) = s
-                {
-                    true
-                } else {
-                    false
-                }
+                matches!(
+                    s,
+                    Ok(Statement {
+                        value: StatementKind::Import(_),
+                        ..
+                    })
+                )
             });
 
         let imports = imports
@@ -510,21 +820,28 @@ impl<'a> Parser<'a> {
             .collect::<ParseResult<Vec<_>>>()?;
 
         Ok(ParsedModule {
-            spec: self.module.take().unwrap(),
+            spec: self.module.take(),
             imports,
             statements: statements.into_iter().collect::<ParseResult<Vec<_>>>()?,
         })
     }
 
-    fn error<T>(&self, msg: String) -> ParseResult<T> {
-        Err(format!("Error in {}: {}", self.filename, msg))
-    }
-
     pub fn next_statement(&mut self) -> Option<ParseResult<Statement>> {
-        match self.peek() {
-            Ok(Some(_)) => Some(self.parse_statement()),
-            Err(msg) => Some(Err(msg.clone())),
-            Ok(None) => None,
+        loop {
+            return match self.peek() {
+                // a module declaration isn't a statement of its own, so one at
+                // the end of the input leaves nothing to parse
+                Ok(Some(Token {
+                    typ: TokenType::Module,
+                    ..
+                })) => match self.parse_module_statement() {
+                    Ok(()) => continue,
+                    Err(e) => Some(Err(e.with_module(self.filename))),
+                },
+                Ok(Some(_)) => Some(self.parse_statement()),
+                Err(e) => Some(Err(e)),
+                Ok(None) => None,
+            };
         }
     }
 
@@ -545,18 +862,19 @@ impl<'a> Parser<'a> {
     fn expect(&mut self, expected: TokenType) -> ParseResult<Token> {
         match &self.next_token()? {
             Some(tok) if tok.typ == expected => Ok(tok.clone()),
-            Some(Token { typ, position, .. }) => Err(format!(
-                "Expected {:?}, got {:?} at {}",
-                expected, typ, position
+            Some(Token { typ, position, .. }) => {
+                error_at(*position, format!("Expected {:?}, got {:?}", expected, typ))
+            }
+            None => Err(VmError::ParseError(
+                format!("Expected {:?}, found end of input", expected).into(),
             )),
-            None => Err(format!("Expected {:?}, found end of input", expected)),
         }
     }
 
     fn peek(&mut self) -> ParseResult<Option<&Token>> {
         match self.lexer.peek() {
-            Some(&Ok(ref tok)) => Ok(Some(tok)),
-            Some(Err(msg)) => Err(format!("Error in {}: {}", self.filename, msg.clone())),
+            Some(Ok(tok)) => Ok(Some(tok)),
+            Some(Err(e)) => Err(e.clone()),
             None => Ok(None),
         }
     }
@@ -585,6 +903,10 @@ impl<'a> Parser<'a> {
                     TokenType::Return => self.parse_return_statement(),
                     TokenType::Export => self.parse_export_statement(),
                     TokenType::Import => self.parse_import_statement(),
+                    TokenType::Record => self.parse_record_declaration(),
+                    TokenType::Try => self.parse_try_statement(),
+                    TokenType::Throw => self.parse_throw_statement(),
+                    TokenType::Match => self.parse_match_statement(),
                     TokenType::Module => {
                         self.parse_module_statement()?;
                         continue;
@@ -592,10 +914,12 @@ impl<'a> Parser<'a> {
                     _ => self.parse_expression_statement(),
                 }
             } else {
-                unreachable!();
+                Err(VmError::ParseError(
+                    "Expected statement, found end of input".into(),
+                ))
             } {
                 Ok(s) => Ok(s),
-                Err(msg) => self.error(msg),
+                Err(e) => Err(e.with_module(self.filename)),
             };
         }
     }
@@ -639,6 +963,49 @@ impl<'a> Parser<'a> {
         Ok(items)
     }
 
+    fn parse_spread(&mut self) -> ParseResult<Expression> {
+        match self.peek()? {
+            Some(&Token {
+                typ: TokenType::DotDotDot,
+                position,
+                ..
+            }) => {
+                self.expect(TokenType::DotDotDot)?;
+                Ok(Expression {
+                    position,
+                    value: ExpressionKind::Spread(Box::new(self.parse_expression()?)),
+                })
+            }
+            _ => self.parse_expression(),
+        }
+    }
+
+    fn parse_parameter_list(&mut self) -> ParseResult<Vec<Expression>> {
+        let params = self.parse_list(
+            TokenType::RightParen,
+            TokenType::Comma,
+            Self::parse_spread,
+            |param| match &param.value {
+                ExpressionKind::Spread(ident) => assert_ident(ident),
+                _ => assert_ident(param),
+            },
+        )?;
+
+        if let Some(rest) = params
+            .iter()
+            .rev()
+            .skip(1)
+            .find(|p| matches!(p.value, ExpressionKind::Spread(_)))
+        {
+            return error_at(
+                rest.position,
+                "Rest parameter must be the last parameter".to_string(),
+            );
+        }
+
+        Ok(params)
+    }
+
     fn parse_function_declaration(&mut self) -> ParseResult<Statement> {
         let function = self.expect(TokenType::Function)?;
         let ident = self.expect(TokenType::Identifier)?;
@@ -646,12 +1013,7 @@ impl<'a> Parser<'a> {
 
         self.expect(TokenType::LeftParen)?;
 
-        let params = self.parse_list(
-            TokenType::RightParen,
-            TokenType::Comma,
-            Self::parse_expression,
-            assert_ident,
-        )?;
+        let params = self.parse_parameter_list()?;
 
         let mut body = Vec::new();
 
@@ -670,6 +1032,33 @@ impl<'a> Parser<'a> {
         })
     }
 
+    fn parse_record_declaration(&mut self) -> ParseResult<Statement> {
+        let record = self.expect(TokenType::Record)?;
+        let ident = self.expect(TokenType::Identifier)?;
+        let ident = self.parse_identifier_expression(ident)?;
+
+        self.expect(TokenType::LeftBrace)?;
+
+        let fields = self.parse_list(
+            TokenType::RightBrace,
+            TokenType::Comma,
+            Self::parse_expression,
+            assert_ident,
+        )?;
+
+        if let ExpressionKind::Identifier(name) = ident.value {
+            self.records.insert(name);
+        }
+
+        Ok(Statement {
+            position: record.position,
+            value: StatementKind::Record {
+                name: ident,
+                fields,
+            },
+        })
+    }
+
     fn parse_if_statement(&mut self) -> ParseResult<Statement> {
         let if_ = self.expect(TokenType::If)?;
         let predicate = self.parse_expression()?;
@@ -726,8 +1115,22 @@ impl<'a> Parser<'a> {
     fn parse_for_statement(&mut self) -> ParseResult<Statement> {
         let for_ = self.expect(TokenType::For)?;
 
+        // `for x in` and `for x = 0;` both start with an identifier, so
+        // which loop it is only shows after it
         let initializer = if self.matches(TokenType::Semicolon)? {
             None
+        } else if self.peek()?.map(|t| t.typ) == Some(TokenType::Identifier) {
+            let ident = self.expect(TokenType::Identifier)?;
+            let ident = self.parse_identifier_expression(ident)?;
+            if let Some(TokenType::In) | Some(TokenType::Comma) = self.peek()?.map(|t| t.typ) {
+                return self.parse_for_in_statement(for_, ident);
+            }
+            let expression = self.parse_infix_expression(ident, 0)?;
+            self.expect(TokenType::Semicolon)?;
+            Some(Box::new(Statement {
+                position: expression.position,
+                value: StatementKind::Expression(expression),
+            }))
         } else {
             Some(Box::new(self.parse_statement()?))
         };
@@ -764,6 +1167,124 @@ impl<'a> Parser<'a> {
         })
     }
 
+    fn parse_for_in_statement(&mut self, for_: Token, first: Expression) -> ParseResult<Statement> {
+        let (key, value) = if self.matches(TokenType::Comma)? {
+            let value = self.expect(TokenType::Identifier)?;
+            (Some(first), self.parse_identifier_expression(value)?)
+        } else {
+            (None, first)
+        };
+
+        self.expect(TokenType::In)?;
+        let iterable = self.parse_expression()?;
+        self.expect(TokenType::LeftBrace)?;
+
+        let mut body = Vec::new();
+        while !self.matches(TokenType::RightBrace)? {
+            body.push(self.parse_statement()?);
+        }
+
+        Ok(Statement {
+            position: for_.position,
+            value: StatementKind::ForIn {
+                key,
+                value,
+                iterable,
+                body,
+            },
+        })
+    }
+
+    fn parse_try_statement(&mut self) -> ParseResult<Statement> {
+        let try_ = self.expect(TokenType::Try)?;
+        self.expect(TokenType::LeftBrace)?;
+
+        let mut body = Vec::new();
+        while !self.matches(TokenType::RightBrace)? {
+            body.push(self.parse_statement()?);
+        }
+
+        self.expect(TokenType::Catch)?;
+        self.expect(TokenType::LeftParen)?;
+        let ident = self.expect(TokenType::Identifier)?;
+        let binding = self.parse_identifier_expression(ident)?;
+        self.expect(TokenType::RightParen)?;
+        self.expect(TokenType::LeftBrace)?;
+
+        let mut handler = Vec::new();
+        while !self.matches(TokenType::RightBrace)? {
+            handler.push(self.parse_statement()?);
+        }
+
+        Ok(Statement {
+            position: try_.position,
+            value: StatementKind::Try {
+                body,
+                binding,
+                handler,
+            },
+        })
+    }
+
+    fn parse_throw_statement(&mut self) -> ParseResult<Statement> {
+        let throw = self.expect(TokenType::Throw)?;
+        let expression = self.parse_expression()?;
+        self.expect(TokenType::Semicolon)?;
+
+        Ok(Statement {
+            position: throw.position,
+            value: StatementKind::Throw(expression),
+        })
+    }
+
+    fn parse_match_statement(&mut self) -> ParseResult<Statement> {
+        let match_ = self.expect(TokenType::Match)?;
+        let value = self.parse_expression()?;
+        self.expect(TokenType::LeftBrace)?;
+
+        // an arm's body is a block, or an expression that needs a comma after
+        // it unless it's the last arm
+        let mut arms = Vec::new();
+        while !self.matches(TokenType::RightBrace)? {
+            let pattern = self.parse_pattern()?;
+            let guard = self.parse_match_guard()?;
+
+            let (body, separated) = if self.matches(TokenType::LeftBrace)? {
+                let mut body = Vec::new();
+                while !self.matches(TokenType::RightBrace)? {
+                    body.push(self.parse_statement()?);
+                }
+                self.matches(TokenType::Comma)?;
+                (body, true)
+            } else {
+                let expression = self.parse_expression()?;
+                let body = vec![Statement {
+                    position: expression.position,
+                    value: StatementKind::Expression(expression),
+                }];
+                (body, self.matches(TokenType::Comma)?)
+            };
+
+            arms.push(MatchArm {
+                pattern,
+                guard,
+                body,
+            });
+
+            if !separated {
+                self.expect(TokenType::RightBrace)?;
+                break;
+            }
+        }
+        // it reads like a match expression, which would be followed by one
+        self.matches(TokenType::Semicolon)?;
+
+        Ok(Statement {
+            position: match_.position,
+            value: StatementKind::Match { value, arms },
+        })
+    }
+
     fn parse_return_statement(&mut self) -> ParseResult<Statement> {
         let return_ = self.expect(TokenType::Return)?;
 
@@ -787,10 +1308,20 @@ impl<'a> Parser<'a> {
 
         let name = match &decl.value {
             StatementKind::Function { name, .. } | StatementKind::Let { name, .. } => &name.value,
-            _ => return Err("Can only export declarations".to_string()),
+            _ => return error_at(export.position, "Can only export declarations".to_string()),
+        };
+
+        let module = match self.module.as_mut() {
+            Some(module) => module,
+            None => {
+                return error_at(
+                    export.position,
+                    "Can only export from a file with a module header".to_string(),
+                )
+            }
         };
 
-        self.module.as_mut().unwrap().add_export(match name {
+        module.add_export(match name {
             ExpressionKind::Identifier(name) => *name,
             _ => unreachable!(),
         });
@@ -807,7 +1338,12 @@ impl<'a> Parser<'a> {
 
         let idx = match filename.value {
             ExpressionKind::String(idx) => idx,
-            _ => return Err("Import filename must be a string literal".to_string()),
+            _ => {
+                return error_at(
+                    filename.position,
+                    "Import filename must be a string literal".to_string(),
+                )
+            }
         };
 
         self.expect(TokenType::Semicolon)?;
@@ -866,23 +1402,36 @@ impl<'a> Parser<'a> {
 
     fn nud(&mut self, token: Token) -> ParseResult<Expression> {
         match token.typ {
-            TokenType::Identifier => self.parse_identifier_expression(token),
+            TokenType::Identifier => {
+                let ident = self.parse_identifier_expression(token)?;
+                let is_record = match ident.value {
+                    ExpressionKind::Identifier(name) => self.records.contains(&name),
+                    _ => false,
+                };
+
+                if is_record && self.peek()?.map(|t| t.typ) == Some(TokenType::LeftBrace) {
+                    self.parse_record_expression(ident)
+                } else {
+                    Ok(ident)
+                }
+            }
             TokenType::Integer => self.parse_integer_expression(token),
             TokenType::Double => self.parse_double_expression(token),
             TokenType::String => self.parse_string_expression(token),
+            TokenType::InterpolationStart => self.parse_interpolation_expression(token),
+            TokenType::Atom => self.parse_atom_expression(token),
             TokenType::True | TokenType::False => self.parse_boolean_expression(token),
             TokenType::Null => self.parse_null_expression(token),
             TokenType::LeftParen => self.parse_parenthesized_expression(token),
             TokenType::LeftBracket => self.parse_array_expression(token),
+            TokenType::LeftBrace => self.parse_map_expression(token),
             TokenType::Minus | TokenType::Bang | TokenType::Tilde => {
                 self.parse_unary_expression(token)
             }
             TokenType::Function => self.parse_function_expression(token),
+            TokenType::Match => self.parse_match_expression(token),
 
-            _ => Err(format!(
-                "Unexpected token {:?} at {}",
-                token.typ, token.position
-            )),
+            _ => error_at(token.position, format!("Unexpected token {:?}", token.typ)),
         }
     }
 
@@ -906,15 +1455,27 @@ impl<'a> Parser<'a> {
             | TokenType::GreaterGreater
             | TokenType::Dot
             | TokenType::AndAnd
-            | TokenType::PipePipe => self.parse_left_assoc_binary(token, left),
-            TokenType::Equal | TokenType::StarStar => self.parse_right_assoc_binary(token, left),
+            | TokenType::PipePipe
+            | TokenType::DotDot
+            | TokenType::DotDotEqual
+            | TokenType::In => self.parse_left_assoc_binary(token, left),
+            TokenType::Equal
+            | TokenType::StarStar
+            | TokenType::PlusEqual
+            | TokenType::MinusEqual
+            | TokenType::StarEqual
+            | TokenType::SlashEqual
+            | TokenType::PercentEqual
+            | TokenType::StarStarEqual
+            | TokenType::AndEqual
+            | TokenType::PipeEqual
+            | TokenType::CaretEqual
+            | TokenType::LessLessEqual
+            | TokenType::GreaterGreaterEqual => self.parse_right_assoc_binary(token, left),
             TokenType::LeftParen => self.parse_call_expression(token, left),
             TokenType::LeftBracket => self.parse_index_expression(token, left),
 
-            _ => Err(format!(
-                "Unexpected token {:?} at {}",
-                token.typ, token.position
-            )),
+            _ => error_at(token.position, format!("Unexpected token {:?}", token.typ)),
         }
     }
 
@@ -925,16 +1486,25 @@ impl<'a> Parser<'a> {
     fn parse_expression_inner(&mut self, rbp: usize) -> ParseResult<Expression> {
         macro_rules! some {
             ($token:expr) => {
-                $token.ok_or_else(|| "Unexpected end of input".to_string())?
+                $token.ok_or_else(|| VmError::ParseError("Unexpected end of input".into()))?
             };
         }
 
-        let mut t = some!(self.next_token()?);
-        let mut left = self.nud(t)?;
+        let t = some!(self.next_token()?);
+        let left = self.nud(t)?;
+        self.parse_infix_expression(left, rbp)
+    }
+
+    // parses the operators that follow an expression that was already parsed
+    fn parse_infix_expression(
+        &mut self,
+        mut left: Expression,
+        rbp: usize,
+    ) -> ParseResult<Expression> {
         let mut token = self.peek()?.cloned();
 
         while token.is_some() && rbp < token.unwrap().lbp()? {
-            t = some!(self.next_token()?);
+            let t = self.next_token()?.unwrap();
             left = self.led(t, left)?;
             token = self.peek()?.cloned();
         }
@@ -982,6 +1552,56 @@ impl<'a> Parser<'a> {
         })
     }
 
+    // empty pieces of the string are left out
+    fn parse_interpolation_expression(&mut self, start: Token) -> ParseResult<Expression> {
+        let position = start.position;
+        let mut parts = Vec::new();
+        let mut piece = start;
+
+        loop {
+            if !piece.text.is_empty() {
+                parts.push(self.parse_string_expression(piece.clone())?);
+            }
+            if piece.typ == TokenType::InterpolationEnd {
+                break;
+            }
+
+            parts.push(self.parse_expression()?);
+            piece = match self.next_token()? {
+                Some(
+                    token @ Token {
+                        typ: TokenType::InterpolationMiddle | TokenType::InterpolationEnd,
+                        ..
+                    },
+                ) => token,
+                Some(token) => {
+                    return error_at(
+                        token.position,
+                        format!("Expected }} after interpolation, got {:?}", token.typ),
+                    )
+                }
+                None => {
+                    return Err(VmError::ParseError(
+                        "Expected } after interpolation, found end of input".into(),
+                    ))
+                }
+            };
+        }
+
+        Ok(Expression {
+            position,
+            value: ExpressionKind::Interpolation(parts),
+        })
+    }
+
+    fn parse_atom_expression(&mut self, atom: Token) -> ParseResult<Expression> {
+        let id = self.agent.intern_string(&atom.text);
+        Ok(Expression {
+            position: atom.position,
+            value: ExpressionKind::Atom(id),
+        })
+    }
+
     fn parse_null_expression(&mut self, null: Token) -> ParseResult<Expression> {
         Ok(Expression {
             position: null.position,
@@ -1019,6 +1639,30 @@ impl<'a> Parser<'a> {
         })
     }
 
+    // A key that's a plain identifier is the name as a string, like `{ a: 1 }`
+    // is the same as `{ "a": 1 }`.
+    fn parse_map_entry(&mut self) -> ParseResult<(Expression, Expression)> {
+        let mut key = self.parse_expression()?;
+        if let ExpressionKind::Identifier(name) = key.value {
+            key.value = ExpressionKind::String(name);
+        }
+        self.expect(TokenType::Colon)?;
+        let value = self.parse_expression()?;
+        Ok((key, value))
+    }
+
+    fn parse_map_expression(&mut self, left_brace: Token) -> ParseResult<Expression> {
+        Ok(Expression {
+            position: left_brace.position,
+            value: ExpressionKind::Map(self.parse_list(
+                TokenType::RightBrace,
+                TokenType::Comma,
+                Self::parse_map_entry,
+                |_| Ok(()),
+            )?),
+        })
+    }
+
     fn parse_unary_expression(&mut self, op: Token) -> ParseResult<Expression> {
         let right = self.parse_expression_inner(op.rbp())?;
 
@@ -1054,7 +1698,7 @@ impl<'a> Parser<'a> {
         let args = self.parse_list(
             TokenType::RightParen,
             TokenType::Comma,
-            Self::parse_expression,
+            Self::parse_spread,
             |_| Ok(()),
         )?;
 
@@ -1078,16 +1722,42 @@ impl<'a> Parser<'a> {
         })
     }
 
-    fn parse_function_expression(&mut self, functionkw: Token) -> ParseResult<Expression> {
-        self.expect(TokenType::LeftParen)?;
+    fn parse_record_field(&mut self) -> ParseResult<(Expression, Expression)> {
+        let ident = self.expect(TokenType::Identifier)?;
+        let ident = self.parse_identifier_expression(ident)?;
+        self.expect(TokenType::Equal)?;
+        // parse above assignment precedence so the `=` isn't swallowed
+        let value = self.parse_expression_inner(1)?;
+        Ok((ident, value))
+    }
 
-        let parameters = self.parse_list(
-            TokenType::RightParen,
+    fn parse_record_expression(&mut self, name: Expression) -> ParseResult<Expression> {
+        self.expect(TokenType::LeftBrace)?;
+
+        let fields = self.parse_list(
+            TokenType::RightBrace,
             TokenType::Comma,
-            Self::parse_expression,
-            assert_ident,
+            Self::parse_record_field,
+            |_| Ok(()),
         )?;
 
+        Ok(Expression {
+            position: name.position,
+            value: ExpressionKind::Record {
+                name: match name.value {
+                    ExpressionKind::Identifier(name) => name,
+                    _ => unreachable!(),
+                },
+                fields,
+            },
+        })
+    }
+
+    fn parse_function_expression(&mut self, functionkw: Token) -> ParseResult<Expression> {
+        self.expect(TokenType::LeftParen)?;
+
+        let parameters = self.parse_parameter_list()?;
+
         self.expect(TokenType::LeftBrace)?;
 
         let mut body = Vec::new();
@@ -1100,6 +1770,259 @@ impl<'a> Parser<'a> {
             value: ExpressionKind::Function { parameters, body },
         })
     }
+
+    fn parse_match_expression(&mut self, match_: Token) -> ParseResult<Expression> {
+        let value = self.parse_expression()?;
+        self.expect(TokenType::LeftBrace)?;
+
+        let arms = self.parse_list(
+            TokenType::RightBrace,
+            TokenType::Comma,
+            Self::parse_match_arm,
+            |_| Ok(()),
+        )?;
+
+        Ok(Expression {
+            position: match_.position,
+            value: ExpressionKind::Match(Box::new(value), arms),
+        })
+    }
+
+    fn parse_match_arm(&mut self) -> ParseResult<MatchArm<Expression>> {
+        let pattern = self.parse_pattern()?;
+        let guard = self.parse_match_guard()?;
+        let body = self.parse_expression()?;
+
+        Ok(MatchArm {
+            pattern,
+            guard,
+            body,
+        })
+    }
+
+    // the optional `if guard` of an arm, up to and including the `=>`
+    fn parse_match_guard(&mut self) -> ParseResult<Option<Expression>> {
+        let guard = if self.matches(TokenType::If)? {
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+        self.expect(TokenType::FatArrow)?;
+        Ok(guard)
+    }
+
+    fn parse_pattern(&mut self) -> ParseResult<Pattern> {
+        let token = self
+            .next_token()?
+            .ok_or_else(|| VmError::ParseError("Unexpected end of input".into()))?;
+        let position = token.position;
+
+        let value = match token.typ {
+            TokenType::Identifier if token.text == "_" => PatternKind::Wildcard,
+            TokenType::Identifier => {
+                // names starting with an uppercase letter are constants to
+                // compare against rather than bindings, like `OK` or
+                // `Result.OK`
+                let constant = token.text.starts_with(|c: char| c.is_ascii_uppercase());
+                let ident = self.parse_identifier_expression(token)?;
+                let name = match ident.value {
+                    ExpressionKind::Identifier(name) => name,
+                    _ => unreachable!(),
+                };
+
+                if self.records.contains(&name)
+                    && self.peek()?.map(|t| t.typ) == Some(TokenType::LeftBrace)
+                {
+                    self.expect(TokenType::LeftBrace)?;
+                    let fields = self.parse_list(
+                        TokenType::RightBrace,
+                        TokenType::Comma,
+                        Self::parse_record_field_pattern,
+                        |_| Ok(()),
+                    )?;
+                    PatternKind::Record { name, fields }
+                } else if constant {
+                    // only a `.` binds tighter than a call
+                    let value = self.parse_infix_expression(ident, 17)?;
+                    self.parse_range_pattern(value)?
+                } else {
+                    PatternKind::Binding(name)
+                }
+            }
+            TokenType::Integer
+            | TokenType::Double
+            | TokenType::String
+            | TokenType::Atom
+            | TokenType::True
+            | TokenType::False
+            | TokenType::Null
+            | TokenType::Minus => {
+                let value = self.nud(token)?;
+                if let ExpressionKind::UnaryOperation(_, operand) = &value.value {
+                    match operand.value {
+                        ExpressionKind::Integer(_) | ExpressionKind::Double(_) => {}
+                        _ => {
+                            return error_at(
+                                operand.position,
+                                "Expected a number in pattern".to_string(),
+                            )
+                        }
+                    }
+                }
+                self.parse_range_pattern(value)?
+            }
+            TokenType::LeftBracket => self.parse_array_pattern()?,
+            TokenType::LeftBrace => PatternKind::Map(self.parse_list(
+                TokenType::RightBrace,
+                TokenType::Comma,
+                Self::parse_map_entry_pattern,
+                |_| Ok(()),
+            )?),
+            _ => {
+                return error_at(
+                    position,
+                    format!("Unexpected token {:?} in pattern", token.typ),
+                )
+            }
+        };
+
+        Ok(Pattern { position, value })
+    }
+
+    // a value pattern, or a range pattern if the value is followed by `..` or
+    // `..=`
+    fn parse_range_pattern(&mut self, start: Expression) -> ParseResult<PatternKind> {
+        let inclusive = if self.matches(TokenType::DotDot)? {
+            false
+        } else if self.matches(TokenType::DotDotEqual)? {
+            true
+        } else {
+            return Ok(PatternKind::Value(start));
+        };
+
+        match self.parse_pattern()? {
+            Pattern {
+                value: PatternKind::Value(end),
+                ..
+            } => Ok(PatternKind::Range {
+                start,
+                end,
+                inclusive,
+            }),
+            Pattern { position, .. } => {
+                error_at(position, "Expected the end of the range".to_string())
+            }
+        }
+    }
+
+    // the elements, and whether each one is a `...rest`
+    fn parse_array_element_pattern(&mut self) -> ParseResult<(Pattern, bool)> {
+        if self.matches(TokenType::DotDotDot)? {
+            let rest = self.parse_pattern()?;
+            match rest.value {
+                PatternKind::Binding(_) | PatternKind::Wildcard => Ok((rest, true)),
+                _ => error_at(rest.position, "Expected identifier".to_string()),
+            }
+        } else {
+            Ok((self.parse_pattern()?, false))
+        }
+    }
+
+    fn parse_array_pattern(&mut self) -> ParseResult<PatternKind> {
+        let mut elements = self.parse_list(
+            TokenType::RightBracket,
+            TokenType::Comma,
+            Self::parse_array_element_pattern,
+            |_| Ok(()),
+        )?;
+
+        if let Some((rest, _)) = elements.iter().rev().skip(1).find(|(_, rest)| *rest) {
+            return error_at(
+                rest.position,
+                "Rest pattern must be the last element".to_string(),
+            );
+        }
+
+        let rest = match elements.last() {
+            Some((_, true)) => elements.pop().map(|(rest, _)| Box::new(rest)),
+            _ => None,
+        };
+
+        Ok(PatternKind::Array(
+            elements.into_iter().map(|(element, _)| element).collect(),
+            rest,
+        ))
+    }
+
+    // A key that's a plain identifier is the name as a string, like in map
+    // literals, and `{ a }` is short for `{ a: a }`.
+    fn parse_map_entry_pattern(&mut self) -> ParseResult<(Expression, Pattern)> {
+        let token = self
+            .next_token()?
+            .ok_or_else(|| VmError::ParseError("Unexpected end of input".into()))?;
+        let position = token.position;
+
+        let key = match token.typ {
+            TokenType::Identifier => {
+                let ident = self.parse_identifier_expression(token)?;
+                let name = match ident.value {
+                    ExpressionKind::Identifier(name) => name,
+                    _ => unreachable!(),
+                };
+
+                let key = Expression {
+                    position,
+                    value: ExpressionKind::String(name),
+                };
+                if !self.matches(TokenType::Colon)? {
+                    let binding = Pattern {
+                        position,
+                        value: PatternKind::Binding(name),
+                    };
+                    return Ok((key, binding));
+                }
+                key
+            }
+            TokenType::Integer
+            | TokenType::String
+            | TokenType::Atom
+            | TokenType::True
+            | TokenType::False
+            | TokenType::Null => {
+                let key = self.nud(token)?;
+                self.expect(TokenType::Colon)?;
+                key
+            }
+            _ => {
+                return error_at(
+                    position,
+                    format!("Unexpected token {:?} in pattern", token.typ),
+                )
+            }
+        };
+
+        Ok((key, self.parse_pattern()?))
+    }
+
+    // `field = pattern`, or just `field` to bind it to a variable of the same
+    // name
+    fn parse_record_field_pattern(&mut self) -> ParseResult<(Expression, Pattern)> {
+        let ident = self.expect(TokenType::Identifier)?;
+        let field = self.parse_identifier_expression(ident)?;
+
+        let pattern = if self.matches(TokenType::Equal)? {
+            self.parse_pattern()?
+        } else if let ExpressionKind::Identifier(name) = field.value {
+            Pattern {
+                position: field.position,
+                value: PatternKind::Binding(name),
+            }
+        } else {
+            unreachable!();
+        };
+
+        Ok((field, pattern))
+    }
 }
 
 impl<'a> Iterator for Parser<'a> {
@@ -1113,11 +2036,12 @@ impl<'a> Iterator for Parser<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::ErrorInfo;
     use pretty_assertions::assert_eq;
 
     #[test]
     fn test_lexer_symbols() {
-        let input = "[ ] { } ( ) + - * ** / & && | || ^ % ; < > <= >= = == ! != ,";
+        let input = "[ ] { } ( ) + - * ** / & && | || ^ % ; < > <= >= = == ! != , =>";
         let lexer = Lexer::new("test", input);
 
         assert_eq!(
@@ -1150,6 +2074,32 @@ mod tests {
                 Token::new(TokenType::Bang, 1, 55, "!"),
                 Token::new(TokenType::BangEqual, 1, 57, "!="),
                 Token::new(TokenType::Comma, 1, 60, ","),
+                Token::new(TokenType::FatArrow, 1, 62, "=>"),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_lexer_compound_assignment_symbols() {
+        let input = "+= -= *= **= /= %= &= |= ^= <<= >>= << >>";
+        let lexer = Lexer::new("test", input);
+
+        assert_eq!(
+            lexer.filter_map(|a| a.ok()).collect::<Vec<_>>(),
+            vec![
+                Token::new(TokenType::PlusEqual, 1, 1, "+="),
+                Token::new(TokenType::MinusEqual, 1, 4, "-="),
+                Token::new(TokenType::StarEqual, 1, 7, "*="),
+                Token::new(TokenType::StarStarEqual, 1, 10, "**="),
+                Token::new(TokenType::SlashEqual, 1, 14, "/="),
+                Token::new(TokenType::PercentEqual, 1, 17, "%="),
+                Token::new(TokenType::AndEqual, 1, 20, "&="),
+                Token::new(TokenType::PipeEqual, 1, 23, "|="),
+                Token::new(TokenType::CaretEqual, 1, 26, "^="),
+                Token::new(TokenType::LessLessEqual, 1, 29, "<<="),
+                Token::new(TokenType::GreaterGreaterEqual, 1, 33, ">>="),
+                Token::new(TokenType::LessLess, 1, 37, "<<"),
+                Token::new(TokenType::GreaterGreater, 1, 40, ">>"),
             ],
         );
     }
@@ -1188,6 +2138,7 @@ while
 for
 let
 function
+match
 ";
         let lexer = Lexer::new("test", input);
 
@@ -1203,6 +2154,7 @@ function
                 Token::new(TokenType::For, 8, 1, "for"),
                 Token::new(TokenType::Let, 9, 1, "let"),
                 Token::new(TokenType::Function, 10, 1, "function"),
+                Token::new(TokenType::Match, 11, 1, "match"),
             ],
         );
     }
@@ -1218,6 +2170,95 @@ function
         );
     }
 
+    #[test]
+    fn test_unicode_string() {
+        let input = r#""héllo" 1"#;
+        let lexer = Lexer::new("test", input);
+
+        assert_eq!(
+            lexer.filter_map(|a| a.ok()).collect::<Vec<_>>(),
+            vec![
+                Token::new(TokenType::String, 1, 1, "héllo"),
+                Token::new(TokenType::Integer, 1, 9, "1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let input = r#""a${b}c${ {} }" "\${d}""#;
+        let lexer = Lexer::new("test", input);
+
+        assert_eq!(
+            lexer.filter_map(|a| a.ok()).collect::<Vec<_>>(),
+            vec![
+                Token::new(TokenType::InterpolationStart, 1, 1, "a"),
+                Token::new(TokenType::Identifier, 1, 5, "b"),
+                Token::new(TokenType::InterpolationMiddle, 1, 6, "c"),
+                Token::new(TokenType::LeftBrace, 1, 11, "{"),
+                Token::new(TokenType::RightBrace, 1, 12, "}"),
+                Token::new(TokenType::InterpolationEnd, 1, 14, ""),
+                Token::new(TokenType::String, 1, 17, "${d}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_atom() {
+        let input = ":hello, :_a1";
+        let lexer = Lexer::new("test", input);
+
+        assert_eq!(
+            lexer.filter_map(|a| a.ok()).collect::<Vec<_>>(),
+            vec![
+                Token::new(TokenType::Atom, 1, 1, "hello"),
+                Token::new(TokenType::Comma, 1, 7, ","),
+                Token::new(TokenType::Atom, 1, 9, "_a1"),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_colon() {
+        let input = ": a:b \"c\":d (:e)";
+        let lexer = Lexer::new("test", input);
+
+        assert_eq!(
+            lexer.filter_map(|a| a.ok()).collect::<Vec<_>>(),
+            vec![
+                Token::new(TokenType::Colon, 1, 1, ":"),
+                Token::new(TokenType::Identifier, 1, 3, "a"),
+                Token::new(TokenType::Colon, 1, 4, ":"),
+                Token::new(TokenType::Identifier, 1, 5, "b"),
+                Token::new(TokenType::String, 1, 7, "c"),
+                Token::new(TokenType::Colon, 1, 10, ":"),
+                Token::new(TokenType::Identifier, 1, 11, "d"),
+                Token::new(TokenType::LeftParen, 1, 13, "("),
+                Token::new(TokenType::Atom, 1, 14, "e"),
+                Token::new(TokenType::RightParen, 1, 16, ")"),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_ranges() {
+        let input = "1..2 a..=b ...";
+        let lexer = Lexer::new("test", input);
+
+        assert_eq!(
+            lexer.filter_map(|a| a.ok()).collect::<Vec<_>>(),
+            vec![
+                Token::new(TokenType::Integer, 1, 1, "1"),
+                Token::new(TokenType::DotDot, 1, 2, ".."),
+                Token::new(TokenType::Integer, 1, 4, "2"),
+                Token::new(TokenType::Identifier, 1, 6, "a"),
+                Token::new(TokenType::DotDotEqual, 1, 7, "..="),
+                Token::new(TokenType::Identifier, 1, 10, "b"),
+                Token::new(TokenType::DotDotDot, 1, 12, "..."),
+            ],
+        );
+    }
+
     #[test]
     fn test_string_escapes() {
         let input = r#""so I says, \"this\nis\tan escaped string\"""#;
@@ -1460,9 +2501,11 @@ function test(
 
         assert_eq!(
             parser.collect::<Vec<_>>(),
-            vec![Err(
-                "Error in test: Expected Semicolon, found end of input".to_string()
-            ),],
+            vec![Err(VmError::ParseError(ErrorInfo {
+                message: "Expected Semicolon, found end of input".to_string(),
+                position: None,
+                module: Some("test".to_string()),
+            }))],
         );
     }
 
@@ -1475,7 +2518,11 @@ function test(
 
         assert_eq!(
             parser.next().unwrap(),
-            Err("Error in test: Expected Identifier, got While at 1:5".to_string()),
+            Err(VmError::ParseError(ErrorInfo {
+                message: "Expected Identifier, got While".to_string(),
+                position: Some(Position { line: 1, column: 5 }),
+                module: Some("test".to_string()),
+            })),
         );
     }
 
@@ -1827,44 +2874,15 @@ for let a; a; a {}
     }
 
     #[test]
-    fn test_continue_statement() {
-        let mut agent = Agent::new();
-        let input = "continue;";
-        let lexer = Lexer::new("test", input);
-        let parser = Parser::new("test", &mut agent, lexer);
-
-        assert_eq!(
-            parser.collect::<Vec<_>>(),
-            vec![Ok(Statement {
-                position: Position { line: 1, column: 1 },
-                value: StatementKind::Continue,
-            }),],
-        );
-    }
-
-    #[test]
-    fn test_break_statement() {
-        let mut agent = Agent::new();
-        let input = "break;";
-        let lexer = Lexer::new("test", input);
-        let parser = Parser::new("test", &mut agent, lexer);
-
-        assert_eq!(
-            parser.collect::<Vec<_>>(),
-            vec![Ok(Statement {
-                position: Position { line: 1, column: 1 },
-                value: StatementKind::Break,
-            }),],
-        );
-    }
-
-    #[test]
-    fn test_return_statement() {
+    fn test_for_in_statement() {
         let mut agent = Agent::new();
         let input = "
-return;
-return 1;
+for a in b {}
+for a, b in a {}
+for a; a; {}
 ";
+        let ident_a = agent.intern_string("a");
+        let ident_b = agent.intern_string("b");
         let lexer = Lexer::new("test", input);
         let parser = Parser::new("test", &mut agent, lexer);
 
@@ -1873,41 +2891,471 @@ return 1;
             vec![
                 Ok(Statement {
                     position: Position { line: 2, column: 1 },
-                    value: StatementKind::Return(None),
+                    value: StatementKind::ForIn {
+                        key: None,
+                        value: Expression {
+                            position: Position { line: 2, column: 5 },
+                            value: ExpressionKind::Identifier(ident_a),
+                        },
+                        iterable: Expression {
+                            position: Position {
+                                line: 2,
+                                column: 10
+                            },
+                            value: ExpressionKind::Identifier(ident_b),
+                        },
+                        body: Vec::new(),
+                    },
                 }),
                 Ok(Statement {
                     position: Position { line: 3, column: 1 },
-                    value: StatementKind::Return(Some(Expression {
-                        position: Position { line: 3, column: 8 },
-                        value: ExpressionKind::Integer(1),
-                    })),
+                    value: StatementKind::ForIn {
+                        key: Some(Expression {
+                            position: Position { line: 3, column: 5 },
+                            value: ExpressionKind::Identifier(ident_a),
+                        }),
+                        value: Expression {
+                            position: Position { line: 3, column: 8 },
+                            value: ExpressionKind::Identifier(ident_b),
+                        },
+                        iterable: Expression {
+                            position: Position {
+                                line: 3,
+                                column: 13
+                            },
+                            value: ExpressionKind::Identifier(ident_a),
+                        },
+                        body: Vec::new(),
+                    },
+                }),
+                Ok(Statement {
+                    position: Position { line: 4, column: 1 },
+                    value: StatementKind::For {
+                        initializer: Some(Box::new(Statement {
+                            position: Position { line: 4, column: 5 },
+                            value: StatementKind::Expression(Expression {
+                                position: Position { line: 4, column: 5 },
+                                value: ExpressionKind::Identifier(ident_a),
+                            }),
+                        })),
+                        predicate: Some(Expression {
+                            position: Position { line: 4, column: 8 },
+                            value: ExpressionKind::Identifier(ident_a),
+                        }),
+                        increment: None,
+                        body: Vec::new(),
+                    },
                 }),
             ],
         );
     }
 
     #[test]
-    fn test_export_statement() {
+    fn test_match_statement() {
         let mut agent = Agent::new();
+        let input = "match a { [1, b, ...c] if b => {} _ => d, }";
         let ident_a = agent.intern_string("a");
-        let input = "module Test; export let a = 0; export function a() {} export 123;";
+        let ident_b = agent.intern_string("b");
+        let ident_c = agent.intern_string("c");
+        let ident_d = agent.intern_string("d");
         let lexer = Lexer::new("test", input);
         let parser = Parser::new("test", &mut agent, lexer);
 
         assert_eq!(
             parser.collect::<Vec<_>>(),
-            vec![
-                Ok(Statement {
-                    position: Position {
-                        line: 1,
-                        column: 14
+            vec![Ok(Statement {
+                position: Position { line: 1, column: 1 },
+                value: StatementKind::Match {
+                    value: Expression {
+                        position: Position { line: 1, column: 7 },
+                        value: ExpressionKind::Identifier(ident_a),
                     },
-                    value: StatementKind::Export(Box::new(Statement {
-                        position: Position {
-                            line: 1,
-                            column: 21
-                        },
-                        value: StatementKind::Let {
+                    arms: vec![
+                        MatchArm {
+                            pattern: Pattern {
+                                position: Position {
+                                    line: 1,
+                                    column: 11
+                                },
+                                value: PatternKind::Array(
+                                    vec![
+                                        Pattern {
+                                            position: Position {
+                                                line: 1,
+                                                column: 12
+                                            },
+                                            value: PatternKind::Value(Expression {
+                                                position: Position {
+                                                    line: 1,
+                                                    column: 12
+                                                },
+                                                value: ExpressionKind::Integer(1),
+                                            }),
+                                        },
+                                        Pattern {
+                                            position: Position {
+                                                line: 1,
+                                                column: 15
+                                            },
+                                            value: PatternKind::Binding(ident_b),
+                                        },
+                                    ],
+                                    Some(Box::new(Pattern {
+                                        position: Position {
+                                            line: 1,
+                                            column: 21
+                                        },
+                                        value: PatternKind::Binding(ident_c),
+                                    })),
+                                ),
+                            },
+                            guard: Some(Expression {
+                                position: Position {
+                                    line: 1,
+                                    column: 27
+                                },
+                                value: ExpressionKind::Identifier(ident_b),
+                            }),
+                            body: Vec::new(),
+                        },
+                        MatchArm {
+                            pattern: Pattern {
+                                position: Position {
+                                    line: 1,
+                                    column: 35
+                                },
+                                value: PatternKind::Wildcard,
+                            },
+                            guard: None,
+                            body: vec![Statement {
+                                position: Position {
+                                    line: 1,
+                                    column: 40
+                                },
+                                value: StatementKind::Expression(Expression {
+                                    position: Position {
+                                        line: 1,
+                                        column: 40
+                                    },
+                                    value: ExpressionKind::Identifier(ident_d),
+                                }),
+                            }],
+                        },
+                    ],
+                },
+            })],
+        );
+    }
+
+    #[test]
+    fn test_match_statement_semicolon() {
+        let mut agent = Agent::new();
+        let input = "match a { _ => b }; c;";
+        let lexer = Lexer::new("test", input);
+        let parser = Parser::new("test", &mut agent, lexer);
+
+        let statements = parser.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(statements.len(), 2);
+        assert!(matches!(statements[0].value, StatementKind::Match { .. }));
+    }
+
+    #[test]
+    fn test_match_patterns() {
+        let mut agent = Agent::new();
+        let input = "record P { x }
+let a = match b { -1..=9 => 1, { k: c, n } => 2, P { x = Q.R } => 3 };";
+        let ident_a = agent.intern_string("a");
+        let ident_b = agent.intern_string("b");
+        let ident_c = agent.intern_string("c");
+        let ident_k = agent.intern_string("k");
+        let ident_n = agent.intern_string("n");
+        let ident_p = agent.intern_string("P");
+        let ident_q = agent.intern_string("Q");
+        let ident_r = agent.intern_string("R");
+        let ident_x = agent.intern_string("x");
+        let lexer = Lexer::new("test", input);
+        let parser = Parser::new("test", &mut agent, lexer);
+
+        let position = |column| Position { line: 2, column };
+        let expression = |column, value| Expression {
+            position: position(column),
+            value,
+        };
+        let pattern = |column, value| Pattern {
+            position: position(column),
+            value,
+        };
+        let arm = |pattern, column, value| MatchArm {
+            pattern,
+            guard: None,
+            body: expression(column, ExpressionKind::Integer(value)),
+        };
+
+        let statements = parser.collect::<Vec<_>>();
+        assert_eq!(
+            statements[1],
+            Ok(Statement {
+                position: position(1),
+                value: StatementKind::Let {
+                    name: expression(5, ExpressionKind::Identifier(ident_a)),
+                    value: Some(expression(
+                        9,
+                        ExpressionKind::Match(
+                            Box::new(expression(15, ExpressionKind::Identifier(ident_b))),
+                            vec![
+                                arm(
+                                    pattern(
+                                        19,
+                                        PatternKind::Range {
+                                            start: expression(
+                                                19,
+                                                ExpressionKind::UnaryOperation(
+                                                    TokenType::Minus,
+                                                    Box::new(expression(
+                                                        20,
+                                                        ExpressionKind::Integer(1)
+                                                    )),
+                                                ),
+                                            ),
+                                            end: expression(24, ExpressionKind::Integer(9)),
+                                            inclusive: true,
+                                        },
+                                    ),
+                                    29,
+                                    1,
+                                ),
+                                arm(
+                                    pattern(
+                                        32,
+                                        PatternKind::Map(vec![
+                                            (
+                                                expression(34, ExpressionKind::String(ident_k)),
+                                                pattern(37, PatternKind::Binding(ident_c)),
+                                            ),
+                                            (
+                                                expression(40, ExpressionKind::String(ident_n)),
+                                                pattern(40, PatternKind::Binding(ident_n)),
+                                            ),
+                                        ]),
+                                    ),
+                                    47,
+                                    2,
+                                ),
+                                arm(
+                                    pattern(
+                                        50,
+                                        PatternKind::Record {
+                                            name: ident_p,
+                                            fields: vec![(
+                                                expression(54, ExpressionKind::Identifier(ident_x)),
+                                                pattern(
+                                                    58,
+                                                    PatternKind::Value(expression(
+                                                        58,
+                                                        ExpressionKind::BinaryOperation(
+                                                            Box::new(expression(
+                                                                58,
+                                                                ExpressionKind::Identifier(ident_q)
+                                                            )),
+                                                            TokenType::Dot,
+                                                            Box::new(expression(
+                                                                60,
+                                                                ExpressionKind::Identifier(ident_r)
+                                                            )),
+                                                        ),
+                                                    )),
+                                                ),
+                                            )],
+                                        },
+                                    ),
+                                    67,
+                                    3,
+                                ),
+                            ],
+                        ),
+                    )),
+                },
+            }),
+        );
+    }
+
+    #[test]
+    fn test_match_pattern_errors() {
+        let cases = [
+            (
+                "match a { [...b, c] => 1 }",
+                "Rest pattern must be the last element",
+                15,
+            ),
+            ("match a { [...[b]] => 1 }", "Expected identifier", 15),
+            (
+                "match a { 1.. => 1 }",
+                "Unexpected token FatArrow in pattern",
+                15,
+            ),
+            ("match a { 1..b => 1 }", "Expected the end of the range", 14),
+            ("match a { -b => 1 }", "Expected a number in pattern", 12),
+        ];
+
+        for (input, message, column) in cases.iter() {
+            let mut agent = Agent::new();
+            let lexer = Lexer::new("test", input);
+            let mut parser = Parser::new("test", &mut agent, lexer);
+
+            assert_eq!(
+                parser.next(),
+                Some(Err(VmError::ParseError(ErrorInfo {
+                    message: message.to_string(),
+                    position: Some(Position {
+                        line: 1,
+                        column: *column
+                    }),
+                    module: Some("test".to_string()),
+                })))
+            );
+        }
+    }
+
+    #[test]
+    fn test_continue_statement() {
+        let mut agent = Agent::new();
+        let input = "continue;";
+        let lexer = Lexer::new("test", input);
+        let parser = Parser::new("test", &mut agent, lexer);
+
+        assert_eq!(
+            parser.collect::<Vec<_>>(),
+            vec![Ok(Statement {
+                position: Position { line: 1, column: 1 },
+                value: StatementKind::Continue,
+            }),],
+        );
+    }
+
+    #[test]
+    fn test_break_statement() {
+        let mut agent = Agent::new();
+        let input = "break;";
+        let lexer = Lexer::new("test", input);
+        let parser = Parser::new("test", &mut agent, lexer);
+
+        assert_eq!(
+            parser.collect::<Vec<_>>(),
+            vec![Ok(Statement {
+                position: Position { line: 1, column: 1 },
+                value: StatementKind::Break,
+            }),],
+        );
+    }
+
+    #[test]
+    fn test_return_statement() {
+        let mut agent = Agent::new();
+        let input = "
+return;
+return 1;
+";
+        let lexer = Lexer::new("test", input);
+        let parser = Parser::new("test", &mut agent, lexer);
+
+        assert_eq!(
+            parser.collect::<Vec<_>>(),
+            vec![
+                Ok(Statement {
+                    position: Position { line: 2, column: 1 },
+                    value: StatementKind::Return(None),
+                }),
+                Ok(Statement {
+                    position: Position { line: 3, column: 1 },
+                    value: StatementKind::Return(Some(Expression {
+                        position: Position { line: 3, column: 8 },
+                        value: ExpressionKind::Integer(1),
+                    })),
+                }),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_try_statement() {
+        let mut agent = Agent::new();
+        let ident_e = agent.intern_string("e");
+        let input = "try { throw 1; } catch (e) { e; }";
+        let lexer = Lexer::new("test", input);
+        let parser = Parser::new("test", &mut agent, lexer);
+
+        assert_eq!(
+            parser.collect::<Vec<_>>(),
+            vec![Ok(Statement {
+                position: Position { line: 1, column: 1 },
+                value: StatementKind::Try {
+                    body: vec![Statement {
+                        position: Position { line: 1, column: 7 },
+                        value: StatementKind::Throw(Expression {
+                            position: Position {
+                                line: 1,
+                                column: 13
+                            },
+                            value: ExpressionKind::Integer(1),
+                        }),
+                    }],
+                    binding: Expression {
+                        position: Position {
+                            line: 1,
+                            column: 25
+                        },
+                        value: ExpressionKind::Identifier(ident_e),
+                    },
+                    handler: vec![Statement {
+                        position: Position {
+                            line: 1,
+                            column: 30
+                        },
+                        value: StatementKind::Expression(Expression {
+                            position: Position {
+                                line: 1,
+                                column: 30
+                            },
+                            value: ExpressionKind::Identifier(ident_e),
+                        }),
+                    }],
+                },
+            })],
+        );
+    }
+
+    #[test]
+    fn test_try_without_catch() {
+        let mut agent = Agent::new();
+        let input = "try {}";
+        let lexer = Lexer::new("test", input);
+        let mut parser = Parser::new("test", &mut agent, lexer);
+
+        assert!(parser.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_export_statement() {
+        let mut agent = Agent::new();
+        let ident_a = agent.intern_string("a");
+        let input = "module Test; export let a = 0; export function a() {} export 123;";
+        let lexer = Lexer::new("test", input);
+        let parser = Parser::new("test", &mut agent, lexer);
+
+        assert_eq!(
+            parser.collect::<Vec<_>>(),
+            vec![
+                Ok(Statement {
+                    position: Position {
+                        line: 1,
+                        column: 14
+                    },
+                    value: StatementKind::Export(Box::new(Statement {
+                        position: Position {
+                            line: 1,
+                            column: 21
+                        },
+                        value: StatementKind::Let {
                             name: Expression {
                                 position: Position {
                                     line: 1,
@@ -1948,11 +3396,120 @@ return 1;
                         },
                     })),
                 }),
-                Err("Error in test: Can only export declarations".to_string()),
+                Err(VmError::ParseError(ErrorInfo {
+                    message: "Can only export declarations".to_string(),
+                    position: Some(Position {
+                        line: 1,
+                        column: 55
+                    }),
+                    module: Some("test".to_string()),
+                })),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_record_declaration() {
+        let mut agent = Agent::new();
+        let ident_point = agent.intern_string("Point");
+        let ident_x = agent.intern_string("x");
+        let ident_y = agent.intern_string("y");
+        let input = "record Point { x, y } Point { y = 1 };";
+        let lexer = Lexer::new("test", input);
+        let parser = Parser::new("test", &mut agent, lexer);
+
+        assert_eq!(
+            parser.collect::<Vec<_>>(),
+            vec![
+                Ok(Statement {
+                    position: Position { line: 1, column: 1 },
+                    value: StatementKind::Record {
+                        name: Expression {
+                            position: Position { line: 1, column: 8 },
+                            value: ExpressionKind::Identifier(ident_point),
+                        },
+                        fields: vec![
+                            Expression {
+                                position: Position {
+                                    line: 1,
+                                    column: 16
+                                },
+                                value: ExpressionKind::Identifier(ident_x),
+                            },
+                            Expression {
+                                position: Position {
+                                    line: 1,
+                                    column: 19
+                                },
+                                value: ExpressionKind::Identifier(ident_y),
+                            },
+                        ],
+                    },
+                }),
+                Ok(Statement {
+                    position: Position {
+                        line: 1,
+                        column: 23
+                    },
+                    value: StatementKind::Expression(Expression {
+                        position: Position {
+                            line: 1,
+                            column: 23
+                        },
+                        value: ExpressionKind::Record {
+                            name: ident_point,
+                            fields: vec![(
+                                Expression {
+                                    position: Position {
+                                        line: 1,
+                                        column: 31
+                                    },
+                                    value: ExpressionKind::Identifier(ident_y),
+                                },
+                                Expression {
+                                    position: Position {
+                                        line: 1,
+                                        column: 35
+                                    },
+                                    value: ExpressionKind::Integer(1),
+                                },
+                            )],
+                        },
+                    }),
+                }),
             ]
         );
     }
 
+    #[test]
+    fn test_parse_without_module_header() {
+        let mut agent = Agent::new();
+        let input = "let a = 1;";
+        let lexer = Lexer::new("test", input);
+        let mut parser = Parser::new("test", &mut agent, lexer);
+        let parsed = parser.parse().unwrap();
+
+        assert!(parsed.spec.is_none());
+        assert_eq!(parsed.statements.len(), 1);
+    }
+
+    #[test]
+    fn test_export_without_module_header() {
+        let mut agent = Agent::new();
+        let input = "export let a = 1;";
+        let lexer = Lexer::new("test", input);
+        let parser = Parser::new("test", &mut agent, lexer);
+
+        assert_eq!(
+            parser.collect::<Vec<_>>(),
+            vec![Err(VmError::ParseError(ErrorInfo {
+                message: "Can only export from a file with a module header".to_string(),
+                position: Some(Position { line: 1, column: 1 }),
+                module: Some("test".to_string()),
+            }))]
+        );
+    }
+
     #[test]
     fn test_import_statement() {
         let mut agent = Agent::new();
@@ -1978,9 +3535,11 @@ return 1;
 
         assert_eq!(
             parser.collect::<Vec<_>>(),
-            vec![Err(
-                "Error in test: Import filename must be a string literal".to_string()
-            )]
+            vec![Err(VmError::ParseError(ErrorInfo {
+                message: "Import filename must be a string literal".to_string(),
+                position: Some(Position { line: 1, column: 8 }),
+                module: Some("test".to_string()),
+            }))]
         );
     }
 
@@ -2051,6 +3610,13 @@ return 1;
         test_expression!("\"hello world\";", ExpressionKind::String(s), agent);
     }
 
+    #[test]
+    fn test_atom_expression() {
+        let mut agent = Agent::new();
+        let hello = agent.intern_string("hello");
+        test_expression!(":hello;", ExpressionKind::Atom(hello), agent);
+    }
+
     #[test]
     fn test_null_expression() {
         test_expression!("null;", ExpressionKind::Null);
@@ -2082,6 +3648,87 @@ return 1;
         );
     }
 
+    #[test]
+    fn test_map_expression() {
+        let mut agent = Agent::new();
+        let a = agent.intern_string("a");
+        let b = agent.intern_string("b");
+        test_expression!(
+            r#"({ a: 1, "b": b, });"#,
+            ExpressionKind::Map(vec![
+                (
+                    Expression {
+                        position: Position { line: 1, column: 4 },
+                        value: ExpressionKind::String(a),
+                    },
+                    Expression {
+                        position: Position { line: 1, column: 7 },
+                        value: ExpressionKind::Integer(1),
+                    },
+                ),
+                (
+                    Expression {
+                        position: Position {
+                            line: 1,
+                            column: 10
+                        },
+                        value: ExpressionKind::String(b),
+                    },
+                    Expression {
+                        position: Position {
+                            line: 1,
+                            column: 15
+                        },
+                        value: ExpressionKind::Identifier(b),
+                    },
+                ),
+            ]),
+            agent
+        );
+    }
+
+    #[test]
+    fn test_map_expression_atom_keys() {
+        let mut agent = Agent::new();
+        let a = agent.intern_string("a");
+        let y = agent.intern_string("y");
+        test_expression!(
+            "({:a: y});",
+            ExpressionKind::Map(vec![(
+                Expression {
+                    position: Position { line: 1, column: 3 },
+                    value: ExpressionKind::Atom(a),
+                },
+                Expression {
+                    position: Position { line: 1, column: 7 },
+                    value: ExpressionKind::Identifier(y),
+                },
+            )]),
+            agent
+        );
+    }
+
+    #[test]
+    fn test_interpolation_expression() {
+        let mut agent = Agent::new();
+        let a = agent.intern_string("a ");
+        let b = agent.intern_string("b");
+        test_expression!(
+            r#""a ${b}";"#,
+            ExpressionKind::Interpolation(vec![
+                Expression {
+                    position: Position { line: 1, column: 1 },
+                    value: ExpressionKind::String(a),
+                },
+                Expression {
+                    position: Position { line: 1, column: 6 },
+                    value: ExpressionKind::Identifier(b),
+                },
+            ]),
+            agent
+        );
+    }
+
     #[test]
     fn test_unary_minus() {
         test_expression!(
@@ -2164,6 +3811,47 @@ return 1;
         );
     }
 
+    #[test]
+    fn test_range_precedence() {
+        test_expression!(
+            "1 in 2..3 + 4;",
+            ExpressionKind::BinaryOperation(
+                Box::new(Expression {
+                    position: Position { line: 1, column: 1 },
+                    value: ExpressionKind::Integer(1),
+                }),
+                TokenType::In,
+                Box::new(Expression {
+                    position: Position { line: 1, column: 6 },
+                    value: ExpressionKind::BinaryOperation(
+                        Box::new(Expression {
+                            position: Position { line: 1, column: 6 },
+                            value: ExpressionKind::Integer(2),
+                        }),
+                        TokenType::DotDot,
+                        Box::new(Expression {
+                            position: Position { line: 1, column: 9 },
+                            value: ExpressionKind::BinaryOperation(
+                                Box::new(Expression {
+                                    position: Position { line: 1, column: 9 },
+                                    value: ExpressionKind::Integer(3),
+                                }),
+                                TokenType::Plus,
+                                Box::new(Expression {
+                                    position: Position {
+                                        line: 1,
+                                        column: 13
+                                    },
+                                    value: ExpressionKind::Integer(4),
+                                }),
+                            ),
+                        }),
+                    ),
+                }),
+            ),
+        );
+    }
+
     #[test]
     fn test_binary_left_assoc() {
         let mut agent = Agent::new();
@@ -2276,6 +3964,36 @@ return 1;
         );
     }
 
+    #[test]
+    fn test_spread_call_expression() {
+        let mut agent = Agent::new();
+        let ident_f = agent.intern_string("f");
+        let ident_xs = agent.intern_string("xs");
+        test_expression!(
+            "f(1, ...xs);",
+            ExpressionKind::Call(
+                Box::new(Expression {
+                    position: Position { line: 1, column: 1 },
+                    value: ExpressionKind::Identifier(ident_f),
+                }),
+                vec![
+                    Expression {
+                        position: Position { line: 1, column: 3 },
+                        value: ExpressionKind::Integer(1),
+                    },
+                    Expression {
+                        position: Position { line: 1, column: 6 },
+                        value: ExpressionKind::Spread(Box::new(Expression {
+                            position: Position { line: 1, column: 9 },
+                            value: ExpressionKind::Identifier(ident_xs),
+                        })),
+                    },
+                ],
+            ),
+            agent,
+        );
+    }
+
     #[test]
     fn test_index_expression() {
         let mut agent = Agent::new();
@@ -2379,6 +4097,57 @@ return 1;
         );
     }
 
+    #[test]
+    fn test_compound_assignment() {
+        let mut agent = Agent::new();
+        let a = agent.intern_string("a");
+        let b = agent.intern_string("b");
+        test_expression!(
+            "a += b -= 1 + 2;",
+            ExpressionKind::BinaryOperation(
+                Box::new(Expression {
+                    position: Position { line: 1, column: 1 },
+                    value: ExpressionKind::Identifier(a),
+                }),
+                TokenType::PlusEqual,
+                Box::new(Expression {
+                    position: Position { line: 1, column: 6 },
+                    value: ExpressionKind::BinaryOperation(
+                        Box::new(Expression {
+                            position: Position { line: 1, column: 6 },
+                            value: ExpressionKind::Identifier(b),
+                        }),
+                        TokenType::MinusEqual,
+                        Box::new(Expression {
+                            position: Position {
+                                line: 1,
+                                column: 11
+                            },
+                            value: ExpressionKind::BinaryOperation(
+                                Box::new(Expression {
+                                    position: Position {
+                                        line: 1,
+                                        column: 11
+                                    },
+                                    value: ExpressionKind::Integer(1),
+                                }),
+                                TokenType::Plus,
+                                Box::new(Expression {
+                                    position: Position {
+                                        line: 1,
+                                        column: 15
+                                    },
+                                    value: ExpressionKind::Integer(2),
+                                }),
+                            ),
+                        }),
+                    ),
+                }),
+            ),
+            agent
+        );
+    }
+
     #[test]
     fn test_mixed_associativity_and_precedence() {
         test_expression!(
@@ -2460,6 +4229,62 @@ return 1;
         );
     }
 
+    #[test]
+    fn test_variadic_function_expression() {
+        let mut agent = Agent::new();
+        let ident_a = agent.intern_string("a");
+        let ident_rest = agent.intern_string("rest");
+        test_expression!(
+            "(function(a, ...rest) {});",
+            ExpressionKind::Function {
+                parameters: vec![
+                    Expression {
+                        position: Position {
+                            line: 1,
+                            column: 11
+                        },
+                        value: ExpressionKind::Identifier(ident_a),
+                    },
+                    Expression {
+                        position: Position {
+                            line: 1,
+                            column: 14
+                        },
+                        value: ExpressionKind::Spread(Box::new(Expression {
+                            position: Position {
+                                line: 1,
+                                column: 17
+                            },
+                            value: ExpressionKind::Identifier(ident_rest),
+                        })),
+                    },
+                ],
+                body: Vec::new(),
+            },
+            agent,
+        );
+    }
+
+    #[test]
+    fn test_rest_parameter_not_last() {
+        let mut agent = Agent::new();
+        let input = "function f(...a, b) {}";
+        let lexer = Lexer::new("test", input);
+        let mut parser = Parser::new("test", &mut agent, lexer);
+
+        assert_eq!(
+            parser.next(),
+            Some(Err(VmError::ParseError(ErrorInfo {
+                message: "Rest parameter must be the last parameter".to_string(),
+                position: Some(Position {
+                    line: 1,
+                    column: 12
+                }),
+                module: Some("test".to_string()),
+            })))
+        );
+    }
+
     #[test]
     fn test_boolean_expression_true() {
         test_expression!("true;", ExpressionKind::Boolean(true),);
@@ -1,45 +1,165 @@
 use std::collections::HashMap;
 use std::iter::Iterator;
 
+use crate::compiler::artifact::{Reader, Writer};
 use crate::compiler::parser::Position;
+use crate::error::VmError;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct Context {
     pub(crate) position: Position,
+    // index into DebugInfo::files
+    pub(crate) file: usize,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+pub(crate) struct SourceFile {
+    pub(crate) name: String,
+    // not kept in compiled artifacts
+    pub(crate) text: Option<String>,
+}
+
+impl SourceFile {
+    pub(crate) fn line(&self, line: usize) -> Option<&str> {
+        self.text.as_ref()?.lines().nth(line.checked_sub(1)?)
+    }
+}
+
+#[derive(Debug, Clone)]
 enum InfoEntry {
     Context(Context),
     Forward(usize),
 }
 
-#[derive(Debug)]
-pub(crate) struct DebugInfo(HashMap<usize, InfoEntry>);
+#[derive(Debug, Clone)]
+pub(crate) struct DebugInfo {
+    entries: HashMap<usize, InfoEntry>,
+    files: Vec<SourceFile>,
+}
 
 impl DebugInfo {
     pub(crate) fn new() -> Self {
-        DebugInfo(HashMap::new())
+        DebugInfo {
+            entries: HashMap::new(),
+            files: Vec::new(),
+        }
     }
 
-    pub(crate) fn insert<T>(&mut self, mut positions: T, ctx: Context)
+    /// Registers a source file. Positions inserted from now on belong to it.
+    pub(crate) fn add_file(&mut self, name: String, text: String) -> usize {
+        self.files.push(SourceFile {
+            name,
+            text: Some(text),
+        });
+        self.files.len() - 1
+    }
+
+    pub(crate) fn current_file(&self) -> usize {
+        self.files.len().saturating_sub(1)
+    }
+
+    pub(crate) fn file(&self, idx: usize) -> Option<&SourceFile> {
+        self.files.get(idx)
+    }
+
+    // Offsets that already have an entry keep it, since they were inserted by
+    // a more deeply nested expression.
+    pub(crate) fn insert<T>(&mut self, positions: T, ctx: Context)
     where
         T: Iterator<Item = usize>,
     {
+        let mut positions = positions.filter(|i| !self.entries.contains_key(i));
+
         if let Some(original) = positions.next() {
-            self.0.insert(original, InfoEntry::Context(ctx));
+            let rest = positions.collect::<Vec<_>>();
+            self.entries.insert(original, InfoEntry::Context(ctx));
 
-            for i in positions {
-                self.0.entry(i).or_insert(InfoEntry::Forward(original));
+            for i in rest {
+                self.entries.insert(i, InfoEntry::Forward(original));
             }
         }
     }
 
+    /// Writes the file names and entries, sorted by offset so the same
+    /// program always produces the same artifact.
+    pub(crate) fn write(&self, writer: &mut Writer) {
+        writer.usize(self.files.len());
+        for file in &self.files {
+            writer.string(&file.name);
+        }
+
+        let mut entries = self.entries.iter().collect::<Vec<_>>();
+        entries.sort_by_key(|(offset, _)| **offset);
+
+        writer.usize(entries.len());
+        for (offset, entry) in entries {
+            writer.usize(*offset);
+            match entry {
+                InfoEntry::Context(ctx) => {
+                    writer.usize(0);
+                    writer.usize(ctx.position.line);
+                    writer.usize(ctx.position.column);
+                    writer.usize(ctx.file);
+                }
+                InfoEntry::Forward(original) => {
+                    writer.usize(1);
+                    writer.usize(*original);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn read(reader: &mut Reader) -> Result<DebugInfo, VmError> {
+        let mut debuginfo = DebugInfo::new();
+
+        for _ in 0..reader.usize()? {
+            debuginfo.files.push(SourceFile {
+                name: reader.string()?,
+                text: None,
+            });
+        }
+
+        for _ in 0..reader.usize()? {
+            let offset = reader.usize()?;
+            let entry = match reader.usize()? {
+                0 => InfoEntry::Context(Context {
+                    position: Position {
+                        line: reader.usize()?,
+                        column: reader.usize()?,
+                    },
+                    file: reader.index(debuginfo.files.len())?,
+                }),
+                1 => InfoEntry::Forward(reader.usize()?),
+                tag => {
+                    return Err(VmError::CompileError(
+                        format!("Invalid artifact: Unknown debug info entry {}", tag).into(),
+                    ))
+                }
+            };
+            debuginfo.entries.insert(offset, entry);
+        }
+
+        // get() follows forwards, so they have to end at a context
+        let dangling = debuginfo.entries.values().any(|entry| match entry {
+            InfoEntry::Forward(original) => {
+                !matches!(debuginfo.entries.get(original), Some(InfoEntry::Context(_)))
+            }
+            InfoEntry::Context(_) => false,
+        });
+        if dangling {
+            return Err(VmError::CompileError(
+                "Invalid artifact: Debug info forwards to a missing entry".into(),
+            ));
+        }
+
+        Ok(debuginfo)
+    }
+
     pub(crate) fn get(&self, offset: usize) -> Option<&Context> {
-        let mut entry = self.0.get(&offset);
+        let mut entry = self.entries.get(&offset);
 
         while let Some(InfoEntry::Forward(i)) = entry {
-            entry = self.0.get(i);
+            entry = self.entries.get(i);
         }
 
         entry.map(|x| match x {
@@ -48,3 +168,37 @@ impl DebugInfo {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_nested_entries_win() {
+        let mut debuginfo = DebugInfo::new();
+        let file = debuginfo.add_file("test".to_string(), "a + b".to_string());
+
+        debuginfo.insert(
+            1..3,
+            Context {
+                position: Position { line: 1, column: 5 },
+                file,
+            },
+        );
+        debuginfo.insert(
+            0..5,
+            Context {
+                position: Position { line: 1, column: 1 },
+                file,
+            },
+        );
+
+        let column = |offset| debuginfo.get(offset).map(|c| c.position.column);
+        assert_eq!(column(0), Some(1));
+        assert_eq!(column(2), Some(5));
+        assert_eq!(column(4), Some(1));
+        assert_eq!(column(5), None);
+        assert_eq!(debuginfo.file(file).unwrap().line(1), Some("a + b"));
+    }
+}
@@ -0,0 +1,226 @@
+use crate::compiler::parser::Position;
+use crate::value::Value;
+
+/// Details shared by every kind of error. `module` is the file name for
+/// errors found while compiling and the module name for runtime errors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorInfo {
+    pub message: String,
+    pub position: Option<Position>,
+    pub module: Option<String>,
+}
+
+impl ErrorInfo {
+    pub fn new<T>(message: T) -> ErrorInfo
+    where
+        T: Into<String>,
+    {
+        ErrorInfo {
+            message: message.into(),
+            position: None,
+            module: None,
+        }
+    }
+}
+
+impl From<String> for ErrorInfo {
+    fn from(message: String) -> ErrorInfo {
+        ErrorInfo::new(message)
+    }
+}
+
+impl From<&str> for ErrorInfo {
+    fn from(message: &str) -> ErrorInfo {
+        ErrorInfo::new(message)
+    }
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum VmError {
+    LexError(ErrorInfo),
+    ParseError(ErrorInfo),
+    CompileError(ErrorInfo),
+    // bytecode rejected by the verifier before running it
+    VerifyError(ErrorInfo),
+    TypeError(ErrorInfo),
+    ReferenceError(ErrorInfo),
+    IndexError(ErrorInfo),
+    ArityError(ErrorInfo),
+    NativeError(ErrorInfo),
+    // anything else that goes wrong while running, like an integer overflow
+    // or the stack underflowing
+    RuntimeError(ErrorInfo),
+    // the fuel budget ran out. Scripts can't catch it, and the run can be
+    // resumed once there's more fuel.
+    OutOfFuel(ErrorInfo),
+    // one of the interpreter's limits was hit, like the call depth
+    LimitError(ErrorInfo),
+    // a builtin was denied something the host didn't grant
+    PermissionError(ErrorInfo),
+    // a value thrown from a script with `throw`
+    Thrown(Value, ErrorInfo),
+}
+
+impl VmError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            VmError::LexError(_) => "LexError",
+            VmError::ParseError(_) => "ParseError",
+            VmError::CompileError(_) => "CompileError",
+            VmError::VerifyError(_) => "VerifyError",
+            VmError::TypeError(_) => "TypeError",
+            VmError::ReferenceError(_) => "ReferenceError",
+            VmError::IndexError(_) => "IndexError",
+            VmError::ArityError(_) => "ArityError",
+            VmError::NativeError(_) => "NativeError",
+            VmError::RuntimeError(_) => "RuntimeError",
+            VmError::OutOfFuel(_) => "OutOfFuel",
+            VmError::LimitError(_) => "LimitError",
+            VmError::PermissionError(_) => "PermissionError",
+            VmError::Thrown(..) => "Error",
+        }
+    }
+
+    pub fn info(&self) -> &ErrorInfo {
+        match self {
+            VmError::LexError(info)
+            | VmError::ParseError(info)
+            | VmError::CompileError(info)
+            | VmError::VerifyError(info)
+            | VmError::TypeError(info)
+            | VmError::ReferenceError(info)
+            | VmError::IndexError(info)
+            | VmError::ArityError(info)
+            | VmError::NativeError(info)
+            | VmError::RuntimeError(info)
+            | VmError::OutOfFuel(info)
+            | VmError::LimitError(info)
+            | VmError::PermissionError(info)
+            | VmError::Thrown(_, info) => info,
+        }
+    }
+
+    fn info_mut(&mut self) -> &mut ErrorInfo {
+        match self {
+            VmError::LexError(info)
+            | VmError::ParseError(info)
+            | VmError::CompileError(info)
+            | VmError::VerifyError(info)
+            | VmError::TypeError(info)
+            | VmError::ReferenceError(info)
+            | VmError::IndexError(info)
+            | VmError::ArityError(info)
+            | VmError::NativeError(info)
+            | VmError::RuntimeError(info)
+            | VmError::OutOfFuel(info)
+            | VmError::LimitError(info)
+            | VmError::PermissionError(info)
+            | VmError::Thrown(_, info) => info,
+        }
+    }
+
+    /// The name of the atom a caught error is tagged with, e.g.
+    /// `:type_error`.
+    pub fn atom_name(&self) -> &'static str {
+        match self {
+            VmError::LexError(_) => "lex_error",
+            VmError::ParseError(_) => "parse_error",
+            VmError::CompileError(_) => "compile_error",
+            VmError::VerifyError(_) => "verify_error",
+            VmError::TypeError(_) => "type_error",
+            VmError::ReferenceError(_) => "reference_error",
+            VmError::IndexError(_) => "index_error",
+            VmError::ArityError(_) => "arity_error",
+            VmError::NativeError(_) => "native_error",
+            VmError::RuntimeError(_) => "runtime_error",
+            VmError::OutOfFuel(_) => "out_of_fuel",
+            VmError::LimitError(_) => "limit_error",
+            VmError::PermissionError(_) => "permission_error",
+            VmError::Thrown(..) => "thrown",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.info().message
+    }
+
+    pub fn position(&self) -> Option<Position> {
+        self.info().position
+    }
+
+    pub fn module(&self) -> Option<&str> {
+        self.info().module.as_deref()
+    }
+
+    /// Sets the position unless the error already knows a more precise one.
+    pub fn with_position(mut self, position: Position) -> VmError {
+        let info = self.info_mut();
+        if info.position.is_none() {
+            info.position = Some(position);
+        }
+        self
+    }
+
+    /// Sets the module unless the error already has one.
+    pub fn with_module<T>(mut self, module: T) -> VmError
+    where
+        T: Into<String>,
+    {
+        let info = self.info_mut();
+        if info.module.is_none() {
+            info.module = Some(module.into());
+        }
+        self
+    }
+}
+
+impl std::fmt::Display for VmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let info = self.info();
+        write!(f, "{}", self.kind())?;
+        if let Some(module) = &info.module {
+            write!(f, " in {}", module)?;
+        }
+        if let Some(position) = info.position {
+            write!(f, " at {}", position)?;
+        }
+        write!(f, ": {}", info.message)
+    }
+}
+
+impl std::error::Error for VmError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_display() {
+        let error = VmError::TypeError("Expected integer".into())
+            .with_position(Position { line: 3, column: 7 })
+            .with_module("Main");
+
+        assert_eq!(
+            error.to_string(),
+            "TypeError in Main at 3:7: Expected integer"
+        );
+        assert_eq!(
+            VmError::NativeError("Failed to read file".into()).to_string(),
+            "NativeError: Failed to read file"
+        );
+    }
+
+    #[test]
+    fn test_context_is_not_overwritten() {
+        let error = VmError::IndexError("Index 3 is out of bounds".into())
+            .with_position(Position { line: 1, column: 2 })
+            .with_module("A")
+            .with_position(Position { line: 5, column: 6 })
+            .with_module("B");
+
+        assert_eq!(error.position(), Some(Position { line: 1, column: 2 }));
+        assert_eq!(error.module(), Some("A"));
+    }
+}
@@ -0,0 +1,391 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem;
+use std::rc::{Rc, Weak};
+
+use crate::value::{FunctionValue, MapKey, RecordValue, Upvalue, Value};
+
+// collections are at least this many allocations apart
+const MIN_THRESHOLD: usize = 1024;
+
+type Array = RefCell<Vec<Value>>;
+type Map = RefCell<HashMap<MapKey, Value>>;
+
+/// How much memory the objects the interpreter allocated are using.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HeapStats {
+    /// Arrays, maps, records and upvalues that are still alive.
+    pub objects: usize,
+    /// A rough estimate of the memory they take up.
+    pub bytes: usize,
+    pub collections: usize,
+    /// Objects freed by the collector, since reference counting alone
+    /// couldn't free them.
+    pub collected: usize,
+}
+
+enum Tracked {
+    Array(Weak<Array>),
+    Map(Weak<Map>),
+    Record(Weak<RecordValue>),
+    Upvalue(Weak<RefCell<Upvalue>>),
+}
+
+impl Tracked {
+    fn upgrade(&self) -> Option<Object> {
+        match self {
+            Tracked::Array(array) => array.upgrade().map(Object::Array),
+            Tracked::Map(map) => map.upgrade().map(Object::Map),
+            Tracked::Record(record) => record.upgrade().map(Object::Record),
+            Tracked::Upvalue(upvalue) => upvalue.upgrade().map(Object::Upvalue),
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        match self {
+            Tracked::Array(array) => array.strong_count() > 0,
+            Tracked::Map(map) => map.strong_count() > 0,
+            Tracked::Record(record) => record.strong_count() > 0,
+            Tracked::Upvalue(upvalue) => upvalue.strong_count() > 0,
+        }
+    }
+}
+
+enum Object {
+    Array(Rc<Array>),
+    Map(Rc<Map>),
+    Record(Rc<RecordValue>),
+    Upvalue(Rc<RefCell<Upvalue>>),
+    Function(Rc<FunctionValue>),
+}
+
+impl Object {
+    fn from_value(value: &Value) -> Option<Object> {
+        match value {
+            Value::Array(array) => Some(Object::Array(array.clone())),
+            Value::Map(map) => Some(Object::Map(map.clone())),
+            Value::Record(record) => Some(Object::Record(record.clone())),
+            Value::Function(function) => Some(Object::Function(function.clone())),
+            _ => None,
+        }
+    }
+
+    fn address(&self) -> usize {
+        match self {
+            Object::Array(array) => Rc::as_ptr(array) as *const () as usize,
+            Object::Map(map) => Rc::as_ptr(map) as *const () as usize,
+            Object::Record(record) => Rc::as_ptr(record) as *const () as usize,
+            Object::Upvalue(upvalue) => Rc::as_ptr(upvalue) as *const () as usize,
+            Object::Function(function) => Rc::as_ptr(function) as *const () as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::Array(array) => Rc::strong_count(array),
+            Object::Map(map) => Rc::strong_count(map),
+            Object::Record(record) => Rc::strong_count(record),
+            Object::Upvalue(upvalue) => Rc::strong_count(upvalue),
+            Object::Function(function) => Rc::strong_count(function),
+        }
+    }
+
+    // a rough estimate of the memory it takes up
+    fn size(&self) -> usize {
+        let values = |len: usize| len * mem::size_of::<Value>();
+        match self {
+            Object::Array(array) => {
+                mem::size_of::<Array>() + array.try_borrow().map_or(0, |array| values(array.len()))
+            }
+            Object::Map(map) => {
+                mem::size_of::<Map>()
+                    + map.try_borrow().map_or(0, |map| {
+                        map.len() * (mem::size_of::<MapKey>() + mem::size_of::<Value>())
+                    })
+            }
+            Object::Record(record) => {
+                mem::size_of::<RecordValue>()
+                    + record
+                        .fields
+                        .try_borrow()
+                        .map_or(0, |fields| values(fields.len()))
+            }
+            Object::Upvalue(_) => mem::size_of::<RefCell<Upvalue>>(),
+            Object::Function(_) => mem::size_of::<FunctionValue>(),
+        }
+    }
+
+    // None if the object is being modified, so it can't be looked into
+    fn children(&self) -> Option<Vec<Object>> {
+        match self {
+            Object::Array(array) => {
+                let array = array.try_borrow().ok()?;
+                Some(array.iter().filter_map(Object::from_value).collect())
+            }
+            Object::Map(map) => {
+                let map = map.try_borrow().ok()?;
+                Some(map.values().filter_map(Object::from_value).collect())
+            }
+            Object::Record(record) => {
+                let fields = record.fields.try_borrow().ok()?;
+                Some(fields.iter().filter_map(Object::from_value).collect())
+            }
+            Object::Upvalue(upvalue) => {
+                let upvalue = upvalue.try_borrow().ok()?;
+                Some(
+                    upvalue
+                        .closed_value()
+                        .and_then(Object::from_value)
+                        .into_iter()
+                        .collect(),
+                )
+            }
+            Object::Function(function) => match function.as_ref() {
+                FunctionValue::User { upvalues, .. } => Some(
+                    upvalues
+                        .iter()
+                        .map(|upvalue| Object::Upvalue(upvalue.clone()))
+                        .collect(),
+                ),
+                FunctionValue::Builtin { .. } => Some(Vec::new()),
+            },
+        }
+    }
+
+    // Drops the references this object holds. Functions can't be changed,
+    // but every cycle through one also goes through one of its upvalues.
+    fn clear(&self, dropped: &mut Vec<Value>) {
+        match self {
+            Object::Array(array) => dropped.extend(mem::take(&mut *array.borrow_mut())),
+            Object::Map(map) => dropped.extend(mem::take(&mut *map.borrow_mut()).into_values()),
+            Object::Record(record) => {
+                dropped.extend(mem::take(&mut *record.fields.borrow_mut()).into_vec())
+            }
+            Object::Upvalue(upvalue) => dropped.extend(upvalue.borrow_mut().clear()),
+            Object::Function(_) => {}
+        }
+    }
+}
+
+/// Keeps track of the objects that can end up in a reference cycle, so the
+/// cycles can be freed.
+///
+/// Values are reference counted, which frees everything except cycles, like
+/// an array that contains itself or a closure that captures itself. The
+/// collector looks for objects that are only referenced by each other: an
+/// object with more references than the tracked objects account for is held
+/// by the stack, a module's globals, an intrinsic, an open upvalue in
+/// `Agent::upvalues` or the host, and everything reachable from one of those
+/// stays alive.
+pub(crate) struct Heap {
+    tracked: HashMap<usize, Tracked>,
+    // what the tracked objects took up at the last collection, plus what's
+    // been allocated since. Objects that were freed since aren't subtracted.
+    bytes: usize,
+    threshold: usize,
+    collections: usize,
+    collected: usize,
+}
+
+impl Heap {
+    pub(crate) fn new() -> Self {
+        Self {
+            tracked: HashMap::new(),
+            bytes: 0,
+            threshold: MIN_THRESHOLD,
+            collections: 0,
+            collected: 0,
+        }
+    }
+
+    /// Tracks a newly allocated array, map or record. Other values can't be part
+    /// of a cycle on their own.
+    pub(crate) fn track(&mut self, value: &Value) {
+        let (tracked, object) = match value {
+            Value::Array(array) => (
+                Tracked::Array(Rc::downgrade(array)),
+                Object::Array(array.clone()),
+            ),
+            Value::Map(map) => (Tracked::Map(Rc::downgrade(map)), Object::Map(map.clone())),
+            Value::Record(record) => (
+                Tracked::Record(Rc::downgrade(record)),
+                Object::Record(record.clone()),
+            ),
+            _ => return,
+        };
+        self.insert(object, tracked);
+    }
+
+    pub(crate) fn track_upvalue(&mut self, upvalue: &Rc<RefCell<Upvalue>>) {
+        self.insert(
+            Object::Upvalue(upvalue.clone()),
+            Tracked::Upvalue(Rc::downgrade(upvalue)),
+        );
+    }
+
+    fn insert(&mut self, object: Object, tracked: Tracked) {
+        // the address may have belonged to an object that's been freed since
+        let address = object.address();
+        match self.tracked.get(&address) {
+            Some(existing) if existing.is_alive() => {}
+            _ => {
+                self.bytes += object.size();
+                self.tracked.insert(address, tracked);
+            }
+        }
+    }
+
+    /// Roughly how much memory `added` more elements of an array or entries
+    /// of a map take up.
+    pub(crate) fn growth(value: &Value, added: usize) -> usize {
+        let size = match value {
+            Value::Array(_) => mem::size_of::<Value>(),
+            Value::Map(_) => mem::size_of::<MapKey>() + mem::size_of::<Value>(),
+            _ => 0,
+        };
+        added.saturating_mul(size)
+    }
+
+    /// Counts memory a tracked object has grown by since it was allocated.
+    pub(crate) fn grow(&mut self, bytes: usize) {
+        self.bytes = self.bytes.saturating_add(bytes);
+    }
+
+    /// Roughly how much memory the tracked objects take up. It's only exact
+    /// right after a collection.
+    pub(crate) fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Whether enough has been allocated since the last collection for
+    /// another one to be worth it.
+    pub(crate) fn is_due(&self) -> bool {
+        self.tracked.len() >= self.threshold
+    }
+
+    /// Frees every cycle that nothing outside of it refers to, and returns
+    /// how many objects that was.
+    pub(crate) fn collect(&mut self) -> usize {
+        let mut objects = Vec::with_capacity(self.tracked.len());
+        let mut indices = HashMap::new();
+        for (&address, tracked) in &self.tracked {
+            if let Some(object) = tracked.upgrade() {
+                indices.insert(address, objects.len());
+                objects.push(object);
+            }
+        }
+
+        // anything a tracked object refers to can be part of a cycle too,
+        // like the closures that were never tracked themselves
+        let mut edges = Vec::new();
+        let mut internal = Vec::new();
+        let mut scanned = Vec::new();
+        let mut i = 0;
+        while i < objects.len() {
+            let children = objects[i].children();
+            scanned.push(children.is_some());
+
+            let mut targets = Vec::new();
+            for child in children.into_iter().flatten() {
+                let address = child.address();
+                let j = match indices.get(&address) {
+                    Some(&j) => j,
+                    None => {
+                        indices.insert(address, objects.len());
+                        objects.push(child);
+                        objects.len() - 1
+                    }
+                };
+                targets.push(j);
+            }
+            edges.push(targets);
+            i += 1;
+        }
+        internal.resize(objects.len(), 0);
+        for targets in &edges {
+            for &j in targets {
+                internal[j] += 1;
+            }
+        }
+
+        // the reference in `objects` doesn't count
+        let mut reachable = vec![false; objects.len()];
+        let mut pending = (0..objects.len())
+            .filter(|&i| !scanned[i] || objects[i].strong_count() - 1 > internal[i])
+            .collect::<Vec<_>>();
+        while let Some(i) = pending.pop() {
+            if !reachable[i] {
+                reachable[i] = true;
+                pending.extend(edges[i].iter().filter(|&&j| !reachable[j]));
+            }
+        }
+
+        let mut dropped = Vec::new();
+        let mut collected = 0;
+        for (object, reachable) in objects.iter().zip(&reachable) {
+            if !reachable {
+                object.clear(&mut dropped);
+                collected += 1;
+            }
+        }
+        drop(dropped);
+        drop(objects);
+
+        self.tracked.retain(|_, tracked| tracked.is_alive());
+        self.bytes = self.stats().bytes;
+        self.threshold = MIN_THRESHOLD.max(self.tracked.len() * 2);
+        self.collections += 1;
+        self.collected += collected;
+
+        collected
+    }
+
+    pub(crate) fn stats(&self) -> HeapStats {
+        let mut stats = HeapStats {
+            collections: self.collections,
+            collected: self.collected,
+            ..HeapStats::default()
+        };
+
+        for object in self.tracked.values().filter_map(Tracked::upgrade) {
+            stats.objects += 1;
+            stats.bytes += object.size();
+        }
+
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_collect_cycles() {
+        let mut heap = Heap::new();
+
+        let kept = Value::from(vec![Value::Null]);
+        heap.track(&kept);
+        if let Value::Array(array) = &kept {
+            array.borrow_mut()[0] = kept.clone();
+        }
+
+        {
+            let dropped = Value::from(vec![Value::Null, Value::from(1)]);
+            heap.track(&dropped);
+            if let Value::Array(array) = &dropped {
+                array.borrow_mut()[0] = dropped.clone();
+            }
+        }
+
+        assert_eq!(heap.stats().objects, 2);
+        assert_eq!(heap.collect(), 1);
+        assert_eq!(heap.stats().objects, 1);
+        assert_eq!(heap.stats().collected, 1);
+
+        if let (Value::Array(array), Value::Array(inner)) = (&kept, &kept.clone()) {
+            assert!(matches!(&array.borrow()[0], Value::Array(a) if Rc::ptr_eq(a, inner)));
+        }
+    }
+}
@@ -1,15 +1,23 @@
-use std::cell::RefCell;
+use std::borrow::Cow;
+use std::cell::{RefCell, RefMut};
 use std::collections::HashMap;
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::ops::{Add, Deref, Div, Mul, Rem, Sub};
 use std::rc::Rc;
 
 use crate::agent::Agent;
 use crate::compiler::disassemble::disassemble;
+use crate::compiler::parser::Position;
 use crate::debuginfo::DebugInfo;
+use crate::error::{ErrorInfo, VmError};
+use crate::heap::{Heap, HeapStats};
 use crate::module::Module;
 use crate::opcode::OpCode;
-use crate::value::{FunctionValue, Upvalue, Value};
+use crate::permissions::Permissions;
+use crate::value::{
+    sorted_entries, FunctionValue, MapKey, RangeValue, RecordValue, Upvalue, Value,
+};
+use crate::verifier::verify;
 
 macro_rules! print_stack {
     ($stack:expr) => {{
@@ -24,52 +32,123 @@ macro_rules! print_stack {
     }};
 }
 
+// what calls and allocations cost, every other instruction costs one unit of
+// fuel
+const CALL_FUEL: u64 = 10;
+const ALLOCATION_FUEL: u64 = 5;
+
+// how many frames a long traceback keeps at either end
+const TRACEBACK_FRAMES: usize = 20;
+
+/// How much a program may use before it gets a `LimitError`, which it can
+/// catch like any other error.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// How deeply function calls can be nested.
+    pub max_call_depth: usize,
+    /// How many values the stack can hold.
+    pub max_stack: usize,
+    /// How many elements a single array can be allocated with.
+    pub max_allocation: usize,
+    /// Roughly how much memory arrays, maps, records and upvalues can take up
+    /// altogether.
+    pub max_heap_bytes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_call_depth: 100_000,
+            max_stack: 1_000_000,
+            max_allocation: 1 << 24,
+            max_heap_bytes: usize::MAX,
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Frame {
+    // offset of the call instruction that entered this frame
+    call_site: usize,
     prev_ip: usize,
     prev_bp: usize,
     num_args: usize,
     module_id: usize,
     current_function: Option<usize>,
+    // where a for loop goes once it's done, if this frame is a call to the
+    // loop's iterator function. The loop takes the next step when it returns.
+    iteration_end: Option<usize>,
+}
+
+// Pushed when entering a try block. Records everything needed to get back to
+// the catch block from wherever the error is raised.
+struct Handler {
+    address: usize,
+    sp: usize,
+    bp: usize,
+    call_depth: usize,
 }
 
-pub struct Interpreter<'a> {
-    pub agent: &'a mut Agent,
+pub struct Interpreter {
+    pub agent: Agent,
     intrinsics: HashMap<usize, Value>,
     modules: HashMap<usize, Module>,
     current_module: Option<Module>,
     call_stack: Vec<Frame>,
+    handlers: Vec<Handler>,
+    // the program being run, kept for calls made by builtins
+    code: Rc<[u8]>,
     stack: Vec<Value>,
     ip: usize,
+    // offset of the instruction being executed, ip has already moved past it
+    instruction_start: usize,
     bp: usize,
     sp: usize,
-    debuginfo: Option<&'a DebugInfo>,
+    debuginfo: Option<DebugInfo>,
+    heap: Heap,
+    // None for no limit
+    fuel: Option<u64>,
+    // stopped for fuel with the frames left in place
+    suspended: bool,
+    limits: Limits,
+    permissions: Permissions,
 }
 
-impl<'a> Interpreter<'a> {
-    pub fn new(agent: &'a mut Agent) -> Interpreter<'a> {
+impl Interpreter {
+    pub fn new(agent: Agent) -> Interpreter {
         Interpreter::with_intrinsics(agent, HashMap::new())
     }
 
-    pub fn with_intrinsics(
-        agent: &'a mut Agent,
-        intrinsics: HashMap<usize, Value>,
-    ) -> Interpreter<'a> {
+    pub fn with_intrinsics(agent: Agent, intrinsics: HashMap<usize, Value>) -> Interpreter {
         Interpreter {
             agent,
             intrinsics,
             modules: HashMap::new(),
             current_module: None,
             call_stack: Vec::new(),
+            handlers: Vec::new(),
+            code: Rc::new([]),
             stack: Vec::new(),
             ip: 0,
+            instruction_start: 0,
             bp: 0,
             sp: 0,
             debuginfo: None,
+            heap: Heap::new(),
+            fuel: None,
+            suspended: false,
+            limits: Limits::default(),
+            permissions: Permissions::new(),
         }
     }
 
-    pub(crate) fn set_debuginfo(&mut self, debuginfo: &'a DebugInfo) {
+    /// Makes a value available as a global in the modules initialised from
+    /// now on.
+    pub fn add_intrinsic(&mut self, name: usize, value: Value) {
+        self.intrinsics.insert(name, value);
+    }
+
+    pub(crate) fn set_debuginfo(&mut self, debuginfo: DebugInfo) {
         self.debuginfo.replace(debuginfo);
     }
 
@@ -83,9 +162,11 @@ impl<'a> Interpreter<'a> {
         self.stack.truncate(self.sp);
     }
 
-    fn pop(&mut self) -> Result<Value, &'static str> {
+    fn pop(&mut self) -> Result<Value, VmError> {
         self.sp -= 1;
-        self.stack.pop().ok_or("Stack underflow")
+        self.stack
+            .pop()
+            .ok_or_else(|| self.error(VmError::RuntimeError, "Stack underflow".to_string()))
     }
 
     fn pop_and_get(&mut self, count: usize) -> Vec<Value> {
@@ -93,13 +174,13 @@ impl<'a> Interpreter<'a> {
         self.stack.split_off(self.sp).into_iter().rev().collect()
     }
 
-    fn next_instruction(&mut self, code: &Vec<u8>) -> u8 {
+    fn next_instruction(&mut self, code: &[u8]) -> u8 {
         let inst = code[self.ip];
         self.ip += 1;
         inst
     }
 
-    fn next_usize_bytes(&mut self, code: &Vec<u8>) -> [u8; std::mem::size_of::<usize>()] {
+    fn next_usize_bytes(&mut self, code: &[u8]) -> [u8; std::mem::size_of::<usize>()] {
         const USIZE_SIZE: usize = std::mem::size_of::<usize>();
 
         let array: [u8; USIZE_SIZE] = code[self.ip..self.ip + USIZE_SIZE]
@@ -119,20 +200,23 @@ impl<'a> Interpreter<'a> {
     }
 
     // in any scope except the global scope, the base pointer points after the arguments
-    fn arguments_index(&self) -> Result<usize, String> {
+    fn arguments_index(&self) -> Result<usize, VmError> {
         if self.call_stack.is_empty() {
-            Err(self.error("Trying to access arguments when not in function".to_string()))
+            Err(self.error(
+                VmError::RuntimeError,
+                "Trying to access arguments when not in function".to_string(),
+            ))
         } else {
             Ok(self.bp - 1)
         }
     }
 
-    fn argument(&self, at: usize) -> Result<&Value, String> {
+    fn argument(&self, at: usize) -> Result<&Value, VmError> {
         Ok(&self.stack[self.arguments_index()? - at])
     }
 
-    fn set_argument(&mut self, idx: usize, value: Value) -> Result<(), String> {
-        let idx = self.arguments_index()? + idx;
+    fn set_argument(&mut self, idx: usize, value: Value) -> Result<(), VmError> {
+        let idx = self.arguments_index()? - idx;
         self.stack[idx] = value;
         Ok(())
     }
@@ -155,117 +239,494 @@ impl<'a> Interpreter<'a> {
         self.stack[idx] = value;
     }
 
-    fn executing_function(&self) -> Result<&Value, String> {
+    fn executing_function(&self) -> Result<&Value, VmError> {
         if self.call_stack.is_empty() {
-            Err(self.error("Tried to get executing function in global scope".to_string()))
+            Err(self.error(
+                VmError::RuntimeError,
+                "Tried to get executing function in global scope".to_string(),
+            ))
         } else if let Some(func) = self.stack.get(self.bp) {
             if let func @ Value::Function(_) = func {
                 Ok(func)
             } else {
                 Err(self.error(
+                    VmError::RuntimeError,
                     "Tried to get executing function but bp didn't point to function".to_string(),
                 ))
             }
         } else {
-            Err(self.error(format!(
-                "Base pointer is not within stack: bp={} stack length={}",
-                self.bp,
-                self.stack.len()
-            )))
-        }
-    }
-
-    fn print_stacktrace(&self) -> String {
-        let mut buf = "Stack trace:\n".to_string();
-        for frame in self.call_stack.iter().rev() {
-            if let Some(current_function) = frame.current_function {
-                if let Value::Function(function_value) = &self.stack[current_function] {
-                    match function_value.deref() {
-                        FunctionValue::User { name, .. } => {
-                            let module_name = self.agent.string_table[self
-                                .modules
-                                .get(&frame.module_id)
-                                .or_else(|| self.current_module.as_ref())
-                                .unwrap()
-                                .name()]
-                            .clone();
-                            let function_name =
-                                name.map(|name| self.agent.string_table[name].clone());
-                            buf += format!(
-                                "	{}.{}\n",
-                                module_name,
-                                function_name.unwrap_or_else(|| "<anonymous>".into())
-                            )
-                            .as_ref();
-                        }
-
-                        FunctionValue::Builtin { name, .. } => {
-                            buf += format!(
-                                "	builtin {}\n",
-                                name.map(|name| self.agent.string_table[name].clone())
-                                    .unwrap_or_else(|| "<anonymous>".into())
-                            )
-                            .as_ref();
-                        }
-                    }
-                } else {
-                    unreachable!()
-                }
-            } else {
-                buf += "	toplevel\n";
+            Err(self.error(
+                VmError::RuntimeError,
+                format!(
+                    "Base pointer is not within stack: bp={} stack length={}",
+                    self.bp,
+                    self.stack.len()
+                ),
+            ))
+        }
+    }
+
+    pub(crate) fn print_stacktrace(&self) -> String {
+        let mut buf = "Traceback (most recent call last):\n".to_string();
+
+        // every frame is left at the call site of the next one, and the
+        // innermost one at the instruction that failed
+        let site = |depth: usize| {
+            self.call_stack
+                .get(depth)
+                .map_or(self.instruction_start, |frame| frame.call_site)
+        };
+
+        // recursion shows up as the same frame over and over, so runs of it
+        // are collapsed
+        let mut frames = vec![self.describe_site(site(0), "<toplevel>".to_string())];
+        let mut repeated = 0;
+        for depth in 0..self.call_stack.len() {
+            let frame = self.describe_site(site(depth + 1), self.frame_name(depth));
+            if frames.last() == Some(&frame) {
+                repeated += 1;
+                continue;
             }
+            if repeated > 0 {
+                frames.push(format!(
+                    "  ... previous frame repeated {} times\n",
+                    repeated
+                ));
+                repeated = 0;
+            }
+            frames.push(frame);
+        }
+        if repeated > 0 {
+            frames.push(format!(
+                "  ... previous frame repeated {} times\n",
+                repeated
+            ));
         }
+
+        // and whatever is left of a deep stack is cut down to both ends
+        if frames.len() > 2 * TRACEBACK_FRAMES {
+            let omitted = frames.len() - 2 * TRACEBACK_FRAMES;
+            frames.splice(
+                TRACEBACK_FRAMES..frames.len() - TRACEBACK_FRAMES,
+                std::iter::once(format!("  ... {} more frames\n", omitted)),
+            );
+        }
+
+        buf += &frames.concat();
         buf
     }
 
-    fn current_module(&self) -> Option<&Module> {
-        if !self.call_stack.is_empty()
-            && self.call_stack.last().unwrap().module_id
-                != self.current_module.as_ref().unwrap().name()
-        {
-            self.modules.get(&self.call_stack.last().unwrap().module_id)
+    // `file:line:column` of an instruction, if there's debug info for it
+    fn site_location(&self, site: usize) -> Option<String> {
+        let debuginfo = self.debuginfo.as_ref()?;
+        let context = debuginfo.get(site)?;
+        let file = debuginfo.file(context.file)?;
+        Some(format!("{}:{}", file.name, context.position))
+    }
+
+    fn frame_name(&self, depth: usize) -> String {
+        // the function of a frame sits at its base pointer, which is only
+        // saved once the next frame is entered
+        let bp = self
+            .call_stack
+            .get(depth + 1)
+            .and_then(|frame| frame.current_function)
+            .unwrap_or(self.bp);
+        let module_name = self
+            .modules
+            .get(&self.call_stack[depth].module_id)
+            .or(self.current_module.as_ref())
+            .map(|module| self.agent.string_table[module.name()].as_str())
+            .unwrap_or("<unknown>");
+
+        match self.stack.get(bp) {
+            Some(Value::Function(function_value)) => match function_value.deref() {
+                FunctionValue::User { name, .. } => format!(
+                    "{}.{}",
+                    module_name,
+                    name.map_or("<anonymous>", |name| &self.agent.string_table[name])
+                ),
+                FunctionValue::Builtin { name, .. } => format!(
+                    "builtin {}",
+                    name.map_or("<anonymous>", |name| &self.agent.string_table[name])
+                ),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn describe_site(&self, site: usize, function: String) -> String {
+        let debuginfo = match &self.debuginfo {
+            Some(debuginfo) => debuginfo,
+            None => return format!("  in {}\n", function),
+        };
+        let context = match debuginfo.get(site) {
+            Some(context) => context,
+            None => return format!("  in {}\n", function),
+        };
+        let Position { line, column } = context.position;
+        let file = debuginfo.file(context.file);
+
+        let mut buf = format!(
+            "  {}:{}:{}, in {}\n",
+            file.map_or("<unknown>", |file| &file.name),
+            line,
+            column,
+            function
+        );
+        if let Some(text) = file.and_then(|file| file.line(line)) {
+            let excerpt = text.trim_start();
+            let indent = text.chars().count() - excerpt.chars().count();
+            buf += &format!(
+                "    {}\n    {}^\n",
+                excerpt.trim_end(),
+                " ".repeat(column.saturating_sub(indent + 1))
+            );
+        }
+
+        buf
+    }
+
+    /// How much memory the arrays, records and closures allocated so far are
+    /// using.
+    pub fn heap_stats(&self) -> HeapStats {
+        self.heap.stats()
+    }
+
+    /// Frees the reference cycles nothing refers to anymore. This also
+    /// happens on its own as more gets allocated.
+    pub fn collect_garbage(&mut self) -> usize {
+        self.heap.collect()
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    pub fn set_permissions(&mut self, permissions: Permissions) {
+        self.permissions = permissions;
+    }
+
+    /// What the builtins may do. They have to check it themselves.
+    pub fn permissions(&self) -> &Permissions {
+        &self.permissions
+    }
+
+    /// Checks that an array of `len` elements may be allocated, before
+    /// allocating it.
+    pub fn check_allocation(&self, len: usize) -> Result<(), VmError> {
+        if len > self.limits.max_allocation {
+            Err(self.error(
+                VmError::LimitError,
+                format!("Cannot allocate an array of {} elements", len),
+            ))
         } else {
-            self.current_module.as_ref()
+            Ok(())
+        }
+    }
+
+    // registers a new array or record with the collector
+    pub(crate) fn track(&mut self, value: &Value) -> Result<(), VmError> {
+        self.heap.track(value);
+
+        if self.heap.bytes() > self.limits.max_heap_bytes {
+            // what was freed without a collection still counts until one
+            self.heap.collect();
+            if self.heap.bytes() > self.limits.max_heap_bytes {
+                return Err(self.error(VmError::LimitError, "Out of memory".to_string()));
+            }
+        }
+        self.collect_if_due();
+
+        Ok(())
+    }
+
+    /// Counts the elements an array or map is about to grow by against the
+    /// heap limit, before adding them.
+    pub(crate) fn track_growth(&mut self, value: &Value, added: usize) -> Result<(), VmError> {
+        let bytes = Heap::growth(value, added);
+
+        if self.heap.bytes().saturating_add(bytes) > self.limits.max_heap_bytes {
+            self.heap.collect();
+            if self.heap.bytes().saturating_add(bytes) > self.limits.max_heap_bytes {
+                return Err(self.error(VmError::LimitError, "Out of memory".to_string()));
+            }
+        }
+        self.heap.grow(bytes);
+
+        Ok(())
+    }
+
+    fn collect_if_due(&mut self) {
+        if self.heap.is_due() {
+            self.heap.collect();
+        }
+    }
+
+    /// Adds a module that doesn't need initialising, like a native one.
+    pub(crate) fn add_module(&mut self, module: Module) {
+        self.modules.insert(module.name(), module);
+    }
+
+    /// A module that has finished initialising.
+    pub(crate) fn module(&self, name: usize) -> Option<&Module> {
+        self.modules.get(&name)
+    }
+
+    // functions can also be called from outside of any module, by the host
+    fn current_module(&self) -> Option<&Module> {
+        match self.call_stack.last() {
+            Some(frame)
+                if self.current_module.as_ref().map(Module::name) != Some(frame.module_id) =>
+            {
+                self.modules.get(&frame.module_id)
+            }
+            _ => self.current_module.as_ref(),
         }
     }
 
     fn current_module_mut(&mut self) -> Option<&mut Module> {
-        if !self.call_stack.is_empty()
-            && self.call_stack.last().unwrap().module_id
-                != self.current_module.as_ref().unwrap().name()
-        {
-            self.modules
-                .get_mut(&self.call_stack.last().unwrap().module_id)
-        } else {
-            self.current_module.as_mut()
+        match self.call_stack.last() {
+            Some(frame)
+                if self.current_module.as_ref().map(Module::name) != Some(frame.module_id) =>
+            {
+                self.modules.get_mut(&frame.module_id)
+            }
+            _ => self.current_module.as_mut(),
         }
     }
 
-    fn error(&self, msg: String) -> String {
-        let mod_name = self.current_module().unwrap().name();
-        format!(
-            "Error in {}: {}\n{:#?}",
-            self.agent.string_table[mod_name].clone(),
-            msg,
-            self.debuginfo.map(|d| d.get(self.ip)),
-        )
+    fn error(&self, kind: fn(ErrorInfo) -> VmError, msg: String) -> VmError {
+        self.locate(kind(msg.into()))
+    }
+
+    /// Fills in the current module and source position, if the error doesn't
+    /// already know where it came from.
+    fn locate(&self, error: VmError) -> VmError {
+        let error = match self
+            .debuginfo
+            .as_ref()
+            .and_then(|d| d.get(self.instruction_start))
+        {
+            Some(context) => error.with_position(context.position),
+            None => error,
+        };
+
+        match self.current_module() {
+            Some(module) => error.with_module(self.agent.string_table[module.name()].clone()),
+            None => error,
+        }
     }
 
     pub fn evaluate(&mut self, code: Vec<u8>) -> Value {
         match self._evaluate(code) {
             Ok(value) => value,
             Err(e) => {
-                eprintln!("{}\n{}", e, self.print_stacktrace());
+                eprintln!("{}{}", self.print_stacktrace(), e);
                 Value::Null
             }
         }
     }
 
-    fn _evaluate(&mut self, code: Vec<u8>) -> Result<Value, String> {
+    pub(crate) fn _evaluate(&mut self, code: Vec<u8>) -> Result<Value, VmError> {
+        verify(&self.agent, &code, self.ip)?;
+        let code: Rc<[u8]> = code.into();
+        self.code = code.clone();
+
         if cfg!(vm_debug) {
-            disassemble(self.agent, &code)?;
+            disassemble(&self.agent, &code)?;
+        }
+
+        self.run()
+    }
+
+    /// Limits how much more code can run. Every instruction costs a unit of
+    /// fuel, and calls and allocations cost more. `None` removes the limit.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    pub fn add_fuel(&mut self, fuel: u64) {
+        if let Some(remaining) = &mut self.fuel {
+            *remaining = remaining.saturating_add(fuel);
+        }
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Whether the last run stopped with [`VmError::OutOfFuel`] and can be
+    /// resumed.
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    /// Continues a run that ran out of fuel where it stopped.
+    pub fn resume(&mut self) -> Result<Value, VmError> {
+        if !self.suspended {
+            return Err(VmError::RuntimeError("Nothing to resume".into()));
+        }
+
+        self.run()
+    }
+
+    fn run(&mut self) -> Result<Value, VmError> {
+        let code = self.code.clone();
+        self.suspended = false;
+
+        let code_len = code.len();
+        while self.ip < code_len {
+            if !self.consume_fuel(&code) {
+                self.suspended = true;
+                return Err(self.out_of_fuel());
+            }
+            match self.execute_instruction(&code) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => self.catch(e)?,
+            }
+        }
+
+        Ok(if self.stack.is_empty() {
+            Value::Null
+        } else {
+            self.pop()?
+        })
+    }
+
+    /// Calls a function and returns its result. Builtins can use this to call
+    /// back into the program, since a user function gets its own dispatch
+    /// loop that runs until it returns. If the call fails, everything it
+    /// pushed is unwound before the error is handed back.
+    pub fn call_function(&mut self, function: Value, args: Vec<Value>) -> Result<Value, VmError> {
+        let depth = self.call_stack.len();
+        let handlers = self.handlers.len();
+        let (sp, bp, ip) = (self.sp, self.bp, self.ip);
+        let instruction_start = self.instruction_start;
+
+        let num_args = args.len();
+        // arguments are pushed in reverse
+        for arg in args.into_iter().rev() {
+            self.push(arg);
+        }
+
+        let result = self
+            .call_value(function, num_args)
+            .and_then(|_| self.run_until_return(depth));
+        // errors raised by the caller point at its own instruction again
+        self.instruction_start = instruction_start;
+
+        match result {
+            Ok(()) => self.pop(),
+            Err(e) => {
+                self.close_upvalues(sp)?;
+                self.call_stack.truncate(depth);
+                self.handlers.truncate(handlers);
+                self.pop_n(self.sp - sp);
+                self.bp = bp;
+                self.ip = ip;
+                Err(e)
+            }
+        }
+    }
+
+    // Runs until every frame above the given call depth has returned.
+    fn run_until_return(&mut self, depth: usize) -> Result<(), VmError> {
+        let code = self.code.clone();
+
+        while self.call_stack.len() > depth {
+            // the builtin that made the call can't be suspended, so this
+            // can't be resumed
+            if !self.consume_fuel(&code) {
+                return Err(self.out_of_fuel());
+            }
+            match self.execute_instruction(&code) {
+                Ok(true) => {}
+                Ok(false) => {
+                    return Err(self.error(
+                        VmError::RuntimeError,
+                        "Halted inside a function call".to_string(),
+                    ))
+                }
+                // handlers from outside of the call are left to the caller
+                Err(e) if matches!(self.handlers.last(), Some(handler) if handler.call_depth > depth) => {
+                    self.catch(e)?
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs the code appended to the program since the last call, starting
+    /// at `start`. After an uncaught error the frames are left in place for
+    /// the stack trace, and have to be unwound before running anything else.
+    pub(crate) fn evaluate_from(&mut self, code: Vec<u8>, start: usize) -> Result<Value, VmError> {
+        self.ip = start;
+        self._evaluate(code)
+    }
+
+    // Charges for the next instruction, or returns false without running it
+    // if there isn't enough fuel left.
+    fn consume_fuel(&mut self, code: &[u8]) -> bool {
+        let remaining = match &mut self.fuel {
+            Some(remaining) => remaining,
+            None => return true,
+        };
+
+        let cost = match OpCode::try_from(code[self.ip]) {
+            Ok(OpCode::Call | OpCode::CallSpread) => CALL_FUEL,
+            Ok(
+                OpCode::NewArray
+                | OpCode::NewArrayWithValues
+                | OpCode::ArrayConcat
+                | OpCode::NewRecord
+                | OpCode::NewMap
+                | OpCode::ArrayRest
+                | OpCode::NewFunction
+                | OpCode::NewVariadicFunction,
+            ) => ALLOCATION_FUEL,
+            _ => 1,
+        };
+        if *remaining < cost {
+            return false;
+        }
+
+        *remaining -= cost;
+        true
+    }
+
+    fn out_of_fuel(&mut self) -> VmError {
+        // point at the instruction that didn't run
+        self.instruction_start = self.ip;
+        self.error(VmError::OutOfFuel, "Out of fuel".to_string())
+    }
+
+    /// Drops every frame and handler and keeps the module that was being
+    /// initialised, like it had ended normally. The globals and modules
+    /// defined before an error stay around for the next run.
+    pub(crate) fn unwind(&mut self) {
+        // closures that escaped into globals keep the values they captured
+        let _ = self.close_upvalues(0);
+        self.call_stack.clear();
+        self.handlers.clear();
+        self.pop_n(self.sp);
+        self.bp = 0;
+        self.suspended = false;
+
+        if self.current_module.is_some() {
+            self.end_module();
         }
+    }
 
+    // Returns false once the program halts.
+    fn execute_instruction(&mut self, code: &[u8]) -> Result<bool, VmError> {
         macro_rules! number_binop {
             ($name:expr, $intop:expr, $doubleop:expr) => {
                 number_binop!($name, $intop, $doubleop, |a: i64| -> Result<i64, String> {
@@ -279,13 +740,17 @@ impl<'a> Interpreter<'a> {
                 self.push(if let Value::Integer(a) = a {
                     if let Value::Integer(b) = b {
                         let converter = $bconvert;
-                        Value::from($intop(a, converter(b)?))
+                        Value::from($intop(
+                            a,
+                            converter(b).map_err(|e| self.error(VmError::RuntimeError, e))?,
+                        ))
                     } else if let Value::Double(b) = b {
                         Value::from($doubleop(a as f64, b))
                     } else {
-                        return Err(
-                            self.error(format!("Got unexpected value {:?} in {}", b, $name))
-                        );
+                        return Err(self.error(
+                            VmError::TypeError,
+                            format!("Got unexpected {} in {}", self.describe_value(&b), $name),
+                        ));
                     }
                 } else if let Value::Double(a) = a {
                     if let Value::Integer(b) = b {
@@ -293,121 +758,156 @@ impl<'a> Interpreter<'a> {
                     } else if let Value::Double(b) = b {
                         Value::from($doubleop(a, b))
                     } else {
-                        return Err(
-                            self.error(format!("Got unexpected value {:?} in {}", b, $name))
-                        );
+                        return Err(self.error(
+                            VmError::TypeError,
+                            format!("Got unexpected {} in {}", self.describe_value(&b), $name),
+                        ));
                     }
                 } else {
-                    return Err(self.error(format!("Got unexpected value {:?} in {}", a, $name)));
+                    return Err(self.error(
+                        VmError::TypeError,
+                        format!("Got unexpected {} in {}", self.describe_value(&a), $name),
+                    ));
                 })
             }};
         }
 
-        let code_len = code.len();
-        while self.ip < code_len {
-            let instruction = self.next_instruction(&code);
-            if cfg!(vm_debug) {
-                println!("--------------");
-                print_stack!(&self.stack);
-                println!("{:?}", OpCode::from(instruction));
-                println!("ip: {} sp: {} bp: {}", self.ip, self.sp, self.bp);
-                println!(
-                    "{} {:?}",
-                    if let Some(m) = self.current_module() {
-                        self.agent.string_table[m.name()].clone()
-                    } else {
-                        "No module".to_string()
-                    },
-                    self.debuginfo
-                        .and_then(|d| d.get(self.ip))
-                        .map(|d| d.position)
-                );
+        // integers can't be divided by zero, unlike doubles
+        let nonzero = |b: i64| -> Result<i64, String> {
+            if b == 0 {
+                Err("division by zero".to_string())
+            } else {
+                Ok(b)
             }
+        };
 
-            match OpCode::from(instruction) {
-                OpCode::Halt => break,
-                OpCode::ConstInt => self.const_int(&code),
-                OpCode::ConstDouble => self.const_double(&code),
-                OpCode::ConstNull => self.const_null(),
-                OpCode::ConstTrue => self.const_true(),
-                OpCode::ConstFalse => self.const_false(),
-                OpCode::ConstString => self.const_string(&code),
-
-                OpCode::Add => number_binop!("addition", i64::wrapping_add, f64::add),
-                OpCode::Sub => number_binop!("subtraction", i64::wrapping_sub, f64::sub),
-                OpCode::Mul => number_binop!("multiplication", i64::wrapping_mul, f64::mul),
-                OpCode::Div => number_binop!("division", i64::wrapping_div, f64::div),
-                OpCode::Mod => number_binop!("modulus", i64::wrapping_rem, f64::rem),
-                OpCode::Exp => number_binop!(
-                    "exponentiation",
-                    i64::wrapping_pow,
-                    f64::powf,
-                    |b: i64| -> Result<u32, String> {
-                        b.try_into().map_err(|_| "Integer overflow".to_string())
-                    }
-                ),
+        self.instruction_start = self.ip;
+        let instruction = self.next_instruction(code);
+        if cfg!(vm_debug) {
+            println!("--------------");
+            print_stack!(&self.stack);
+            println!("{:?}", OpCode::try_from(instruction));
+            println!("ip: {} sp: {} bp: {}", self.ip, self.sp, self.bp);
+            println!(
+                "{} {:?}",
+                if let Some(m) = self.current_module() {
+                    self.agent.string_table[m.name()].clone()
+                } else {
+                    "No module".to_string()
+                },
+                self.debuginfo
+                    .as_ref()
+                    .and_then(|d| d.get(self.instruction_start))
+                    .map(|d| d.position)
+            );
+        }
 
-                OpCode::Jump => self.jump(&code),
-                OpCode::JumpIfTrue => self.jump_if_true(&code)?,
-                OpCode::JumpIfFalse => self.jump_if_false(&code)?,
-                OpCode::Call => self.call(&code)?,
-                OpCode::Return => self.return_()?,
-                OpCode::Pop => {
-                    self.pop()?;
+        let opcode = OpCode::try_from(instruction).map_err(|byte| {
+            self.error(VmError::RuntimeError, format!("Invalid opcode {}", byte))
+        })?;
+        match opcode {
+            OpCode::Halt => return Ok(false),
+            OpCode::ConstInt => self.const_int(code),
+            OpCode::ConstDouble => self.const_double(code),
+            OpCode::ConstNull => self.const_null(),
+            OpCode::ConstTrue => self.const_true(),
+            OpCode::ConstFalse => self.const_false(),
+            OpCode::ConstString => self.const_string(code),
+            OpCode::ConstAtom => self.const_atom(code),
+
+            OpCode::Add if self.has_strings_on_top() => self.concat()?,
+            OpCode::Add => number_binop!("addition", i64::wrapping_add, f64::add),
+            OpCode::Sub => number_binop!("subtraction", i64::wrapping_sub, f64::sub),
+            OpCode::Mul => number_binop!("multiplication", i64::wrapping_mul, f64::mul),
+            OpCode::Div => number_binop!("division", i64::wrapping_div, f64::div, nonzero),
+            OpCode::Mod => number_binop!("modulus", i64::wrapping_rem, f64::rem, nonzero),
+            OpCode::Exp => number_binop!(
+                "exponentiation",
+                i64::wrapping_pow,
+                f64::powf,
+                |b: i64| -> Result<u32, String> {
+                    b.try_into().map_err(|_| "Integer overflow".to_string())
                 }
-                OpCode::LoadLocal => self.load_local(&code),
-                OpCode::StoreLocal => self.store_local(&code),
-                OpCode::LoadGlobal => self.load_global(&code)?,
-                OpCode::DeclareGlobal => self.declare_global(&code),
-                OpCode::StoreGlobal => self.store_global(&code)?,
-                OpCode::NewFunction => self.new_function(&code),
-                OpCode::BindLocal => self.bind_local(&code)?,
-                OpCode::BindUpvalue => self.bind_upvalue(&code)?,
-                OpCode::BindArgument => self.bind_argument(&code)?,
-                OpCode::LoadUpvalue => self.load_upvalue(&code)?,
-                OpCode::StoreUpvalue => self.store_upvalue(&code)?,
-                OpCode::LoadArgument => self.load_argument(&code)?,
-                OpCode::StoreArgument => self.store_argument(&code)?,
-                OpCode::LoadFromModule => self.load_from_module(&code)?,
-                OpCode::NewArray => self.new_array(&code),
-                OpCode::NewArrayWithValues => self.new_array_with_values(&code)?,
-                OpCode::ArrayGet => self.array_get()?,
-                OpCode::ArraySet => self.array_set()?,
-                OpCode::Equal => self.equal()?,
-                OpCode::NotEqual => self.not_equal()?,
-                OpCode::LessThan => self.less_than()?,
-                OpCode::LessThanEqual => self.less_than_equal()?,
-                OpCode::GreaterThan => self.greater_than()?,
-                OpCode::GreaterThanEqual => self.greater_than_equal()?,
-                OpCode::BitwiseAnd => self.bitwise_and()?,
-                OpCode::BitwiseOr => self.bitwise_or()?,
-                OpCode::BitwiseXor => self.bitwise_xor()?,
-                OpCode::BitwiseNot => self.bitwise_not()?,
-                OpCode::Not => self.not()?,
-                OpCode::LeftShift => self.left_shift()?,
-                OpCode::RightShift => self.right_shift()?,
-                OpCode::Neg => self.neg()?,
-                OpCode::InitModule => self.init_module(&code),
-                OpCode::EndModule => self.end_module(),
-                OpCode::Dup => self.dup(),
-                OpCode::AllocateLocals => self.allocate_locals(&code),
+            ),
+
+            OpCode::Jump => self.jump(code),
+            OpCode::JumpIfTrue => self.jump_if_true(code)?,
+            OpCode::JumpIfFalse => self.jump_if_false(code)?,
+            OpCode::Call => self.call(code)?,
+            OpCode::Return => self.return_()?,
+            OpCode::Pop => {
+                self.pop()?;
             }
+            OpCode::LoadLocal => self.load_local(code),
+            OpCode::StoreLocal => self.store_local(code),
+            OpCode::LoadGlobal => self.load_global(code)?,
+            OpCode::DeclareGlobal => self.declare_global(code),
+            OpCode::StoreGlobal => self.store_global(code)?,
+            OpCode::NewFunction => self.new_function(code, false),
+            OpCode::BindLocal => self.bind_local(code)?,
+            OpCode::BindUpvalue => self.bind_upvalue(code)?,
+            OpCode::BindArgument => self.bind_argument(code)?,
+            OpCode::LoadUpvalue => self.load_upvalue(code)?,
+            OpCode::StoreUpvalue => self.store_upvalue(code)?,
+            OpCode::LoadArgument => self.load_argument(code)?,
+            OpCode::StoreArgument => self.store_argument(code)?,
+            OpCode::LoadFromModule => self.load_from_module(code)?,
+            OpCode::NewArray => self.new_array(code)?,
+            OpCode::NewArrayWithValues => self.new_array_with_values(code)?,
+            OpCode::ArrayGet => self.array_get()?,
+            OpCode::ArraySet => self.array_set()?,
+            OpCode::Equal => self.equal()?,
+            OpCode::NotEqual => self.not_equal()?,
+            OpCode::LessThan => self.less_than()?,
+            OpCode::LessThanEqual => self.less_than_equal()?,
+            OpCode::GreaterThan => self.greater_than()?,
+            OpCode::GreaterThanEqual => self.greater_than_equal()?,
+            OpCode::BitwiseAnd => self.bitwise_and()?,
+            OpCode::BitwiseOr => self.bitwise_or()?,
+            OpCode::BitwiseXor => self.bitwise_xor()?,
+            OpCode::BitwiseNot => self.bitwise_not()?,
+            OpCode::Not => self.not()?,
+            OpCode::LeftShift => self.left_shift()?,
+            OpCode::RightShift => self.right_shift()?,
+            OpCode::Neg => self.neg()?,
+            OpCode::InitModule => self.init_module(code),
+            OpCode::EndModule => self.end_module(),
+            OpCode::Dup => self.dup(),
+            OpCode::AllocateLocals => self.allocate_locals(code)?,
+            OpCode::Dup2 => self.dup2(),
+            OpCode::Rot3 => self.rot3(),
+            OpCode::NewRecord => self.new_record(code)?,
+            OpCode::NewVariadicFunction => self.new_function(code, true),
+            OpCode::ArrayConcat => self.array_concat()?,
+            OpCode::CallSpread => self.call_spread()?,
+            OpCode::PushHandler => self.push_handler(code),
+            OpCode::PopHandler => self.pop_handler()?,
+            OpCode::Throw => self.throw()?,
+            OpCode::NewMap => self.new_map(code)?,
+            OpCode::GetIter => self.get_iter()?,
+            OpCode::IterNext => self.iter_next(code)?,
+            OpCode::NewRange => self.new_range(false)?,
+            OpCode::NewInclusiveRange => self.new_range(true)?,
+            OpCode::Contains => self.contains()?,
+            OpCode::Interpolate => self.interpolate(code)?,
+            OpCode::MatchArray => self.match_array(code),
+            OpCode::MatchMap => self.match_map(code),
+            OpCode::MatchRecord => self.match_record(code),
+            OpCode::ArrayRest => self.array_rest(code)?,
+            OpCode::NoMatch => self.no_match()?,
+            OpCode::Pick => self.pick(code),
         }
 
-        Ok(if let Some(value) = self.stack.pop() {
-            value
-        } else {
-            Value::Null
-        })
+        Ok(true)
     }
 
-    fn const_int(&mut self, code: &Vec<u8>) {
-        let usize_bytes = self.next_usize_bytes(&code);
+    fn const_int(&mut self, code: &[u8]) {
+        let usize_bytes = self.next_usize_bytes(code);
         self.push(Value::from(i64::from_le_bytes(usize_bytes)));
     }
 
-    fn const_double(&mut self, code: &Vec<u8>) {
-        let usize_bytes = self.next_usize_bytes(&code);
+    fn const_double(&mut self, code: &[u8]) {
+        let usize_bytes = self.next_usize_bytes(code);
         self.push(Value::from(f64::from_bits(u64::from_le_bytes(usize_bytes))));
     }
 
@@ -423,17 +923,22 @@ impl<'a> Interpreter<'a> {
         self.push(Value::from(false));
     }
 
-    fn const_string(&mut self, code: &Vec<u8>) {
-        let idx = usize::from_le_bytes(self.next_usize_bytes(&code));
+    fn const_string(&mut self, code: &[u8]) {
+        let idx = usize::from_le_bytes(self.next_usize_bytes(code));
         self.push(Value::from(self.agent.string_table[idx].as_ref()));
     }
 
-    fn jump(&mut self, code: &Vec<u8>) {
-        self.ip = usize::from_le_bytes(self.next_usize_bytes(&code));
+    fn const_atom(&mut self, code: &[u8]) {
+        let idx = usize::from_le_bytes(self.next_usize_bytes(code));
+        self.push(Value::Atom(idx));
+    }
+
+    fn jump(&mut self, code: &[u8]) {
+        self.ip = usize::from_le_bytes(self.next_usize_bytes(code));
     }
 
-    fn jump_if_true(&mut self, code: &Vec<u8>) -> Result<(), String> {
-        let to = usize::from_le_bytes(self.next_usize_bytes(&code));
+    fn jump_if_true(&mut self, code: &[u8]) -> Result<(), VmError> {
+        let to = usize::from_le_bytes(self.next_usize_bytes(code));
         let cond = self.pop()?;
         if cond.is_truthy() {
             self.ip = to;
@@ -441,8 +946,8 @@ impl<'a> Interpreter<'a> {
         Ok(())
     }
 
-    fn jump_if_false(&mut self, code: &Vec<u8>) -> Result<(), String> {
-        let to = usize::from_le_bytes(self.next_usize_bytes(&code));
+    fn jump_if_false(&mut self, code: &[u8]) -> Result<(), VmError> {
+        let to = usize::from_le_bytes(self.next_usize_bytes(code));
         let cond = self.pop()?;
         if !cond.is_truthy() {
             self.ip = to;
@@ -450,46 +955,97 @@ impl<'a> Interpreter<'a> {
         Ok(())
     }
 
-    fn call(&mut self, code: &Vec<u8>) -> Result<(), String> {
+    fn call(&mut self, code: &[u8]) -> Result<(), VmError> {
+        let function = self.pop()?;
+        let num_args = usize::from_le_bytes(self.next_usize_bytes(code));
+        self.call_value(function, num_args)
+    }
+
+    fn call_spread(&mut self) -> Result<(), VmError> {
         let function = self.pop()?;
-        let num_args = usize::from_le_bytes(self.next_usize_bytes(&code));
+        let args = self.pop()?;
+
+        if let Value::Array(args) = args {
+            let num_args = args.borrow().len();
+            // arguments are pushed in reverse
+            for arg in args.borrow().iter().rev() {
+                self.push(arg.clone());
+            }
+            self.call_value(function, num_args)
+        } else {
+            Err(self.error(
+                VmError::TypeError,
+                format!(
+                    "Cannot spread non-array value {}",
+                    args.display(&self.agent)
+                ),
+            ))
+        }
+    }
+
+    fn call_value(&mut self, function: Value, num_args: usize) -> Result<(), VmError> {
         if let Value::Function(f) = &function {
             macro_rules! ensure_arity {
-                ($arity:expr, $name:expr) => {{
+                ($arity:expr, $variadic:expr, $name:expr) => {{
                     if num_args < $arity {
-                        let name = if let Some(name) = $name {
-                            self.agent.string_table[*name].as_ref()
-                        } else {
-                            "<anonymous>"
-                        };
-                        return Err(self.error(format!(
-                            "Function {} expected {} args, got {}",
-                            name, $arity, num_args
-                        )));
+                        return Err(self.error(
+                            VmError::ArityError,
+                            format!(
+                                "Function {} expected {}{} args, got {}",
+                                self.function_name(*$name),
+                                if $variadic { "at least " } else { "" },
+                                $arity,
+                                num_args
+                            ),
+                        ));
                     }
                 }};
             }
             match f.deref() {
                 FunctionValue::Builtin {
                     arity,
+                    variadic,
                     function,
                     name,
-                    ..
                 } => {
-                    ensure_arity!(*arity, name);
+                    ensure_arity!(*arity, *variadic, name);
                     let args = self.pop_and_get(num_args);
-                    let result = function(self, args)?;
+                    let result = function(self, args).map_err(|e| self.locate(e))?;
+                    self.track(&result)?;
                     self.push(result);
                 }
                 FunctionValue::User {
                     arity,
+                    variadic,
                     address,
                     name,
                     module,
                     ..
                 } => {
-                    ensure_arity!(*arity, name);
+                    ensure_arity!(*arity, *variadic, name);
+                    if self.call_stack.len() >= self.limits.max_call_depth
+                        || self.sp > self.limits.max_stack
+                    {
+                        let location = self
+                            .site_location(self.instruction_start)
+                            .map_or(String::new(), |location| format!(" ({})", location));
+                        return Err(self.error(
+                            VmError::LimitError,
+                            format!(
+                                "Stack overflow at {}{}",
+                                self.function_name(*name),
+                                location
+                            ),
+                        ));
+                    }
+                    let num_args = if *variadic {
+                        self.collect_rest_arguments(num_args, *arity);
+                        *arity + 1
+                    } else {
+                        num_args
+                    };
                     self.call_stack.push(Frame {
+                        call_site: self.instruction_start,
                         prev_ip: self.ip,
                         prev_bp: self.bp,
                         num_args,           // for cleanup
@@ -499,6 +1055,7 @@ impl<'a> Interpreter<'a> {
                         } else {
                             Some(self.bp)
                         },
+                        iteration_end: None,
                     });
                     self.bp = self.sp; // new base is at current stack index
                     self.ip = *address; // jump into function
@@ -507,48 +1064,173 @@ impl<'a> Interpreter<'a> {
             }
             Ok(())
         } else {
-            Err(self.error(format!("Value {} is not callable", function)))
+            Err(self.error(
+                VmError::TypeError,
+                format!("Value {} is not callable", function.display(&self.agent)),
+            ))
+        }
+    }
+
+    // Arguments are pushed in reverse, so the ones past the arity sit below the
+    // required ones. Replace them with a single array, which then lives in the
+    // argument slot right after the required ones.
+    // a value with its type, so that `"1"` and `1` can be told apart
+    fn describe_value(&self, value: &Value) -> String {
+        match value {
+            Value::Null => "null".to_string(),
+            Value::String(s) => format!("string {:?}", s.as_str()),
+            value => format!("{} {}", value.type_of(), value.display(&self.agent)),
+        }
+    }
+
+    fn function_name(&self, name: Option<usize>) -> &str {
+        match name {
+            Some(name) => &self.agent.string_table[name],
+            None => "<anonymous>",
         }
     }
 
-    fn return_(&mut self) -> Result<(), String> {
+    fn collect_rest_arguments(&mut self, num_args: usize, arity: usize) {
+        let start = self.sp - num_args;
+        let rest = self
+            .stack
+            .drain(start..start + num_args - arity)
+            .rev()
+            .collect::<Vec<_>>();
+        let rest = Value::from(rest);
+        self.heap.track(&rest);
+        self.stack.insert(start, rest);
+        self.sp = self.stack.len();
+    }
+
+    fn return_(&mut self) -> Result<(), VmError> {
         let retval = self.pop()?;
-        let frame = self.call_stack.pop().ok_or("Missing stack frame")?;
+        let frame = match self.call_stack.pop() {
+            Some(frame) => frame,
+            None => {
+                return Err(self.error(VmError::RuntimeError, "Missing stack frame".to_string()))
+            }
+        };
+
+        self.close_upvalues(self.bp - frame.num_args)?;
+
+        // a return from inside a try block leaves its handlers behind
+        while matches!(self.handlers.last(), Some(handler) if handler.call_depth > self.call_stack.len())
+        {
+            self.handlers.pop();
+        }
+
+        self.pop_n(frame.num_args + self.sp - self.bp);
+
+        self.bp = frame.prev_bp;
+        self.ip = frame.prev_ip;
+        match frame.iteration_end {
+            Some(end) => self.iterate_with(retval, end),
+            None => {
+                self.push(retval);
+                Ok(())
+            }
+        }
+    }
 
+    // closes every open upvalue pointing at or above the given stack index
+    fn close_upvalues(&mut self, from: usize) -> Result<(), VmError> {
         while let Some(uv) = self.agent.upvalues.pop() {
             if uv.borrow().is_open() {
                 let i = uv.borrow().stack_index();
-                if i < self.bp - frame.num_args {
+                if i < from {
                     self.agent.upvalues.push(uv);
                     break;
                 }
                 uv.borrow_mut().close(self.stack[i].clone());
             } else {
-                return Err(self.error("Had closed upvalue in agent.upvalues".to_string()));
+                return Err(self.error(
+                    VmError::RuntimeError,
+                    "Had closed upvalue in agent.upvalues".to_string(),
+                ));
             }
         }
 
-        self.pop_n(frame.num_args + self.sp - self.bp);
+        Ok(())
+    }
 
-        self.bp = frame.prev_bp;
-        self.ip = frame.prev_ip;
-        self.push(retval);
+    fn push_handler(&mut self, code: &[u8]) {
+        let address = usize::from_le_bytes(self.next_usize_bytes(code));
+        self.handlers.push(Handler {
+            address,
+            sp: self.sp,
+            bp: self.bp,
+            call_depth: self.call_stack.len(),
+        });
+    }
+
+    fn pop_handler(&mut self) -> Result<(), VmError> {
+        match self.handlers.pop() {
+            Some(_) => Ok(()),
+            None => Err(self.error(
+                VmError::RuntimeError,
+                "Missing exception handler".to_string(),
+            )),
+        }
+    }
+
+    fn throw(&mut self) -> Result<(), VmError> {
+        let value = self.pop()?;
+        let message = value.display(&self.agent).to_string();
+        Err(self.locate(VmError::Thrown(value, message.into())))
+    }
+
+    // Unwinds to the innermost handler and jumps to its catch block, or gives
+    // the error back if nothing is there to catch it.
+    fn catch(&mut self, error: VmError) -> Result<(), VmError> {
+        // a script must not be able to keep itself running
+        if let VmError::OutOfFuel(_) = error {
+            return Err(error);
+        }
+
+        let handler = match self.handlers.pop() {
+            Some(handler) => handler,
+            None => return Err(error),
+        };
+
+        self.close_upvalues(handler.sp)?;
+        self.call_stack.truncate(handler.call_depth);
+        self.pop_n(self.sp - handler.sp);
+        self.bp = handler.bp;
+        self.ip = handler.address;
+
+        let value = self.error_value(error);
+        self.push(value);
 
         Ok(())
     }
 
-    fn load_local(&mut self, code: &Vec<u8>) {
-        let usize_bytes = self.next_usize_bytes(&code);
+    // Thrown values are caught as they are. Errors raised by the VM or a
+    // builtin are caught as [kind, message], e.g. [:type_error, "..."].
+    fn error_value(&mut self, error: VmError) -> Value {
+        match error {
+            VmError::Thrown(value, _) => value,
+            error => {
+                let kind = self.agent.intern_string(error.atom_name());
+                let value = Value::from(vec![Value::Atom(kind), Value::from(error.message())]);
+                self.heap.track(&value);
+                value
+            }
+        }
+    }
+
+    fn load_local(&mut self, code: &[u8]) {
+        let usize_bytes = self.next_usize_bytes(code);
         self.push(self.local(usize::from_le_bytes(usize_bytes)).clone());
     }
 
-    fn store_local(&mut self, code: &Vec<u8>) {
-        let idx = usize::from_le_bytes(self.next_usize_bytes(&code));
+    fn store_local(&mut self, code: &[u8]) {
+        let idx = usize::from_le_bytes(self.next_usize_bytes(code));
         self.set_local(idx, self.top().clone());
     }
 
-    fn load_global(&mut self, code: &Vec<u8>) -> Result<(), String> {
-        let usize_bytes = self.next_usize_bytes(&code);
+    fn load_global(&mut self, code: &[u8]) -> Result<(), VmError> {
+        let usize_bytes = self.next_usize_bytes(code);
         let id = usize::from_le_bytes(usize_bytes);
 
         if let Some(module) = self.current_module_mut() {
@@ -557,18 +1239,18 @@ impl<'a> Interpreter<'a> {
                 self.push(new_val);
                 Ok(())
             } else {
-                Err(self.error(format!(
-                    "ReferenceError: {} is not defined",
-                    self.agent.string_table[id]
-                )))
+                Err(self.error(
+                    VmError::ReferenceError,
+                    format!("{} is not defined", self.agent.string_table[id]),
+                ))
             }
         } else {
             unreachable!();
         }
     }
 
-    fn declare_global(&mut self, code: &Vec<u8>) {
-        let id = usize::from_le_bytes(self.next_usize_bytes(&code));
+    fn declare_global(&mut self, code: &[u8]) {
+        let id = usize::from_le_bytes(self.next_usize_bytes(code));
 
         if let Some(module) = self.current_module_mut() {
             module.global_scope.insert(id, Value::Null);
@@ -577,29 +1259,31 @@ impl<'a> Interpreter<'a> {
         }
     }
 
-    fn store_global(&mut self, code: &Vec<u8>) -> Result<(), String> {
-        let id = usize::from_le_bytes(self.next_usize_bytes(&code));
+    fn store_global(&mut self, code: &[u8]) -> Result<(), VmError> {
+        let id = usize::from_le_bytes(self.next_usize_bytes(code));
         let top = self.top().clone();
 
         if let Some(module) = self.current_module_mut() {
-            if module.global_scope.contains_key(&id) {
-                module.global_scope.insert(id, top);
+            if let std::collections::hash_map::Entry::Occupied(mut e) =
+                module.global_scope.entry(id)
+            {
+                e.insert(top);
                 Ok(())
             } else {
-                Err(self.error(format!(
-                    "ReferenceError: {} is not defined",
-                    self.agent.string_table[id]
-                )))
+                Err(self.error(
+                    VmError::ReferenceError,
+                    format!("{} is not defined", self.agent.string_table[id]),
+                ))
             }
         } else {
             unreachable!();
         }
     }
 
-    fn new_function(&mut self, code: &Vec<u8>) {
-        let name = usize::from_le_bytes(self.next_usize_bytes(&code));
-        let arity = usize::from_le_bytes(self.next_usize_bytes(&code));
-        let address = usize::from_le_bytes(self.next_usize_bytes(&code));
+    fn new_function(&mut self, code: &[u8], variadic: bool) {
+        let name = usize::from_le_bytes(self.next_usize_bytes(code));
+        let arity = usize::from_le_bytes(self.next_usize_bytes(code));
+        let address = usize::from_le_bytes(self.next_usize_bytes(code));
         let module = if let Some(module) = self.current_module_mut() {
             module.name()
         } else {
@@ -607,20 +1291,17 @@ impl<'a> Interpreter<'a> {
         };
 
         self.push(Value::from(FunctionValue::User {
-            name: if name == std::usize::MAX {
-                None
-            } else {
-                Some(name)
-            },
+            name: if name == usize::MAX { None } else { Some(name) },
             address,
             arity,
+            variadic,
             module,
             upvalues: Vec::new(),
         }));
     }
 
-    fn bind_local(&mut self, code: &Vec<u8>) -> Result<(), String> {
-        let idx = self.locals_index() + usize::from_le_bytes(self.next_usize_bytes(&code));
+    fn bind_local(&mut self, code: &[u8]) -> Result<(), VmError> {
+        let idx = self.locals_index() + usize::from_le_bytes(self.next_usize_bytes(code));
         let mut func = self.pop()?;
 
         if let Value::Function(function_value) = &mut func {
@@ -633,25 +1314,29 @@ impl<'a> Interpreter<'a> {
                 {
                     upvalue
                 } else {
-                    self.agent
-                        .upvalues
-                        .push(Rc::new(RefCell::new(Upvalue::new(idx))));
+                    let upvalue = Rc::new(RefCell::new(Upvalue::new(idx)));
+                    self.heap.track_upvalue(&upvalue);
+                    self.agent.upvalues.push(upvalue);
                     self.agent.upvalues.last().unwrap()
                 };
                 upvalues.push(upvalue.clone());
                 self.push(func);
+                self.collect_if_due();
 
                 Ok(())
             } else {
                 unreachable!();
             }
         } else {
-            Err(self.error("Cannot bind local to non-user function".to_string()))
+            Err(self.error(
+                VmError::RuntimeError,
+                "Cannot bind local to non-user function".to_string(),
+            ))
         }
     }
 
-    fn bind_upvalue(&mut self, code: &Vec<u8>) -> Result<(), String> {
-        let idx = usize::from_le_bytes(self.next_usize_bytes(&code));
+    fn bind_upvalue(&mut self, code: &[u8]) -> Result<(), VmError> {
+        let idx = usize::from_le_bytes(self.next_usize_bytes(code));
         let mut func = self.pop()?;
 
         if let Value::Function(function_value) = &mut func {
@@ -662,7 +1347,13 @@ impl<'a> Interpreter<'a> {
                         ..
                     } = function_value.deref()
                     {
-                        upvalues.push(efn_upvalues[idx].clone());
+                        let upvalue = efn_upvalues.get(idx).cloned().ok_or_else(|| {
+                            self.error(
+                                VmError::RuntimeError,
+                                format!("Upvalue {} is out of bounds", idx),
+                            )
+                        })?;
+                        upvalues.push(upvalue);
                     } else {
                         unreachable!();
                     }
@@ -675,12 +1366,15 @@ impl<'a> Interpreter<'a> {
                 unreachable!();
             }
         } else {
-            Err(self.error("Cannot bind upvalue to non-user function".to_string()))
+            Err(self.error(
+                VmError::RuntimeError,
+                "Cannot bind upvalue to non-user function".to_string(),
+            ))
         }
     }
 
-    fn bind_argument(&mut self, code: &Vec<u8>) -> Result<(), String> {
-        let idx = usize::from_le_bytes(self.next_usize_bytes(&code));
+    fn bind_argument(&mut self, code: &[u8]) -> Result<(), VmError> {
+        let idx = usize::from_le_bytes(self.next_usize_bytes(code));
         let mut func = self.pop()?;
 
         if let Value::Function(function_value) = &mut func {
@@ -695,13 +1389,14 @@ impl<'a> Interpreter<'a> {
                     {
                         upvalue
                     } else {
-                        self.agent
-                            .upvalues
-                            .push(Rc::new(RefCell::new(Upvalue::new(idx))));
+                        let upvalue = Rc::new(RefCell::new(Upvalue::new(idx)));
+                        self.heap.track_upvalue(&upvalue);
+                        self.agent.upvalues.push(upvalue);
                         self.agent.upvalues.last().unwrap()
                     };
                     upvalues.push(upvalue.clone());
                     self.push(func);
+                    self.collect_if_due();
                     Ok(())
                 } else {
                     unreachable!();
@@ -710,15 +1405,24 @@ impl<'a> Interpreter<'a> {
                 unreachable!();
             }
         } else {
-            Err(self.error("Cannot bind argument to non-user function".to_string()))
+            Err(self.error(
+                VmError::RuntimeError,
+                "Cannot bind argument to non-user function".to_string(),
+            ))
         }
     }
 
-    fn load_upvalue(&mut self, code: &Vec<u8>) -> Result<(), String> {
-        let idx = usize::from_le_bytes(self.next_usize_bytes(&code));
+    fn load_upvalue(&mut self, code: &[u8]) -> Result<(), VmError> {
+        let idx = usize::from_le_bytes(self.next_usize_bytes(code));
         let idx_or_value = if let Value::Function(function_value) = self.executing_function()? {
             if let FunctionValue::User { upvalues, .. } = function_value.deref() {
-                let upvalue = (*upvalues[idx]).borrow();
+                let upvalue = upvalues.get(idx).ok_or_else(|| {
+                    self.error(
+                        VmError::RuntimeError,
+                        format!("Upvalue {} is out of bounds", idx),
+                    )
+                })?;
+                let upvalue = upvalue.borrow();
                 if upvalue.is_open() {
                     Ok(upvalue.stack_index())
                 } else {
@@ -740,11 +1444,16 @@ impl<'a> Interpreter<'a> {
         Ok(())
     }
 
-    fn store_upvalue(&mut self, code: &Vec<u8>) -> Result<(), String> {
-        let idx = usize::from_le_bytes(self.next_usize_bytes(&code));
+    fn store_upvalue(&mut self, code: &[u8]) -> Result<(), VmError> {
+        let idx = usize::from_le_bytes(self.next_usize_bytes(code));
         if let Value::Function(function_value) = self.executing_function()? {
             if let FunctionValue::User { upvalues, .. } = function_value.deref() {
-                let upvalue = &upvalues[idx];
+                let upvalue = upvalues.get(idx).ok_or_else(|| {
+                    self.error(
+                        VmError::RuntimeError,
+                        format!("Upvalue {} is out of bounds", idx),
+                    )
+                })?;
                 if upvalue.borrow().is_open() {
                     let idx = upvalue.borrow().stack_index();
                     self.stack[idx] = self.top().clone();
@@ -760,90 +1469,582 @@ impl<'a> Interpreter<'a> {
         }
     }
 
-    fn load_argument(&mut self, code: &Vec<u8>) -> Result<(), String> {
-        let idx = usize::from_le_bytes(self.next_usize_bytes(&code));
+    fn load_argument(&mut self, code: &[u8]) -> Result<(), VmError> {
+        let idx = usize::from_le_bytes(self.next_usize_bytes(code));
         self.push(self.argument(idx)?.clone());
         Ok(())
     }
 
-    fn store_argument(&mut self, code: &Vec<u8>) -> Result<(), String> {
-        let idx = usize::from_le_bytes(self.next_usize_bytes(&code));
+    fn store_argument(&mut self, code: &[u8]) -> Result<(), VmError> {
+        let idx = usize::from_le_bytes(self.next_usize_bytes(code));
         self.set_argument(idx, self.top().clone())?;
         Ok(())
     }
 
-    fn load_from_module(&mut self, code: &Vec<u8>) -> Result<(), String> {
-        let module_name = usize::from_le_bytes(self.next_usize_bytes(&code));
-        let export_name = usize::from_le_bytes(self.next_usize_bytes(&code));
+    fn load_from_module(&mut self, code: &[u8]) -> Result<(), VmError> {
+        let module_name = usize::from_le_bytes(self.next_usize_bytes(code));
+        let export_name = usize::from_le_bytes(self.next_usize_bytes(code));
 
         self.push(
             self.modules
                 .get(&module_name)
-                .ok_or_else(|| format!("Unknown module {}", self.agent.string_table[module_name]))?
-                .resolve_export(self.agent, export_name)?,
+                .ok_or_else(|| {
+                    self.error(
+                        VmError::ReferenceError,
+                        format!("Unknown module {}", self.agent.string_table[module_name]),
+                    )
+                })?
+                .resolve_export(&self.agent, export_name)
+                .map_err(|e| self.locate(e))?,
         );
         Ok(())
     }
 
-    fn new_array(&mut self, code: &Vec<u8>) {
-        let len = usize::from_le_bytes(self.next_usize_bytes(&code));
-        self.push(Value::from(vec![Value::Null; len]));
+    fn new_array(&mut self, code: &[u8]) -> Result<(), VmError> {
+        let len = usize::from_le_bytes(self.next_usize_bytes(code));
+        self.check_allocation(len)?;
+        let array = Value::from(vec![Value::Null; len]);
+        self.track(&array)?;
+        self.push(array);
+        Ok(())
     }
 
-    fn new_array_with_values(&mut self, code: &Vec<u8>) -> Result<(), String> {
-        let num_values = usize::from_le_bytes(self.next_usize_bytes(&code));
+    fn new_array_with_values(&mut self, code: &[u8]) -> Result<(), VmError> {
+        let num_values = usize::from_le_bytes(self.next_usize_bytes(code));
         let mut values = Vec::with_capacity(num_values);
         for _ in 0..num_values {
             values.push(self.pop()?);
         }
-        self.push(Value::from(values.into_iter().rev().collect::<Vec<_>>()));
+        let array = Value::from(values.into_iter().rev().collect::<Vec<_>>());
+        self.track(&array)?;
+        self.push(array);
         Ok(())
     }
 
-    fn array_get(&mut self) -> Result<(), String> {
-        let idx = self.pop()?;
-        let array = self.top();
+    fn new_record(&mut self, code: &[u8]) -> Result<(), VmError> {
+        let spec = usize::from_le_bytes(self.next_usize_bytes(code));
+        let num_fields = self.agent.records[spec].fields.len();
+        let mut fields = Vec::with_capacity(num_fields);
+        for _ in 0..num_fields {
+            fields.push(self.pop()?);
+        }
+        fields.reverse();
+        let record = Value::Record(Rc::new(RecordValue::new(spec, fields)));
+        self.track(&record)?;
+        self.push(record);
+        Ok(())
+    }
 
-        if let Value::Integer(idx) = idx {
-            if let Value::Array(array) = array {
-                let idx = idx as usize;
-                if array.borrow().len() > idx {
-                    let value = array.borrow()[idx].clone();
-                    self.set_top(value);
-                    Ok(())
-                } else {
-                    Err(self.error(format!("Index {} is out of bounds", idx)))
-                }
+    fn new_map(&mut self, code: &[u8]) -> Result<(), VmError> {
+        let len = usize::from_le_bytes(self.next_usize_bytes(code));
+        let mut entries = Vec::with_capacity(len);
+        for _ in 0..len {
+            let value = self.pop()?;
+            let key = self.pop()?;
+            entries.push((key, value));
+        }
+
+        let mut map = HashMap::with_capacity(len);
+        // later entries win over earlier ones with the same key
+        for (key, value) in entries.into_iter().rev() {
+            map.insert(self.map_key(&key)?, value);
+        }
+
+        let map = Value::from(map);
+        self.track(&map)?;
+        self.push(map);
+        Ok(())
+    }
+
+    // An iterator is two values on the stack: what's being iterated, and
+    // where it's at. That's an index for arrays and the keys that are left
+    // for maps. Strings are iterated as arrays of their characters.
+    fn get_iter(&mut self) -> Result<(), VmError> {
+        let iterable = self.pop()?;
+        let (iterable, position) = match iterable {
+            Value::Array(_) | Value::Function(_) | Value::Range(_) => (iterable, Value::from(0)),
+            Value::String(s) => {
+                let chars = s
+                    .chars()
+                    .map(|c| Value::from(c.to_string()))
+                    .collect::<Vec<_>>();
+                (Value::from(chars), Value::from(0))
+            }
+            Value::Map(map) => {
+                // reversed, so that popping them goes in order
+                let keys = sorted_entries(&map.borrow())
+                    .into_iter()
+                    .rev()
+                    .map(|(key, _)| Value::from(key.clone()))
+                    .collect::<Vec<_>>();
+                (Value::Map(map), Value::from(keys))
+            }
+            _ => {
+                return Err(self.error(
+                    VmError::TypeError,
+                    format!("Value {} is not iterable", iterable.display(&self.agent)),
+                ))
+            }
+        };
+
+        self.push(iterable);
+        self.push(position);
+        Ok(())
+    }
+
+    // Pushes the next key and value of the iterator on top of the stack, or
+    // jumps to the end of the loop if there are none left. A function is
+    // called for every value until it returns null.
+    fn iter_next(&mut self, code: &[u8]) -> Result<(), VmError> {
+        let end = usize::from_le_bytes(self.next_usize_bytes(code));
+        let iterable = self.stack[self.sp - 2].clone();
+        let position = self.top().clone();
+
+        let next = match (&iterable, &position) {
+            (Value::Array(array), Value::Integer(i)) => {
+                let value = array.borrow().get(*i as usize).cloned();
+                self.set_top(Value::from(i + 1));
+                value.map(|value| (Value::from(*i), value))
+            }
+            (Value::Range(range), Value::Integer(i)) => {
+                self.set_top(Value::from(i + 1));
+                range
+                    .get(*i as usize)
+                    .map(|value| (Value::from(*i), Value::from(value)))
+            }
+            // The function is called like any other, so it can run out of
+            // fuel or throw halfway through. The loop goes on once it returns.
+            (Value::Function(_), Value::Integer(_)) => {
+                let depth = self.call_stack.len();
+                self.call_value(iterable, 0)?;
+                if self.call_stack.len() > depth {
+                    if let Some(frame) = self.call_stack.last_mut() {
+                        frame.iteration_end = Some(end);
+                    }
+                    return Ok(());
+                }
+                // a builtin has returned already
+                let value = self.pop()?;
+                return self.iterate_with(value, end);
+            }
+            // keys that were deleted during the loop are skipped
+            (Value::Map(map), Value::Array(keys)) => loop {
+                let key = match keys.borrow_mut().pop() {
+                    Some(key) => key,
+                    None => break None,
+                };
+                let value = MapKey::from_value(&key).and_then(|k| map.borrow().get(&k).cloned());
+                if let Some(value) = value {
+                    break Some((key, value));
+                }
+            },
+            _ => {
+                return Err(self.error(
+                    VmError::RuntimeError,
+                    "Invalid iterator on the stack".to_string(),
+                ))
+            }
+        };
+
+        match next {
+            Some((key, value)) => {
+                self.push(key);
+                self.push(value);
+            }
+            None => self.ip = end,
+        }
+        Ok(())
+    }
+
+    // Takes a step of a loop over a function with the value it returned,
+    // which ends the loop if it's null.
+    fn iterate_with(&mut self, value: Value, end: usize) -> Result<(), VmError> {
+        let i = match self.top() {
+            Value::Integer(i) => *i,
+            _ => {
+                return Err(self.error(
+                    VmError::RuntimeError,
+                    "Invalid iterator on the stack".to_string(),
+                ))
+            }
+        };
+        self.set_top(Value::from(i + 1));
+
+        match value {
+            Value::Null => self.ip = end,
+            value => {
+                self.push(Value::from(i));
+                self.push(value);
+            }
+        }
+        Ok(())
+    }
+
+    fn has_strings_on_top(&self) -> bool {
+        matches!(
+            (&self.stack[self.sp - 2], self.top()),
+            (Value::String(_), Value::String(_))
+        )
+    }
+
+    fn concat(&mut self) -> Result<(), VmError> {
+        if let (Value::String(a), Value::String(b)) = (&self.stack[self.sp - 2], self.top()) {
+            let len = a.len().saturating_add(b.len());
+            self.check_allocation(len)?;
+            let mut s = String::with_capacity(len);
+            s.push_str(a);
+            s.push_str(b);
+            self.pop_n(2);
+            self.push(Value::from(s));
+        }
+        Ok(())
+    }
+
+    // joins the values on top of the stack into one string, formatting the
+    // ones that aren't strings like tostring does
+    fn interpolate(&mut self, code: &[u8]) -> Result<(), VmError> {
+        let count = usize::from_le_bytes(self.next_usize_bytes(code));
+        let parts = self.stack[self.sp - count..]
+            .iter()
+            .map(|value| match value {
+                Value::String(part) => Cow::Borrowed(part.as_str()),
+                value => Cow::Owned(value.display(&self.agent).to_string()),
+            })
+            .collect::<Vec<_>>();
+        self.check_allocation(parts.iter().map(|part| part.len()).sum())?;
+        let s = parts.concat();
+
+        self.pop_n(count);
+        self.push(Value::from(s));
+        Ok(())
+    }
+
+    fn new_range(&mut self, inclusive: bool) -> Result<(), VmError> {
+        let end = self.pop()?;
+        let start = self.pop()?;
+
+        if let (Value::Integer(start), Value::Integer(end)) = (&start, &end) {
+            self.push(Value::Range(RangeValue::new(*start, *end, inclusive)));
+            Ok(())
+        } else {
+            Err(self.error(
+                VmError::TypeError,
+                format!(
+                    "Range bounds must be integers, got {} and {}",
+                    start.type_of(),
+                    end.type_of()
+                ),
+            ))
+        }
+    }
+
+    // `value in collection`, where a map contains its keys and a string its
+    // substrings
+    fn contains(&mut self) -> Result<(), VmError> {
+        let collection = self.pop()?;
+        let value = self.pop()?;
+
+        let contains = match (&collection, &value) {
+            (Value::Range(range), Value::Integer(n)) => range.contains(*n),
+            (Value::Range(_), _) => false,
+            (Value::Array(array), _) => array.borrow().contains(&value),
+            (Value::Map(map), _) => match MapKey::from_value(&value) {
+                Some(key) => map.borrow().contains_key(&key),
+                None => false,
+            },
+            (Value::String(s), Value::String(substring)) => s.contains(substring.as_str()),
+            _ => {
+                return Err(self.error(
+                    VmError::TypeError,
+                    format!(
+                        "Can't look for {} in {}",
+                        value.type_of(),
+                        collection.type_of()
+                    ),
+                ))
+            }
+        };
+
+        self.push(Value::from(contains));
+        Ok(())
+    }
+
+    // The tests of a match pattern replace the value with whether it has the
+    // right shape. They never fail, so that a pattern can be tried on
+    // anything.
+    fn match_array(&mut self, code: &[u8]) {
+        let len = usize::from_le_bytes(self.next_usize_bytes(code));
+        let rest = usize::from_le_bytes(self.next_usize_bytes(code)) != 0;
+        let matches = match self.top() {
+            Value::Array(array) => {
+                let actual = array.borrow().len();
+                actual == len || (rest && actual > len)
+            }
+            _ => false,
+        };
+        self.set_top(Value::from(matches));
+    }
+
+    fn match_map(&mut self, code: &[u8]) {
+        let len = usize::from_le_bytes(self.next_usize_bytes(code));
+        let matches = match &self.stack[self.sp - len - 1] {
+            Value::Map(map) => {
+                let map = map.borrow();
+                self.stack[self.sp - len..self.sp]
+                    .iter()
+                    .all(|key| match MapKey::from_value(key) {
+                        Some(key) => map.contains_key(&key),
+                        None => false,
+                    })
+            }
+            _ => false,
+        };
+        self.pop_n(len + 1);
+        self.push(Value::from(matches));
+    }
+
+    fn match_record(&mut self, code: &[u8]) {
+        let spec = usize::from_le_bytes(self.next_usize_bytes(code));
+        let matches = matches!(self.top(), Value::Record(record) if record.spec == spec);
+        self.set_top(Value::from(matches));
+    }
+
+    // the elements from `start` on, for the `...rest` of an array pattern
+    fn array_rest(&mut self, code: &[u8]) -> Result<(), VmError> {
+        let start = usize::from_le_bytes(self.next_usize_bytes(code));
+        let rest = match self.top() {
+            Value::Array(array) => {
+                let array = array.borrow();
+                Value::from(array[start.min(array.len())..].to_vec())
+            }
+            value => {
+                return Err(self.error(
+                    VmError::TypeError,
+                    format!("Expected array, got {}", value.type_of()),
+                ))
+            }
+        };
+        self.track(&rest)?;
+        self.set_top(rest);
+        Ok(())
+    }
+
+    fn no_match(&mut self) -> Result<(), VmError> {
+        let value = self.pop()?;
+        Err(self.error(
+            VmError::RuntimeError,
+            format!("No pattern matches {}", value.display(&self.agent)),
+        ))
+    }
+
+    fn map_key(&self, key: &Value) -> Result<MapKey, VmError> {
+        MapKey::from_value(key).ok_or_else(|| {
+            self.error(
+                VmError::TypeError,
+                format!(
+                    "Value {} can't be used as a map key",
+                    key.display(&self.agent)
+                ),
+            )
+        })
+    }
+
+    fn array_concat(&mut self) -> Result<(), VmError> {
+        let right = self.pop()?;
+        let left = self.pop()?;
+
+        match (&left, &right) {
+            (Value::Array(left), Value::Array(right)) => {
+                self.check_allocation(left.borrow().len() + right.borrow().len())?;
+                let values = left
+                    .borrow()
+                    .iter()
+                    .chain(right.borrow().iter())
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let array = Value::from(values);
+                self.track(&array)?;
+                self.push(array);
+                Ok(())
+            }
+            (Value::Array(_), value) | (value, _) => Err(self.error(
+                VmError::TypeError,
+                format!(
+                    "Cannot spread non-array value {}",
+                    value.display(&self.agent)
+                ),
+            )),
+        }
+    }
+
+    fn array_get(&mut self) -> Result<(), VmError> {
+        let idx = self.pop()?;
+
+        // a key that isn't in a map reads as null
+        if let Value::Map(map) = self.top().clone() {
+            let key = self.map_key(&idx)?;
+            let value = map.borrow().get(&key).cloned().unwrap_or(Value::Null);
+            self.set_top(value);
+            return Ok(());
+        }
+
+        if let Value::String(s) = self.top().clone() {
+            let value = self.string_get(&s, &idx)?;
+            self.set_top(value);
+            return Ok(());
+        }
+
+        // a range index copies out a slice
+        if let (Value::Array(array), Value::Range(range)) = (self.top().clone(), &idx) {
+            let slice = {
+                let array = array.borrow();
+                match range.slice_bounds(array.len()) {
+                    Some((start, end)) => array[start..end].to_vec(),
+                    None => {
+                        return Err(self.error(
+                            VmError::IndexError,
+                            format!(
+                                "Range {} is out of bounds",
+                                Value::Range(*range).display(&self.agent)
+                            ),
+                        ))
+                    }
+                }
+            };
+            let slice = Value::from(slice);
+            self.track(&slice)?;
+            self.set_top(slice);
+            return Ok(());
+        }
+
+        let array = self.top();
+        if let Value::Integer(idx) = idx {
+            // a negative index is out of bounds too
+            let slot = usize::try_from(idx).ok();
+            // records are laid out like arrays, so the fields of a record
+            // whose layout is known can be read by slot
+            let value = match array {
+                Value::Array(array) => slot.and_then(|slot| array.borrow().get(slot).cloned()),
+                Value::Record(record) => {
+                    slot.and_then(|slot| record.fields.borrow().get(slot).cloned())
+                }
+                _ => {
+                    return Err(self.error(
+                        VmError::TypeError,
+                        "Trying to access index of non-array".to_string(),
+                    ))
+                }
+            };
+
+            if let Some(value) = value {
+                self.set_top(value);
+                Ok(())
             } else {
-                Err(self.error("Trying to access index of non-array".to_string()))
+                Err(self.error(
+                    VmError::IndexError,
+                    format!("Index {} is out of bounds", idx),
+                ))
             }
         } else {
-            Err(self.error("Array index must be an integer".to_string()))
+            Err(self.error(
+                VmError::TypeError,
+                "Array index must be an integer".to_string(),
+            ))
+        }
+    }
+
+    // Strings are indexed by character, and a range index gives the
+    // characters in it.
+    fn string_get(&self, s: &str, idx: &Value) -> Result<Value, VmError> {
+        match idx {
+            Value::Integer(i) => usize::try_from(*i)
+                .ok()
+                .and_then(|i| s.chars().nth(i))
+                .map(|c| Value::from(c.to_string()))
+                .ok_or_else(|| {
+                    self.error(
+                        VmError::IndexError,
+                        format!(
+                            "Index {} is out of bounds for a string of length {}",
+                            i,
+                            s.chars().count()
+                        ),
+                    )
+                }),
+            Value::Range(range) => {
+                let len = s.chars().count();
+                match range.slice_bounds(len) {
+                    Some((start, end)) => Ok(Value::from(
+                        s.chars().skip(start).take(end - start).collect::<String>(),
+                    )),
+                    None => Err(self.error(
+                        VmError::IndexError,
+                        format!(
+                            "Range {} is out of bounds for a string of length {}",
+                            idx, len
+                        ),
+                    )),
+                }
+            }
+            _ => Err(self.error(
+                VmError::TypeError,
+                format!(
+                    "String index must be an integer or a range, got {}",
+                    idx.type_of()
+                ),
+            )),
         }
     }
 
-    fn array_set(&mut self) -> Result<(), String> {
+    fn array_set(&mut self) -> Result<(), VmError> {
         let idx = self.pop()?;
         let array = self.pop()?;
 
+        if let Value::Map(map) = &array {
+            let key = self.map_key(&idx)?;
+            if !map.borrow().contains_key(&key) {
+                self.track_growth(&array, 1)?;
+            }
+            map.borrow_mut().insert(key, self.top().clone());
+            return Ok(());
+        }
+
+        if let Value::String(_) = &array {
+            return Err(self.error(VmError::TypeError, "Strings can't be modified".to_string()));
+        }
+
         if let Value::Integer(idx) = idx {
-            if let Value::Array(array) = array {
-                let idx = idx as usize;
-                if array.borrow().len() > idx {
-                    array.borrow_mut()[idx] = self.top().clone();
-                    Ok(())
-                } else {
-                    Err(self.error(format!("Index {} is out of bounds", idx)))
+            let mut slots = match &array {
+                Value::Array(array) => RefMut::map(array.borrow_mut(), |array| &mut array[..]),
+                Value::Record(record) => {
+                    RefMut::map(record.fields.borrow_mut(), |fields| &mut fields[..])
+                }
+                _ => {
+                    return Err(self.error(
+                        VmError::TypeError,
+                        "Trying to set index of non-array".to_string(),
+                    ))
                 }
+            };
+
+            if let Some(slot) = usize::try_from(idx).ok().and_then(|idx| slots.get_mut(idx)) {
+                *slot = self.top().clone();
+                Ok(())
             } else {
-                Err(self.error("Trying to set index of non-array".to_string()))
+                Err(self.error(
+                    VmError::IndexError,
+                    format!("Index {} is out of bounds", idx),
+                ))
             }
         } else {
-            Err(self.error("Array index must be an integer".to_string()))
+            Err(self.error(
+                VmError::TypeError,
+                "Array index must be an integer".to_string(),
+            ))
         }
     }
 
-    fn equal(&mut self) -> Result<(), String> {
+    fn equal(&mut self) -> Result<(), VmError> {
         let right = self.pop()?;
         let left = self.top();
         let result = Value::from(*left == right);
@@ -852,7 +2053,7 @@ impl<'a> Interpreter<'a> {
         Ok(())
     }
 
-    fn not_equal(&mut self) -> Result<(), String> {
+    fn not_equal(&mut self) -> Result<(), VmError> {
         let right = self.pop()?;
         let left = self.top();
         let result = Value::from(*left != right);
@@ -861,7 +2062,7 @@ impl<'a> Interpreter<'a> {
         Ok(())
     }
 
-    fn less_than(&mut self) -> Result<(), String> {
+    fn less_than(&mut self) -> Result<(), VmError> {
         let right = self.pop()?;
         let left = self.top();
         let result = Value::from(*left < right);
@@ -870,7 +2071,7 @@ impl<'a> Interpreter<'a> {
         Ok(())
     }
 
-    fn less_than_equal(&mut self) -> Result<(), String> {
+    fn less_than_equal(&mut self) -> Result<(), VmError> {
         let right = self.pop()?;
         let left = self.top();
         let result = Value::from(*left <= right);
@@ -879,7 +2080,7 @@ impl<'a> Interpreter<'a> {
         Ok(())
     }
 
-    fn greater_than(&mut self) -> Result<(), String> {
+    fn greater_than(&mut self) -> Result<(), VmError> {
         let right = self.pop()?;
         let left = self.top();
         let result = Value::from(*left > right);
@@ -888,7 +2089,7 @@ impl<'a> Interpreter<'a> {
         Ok(())
     }
 
-    fn greater_than_equal(&mut self) -> Result<(), String> {
+    fn greater_than_equal(&mut self) -> Result<(), VmError> {
         let right = self.pop()?;
         let left = self.top();
         let result = Value::from(*left >= right);
@@ -897,7 +2098,7 @@ impl<'a> Interpreter<'a> {
         Ok(())
     }
 
-    fn bitwise_and(&mut self) -> Result<(), String> {
+    fn bitwise_and(&mut self) -> Result<(), VmError> {
         let right = self.pop()?;
         let left = self.top();
 
@@ -907,14 +2108,20 @@ impl<'a> Interpreter<'a> {
                 self.set_top(result);
                 Ok(())
             } else {
-                Err(self.error("Bitwise operations only support integers".to_string()))
+                Err(self.error(
+                    VmError::TypeError,
+                    "Bitwise operations only support integers".to_string(),
+                ))
             }
         } else {
-            Err(self.error("Bitwise operations only support integers".to_string()))
+            Err(self.error(
+                VmError::TypeError,
+                "Bitwise operations only support integers".to_string(),
+            ))
         }
     }
 
-    fn bitwise_or(&mut self) -> Result<(), String> {
+    fn bitwise_or(&mut self) -> Result<(), VmError> {
         let right = self.pop()?;
         let left = self.top();
 
@@ -924,14 +2131,20 @@ impl<'a> Interpreter<'a> {
                 self.set_top(result);
                 Ok(())
             } else {
-                Err(self.error("Bitwise operations only support integers".to_string()))
+                Err(self.error(
+                    VmError::TypeError,
+                    "Bitwise operations only support integers".to_string(),
+                ))
             }
         } else {
-            Err(self.error("Bitwise operations only support integers".to_string()))
+            Err(self.error(
+                VmError::TypeError,
+                "Bitwise operations only support integers".to_string(),
+            ))
         }
     }
 
-    fn bitwise_xor(&mut self) -> Result<(), String> {
+    fn bitwise_xor(&mut self) -> Result<(), VmError> {
         let right = self.pop()?;
         let left = self.top();
 
@@ -941,14 +2154,20 @@ impl<'a> Interpreter<'a> {
                 self.set_top(result);
                 Ok(())
             } else {
-                Err(self.error("Bitwise operations only support integers".to_string()))
+                Err(self.error(
+                    VmError::TypeError,
+                    "Bitwise operations only support integers".to_string(),
+                ))
             }
         } else {
-            Err(self.error("Bitwise operations only support integers".to_string()))
+            Err(self.error(
+                VmError::TypeError,
+                "Bitwise operations only support integers".to_string(),
+            ))
         }
     }
 
-    fn bitwise_not(&mut self) -> Result<(), String> {
+    fn bitwise_not(&mut self) -> Result<(), VmError> {
         let right = self.top();
 
         if let Value::Integer(right) = right {
@@ -956,18 +2175,21 @@ impl<'a> Interpreter<'a> {
             self.set_top(result);
             Ok(())
         } else {
-            Err(self.error("Bitwise operations only support integers".to_string()))
+            Err(self.error(
+                VmError::TypeError,
+                "Bitwise operations only support integers".to_string(),
+            ))
         }
     }
 
-    fn not(&mut self) -> Result<(), String> {
+    fn not(&mut self) -> Result<(), VmError> {
         let result = Value::from(!self.top().is_truthy());
 
         self.set_top(result);
         Ok(())
     }
 
-    fn left_shift(&mut self) -> Result<(), String> {
+    fn left_shift(&mut self) -> Result<(), VmError> {
         let right = self.pop()?;
         let left = self.top();
 
@@ -977,14 +2199,20 @@ impl<'a> Interpreter<'a> {
                 self.set_top(result);
                 Ok(())
             } else {
-                Err(self.error("Bitwise operations only support integers".to_string()))
+                Err(self.error(
+                    VmError::TypeError,
+                    "Bitwise operations only support integers".to_string(),
+                ))
             }
         } else {
-            Err(self.error("Bitwise operations only support integers".to_string()))
+            Err(self.error(
+                VmError::TypeError,
+                "Bitwise operations only support integers".to_string(),
+            ))
         }
     }
 
-    fn right_shift(&mut self) -> Result<(), String> {
+    fn right_shift(&mut self) -> Result<(), VmError> {
         let right = self.pop()?;
         let left = self.top();
 
@@ -994,14 +2222,20 @@ impl<'a> Interpreter<'a> {
                 self.set_top(result);
                 Ok(())
             } else {
-                Err(self.error("Bitwise operations only support integers".to_string()))
+                Err(self.error(
+                    VmError::TypeError,
+                    "Bitwise operations only support integers".to_string(),
+                ))
             }
         } else {
-            Err(self.error("Bitwise operations only support integers".to_string()))
+            Err(self.error(
+                VmError::TypeError,
+                "Bitwise operations only support integers".to_string(),
+            ))
         }
     }
 
-    fn neg(&mut self) -> Result<(), String> {
+    fn neg(&mut self) -> Result<(), VmError> {
         let right = self.top();
 
         if let Value::Integer(right) = right {
@@ -1009,18 +2243,24 @@ impl<'a> Interpreter<'a> {
             self.set_top(result);
             Ok(())
         } else {
-            Err(self.error("Expected integer in negation expression".to_string()))
+            Err(self.error(
+                VmError::TypeError,
+                "Expected integer in negation expression".to_string(),
+            ))
         }
     }
 
-    fn init_module(&mut self, code: &Vec<u8>) {
-        let name = usize::from_le_bytes(self.next_usize_bytes(&code));
+    fn init_module(&mut self, code: &[u8]) {
+        let name = usize::from_le_bytes(self.next_usize_bytes(code));
 
         debug_assert!(self.current_module.is_none());
-        self.current_module = Some(Module::new(
-            self.agent.modules[&name].clone(),
-            self.intrinsics.clone(),
-        ));
+        // a module is initialised again when more code is compiled into it,
+        // like each input of the REPL
+        let module = match self.modules.remove(&name) {
+            Some(module) => module,
+            None => Module::new(self.agent.modules[&name].clone(), self.intrinsics.clone()),
+        };
+        self.current_module = Some(module);
     }
 
     fn end_module(&mut self) {
@@ -1036,13 +2276,37 @@ impl<'a> Interpreter<'a> {
         self.push(value);
     }
 
-    fn allocate_locals(&mut self, code: &Vec<u8>) {
-        let count = usize::from_le_bytes(self.next_usize_bytes(&code));
+    fn dup2(&mut self) {
+        let a = self.stack[self.sp - 2].clone();
+        let b = self.top().clone();
+        self.push(a);
+        self.push(b);
+    }
+
+    // move the top of the stack below the two values under it: [a, b, c] -> [c, a, b]
+    fn rot3(&mut self) {
+        self.stack[self.sp - 3..self.sp].rotate_right(1);
+    }
+
+    // copy the value `depth` values below the top, which the verifier checked
+    // is in the current frame
+    fn pick(&mut self, code: &[u8]) {
+        let depth = usize::from_le_bytes(self.next_usize_bytes(code));
+        let value = self.stack[self.sp - 1 - depth].clone();
+        self.push(value);
+    }
+
+    fn allocate_locals(&mut self, code: &[u8]) -> Result<(), VmError> {
+        let count = usize::from_le_bytes(self.next_usize_bytes(code));
+        if self.sp + count > self.limits.max_stack {
+            return Err(self.error(VmError::LimitError, "Stack overflow".to_string()));
+        }
 
         self.stack.reserve(count);
         for _ in 0..count {
             self.push(Value::Null);
         }
+        Ok(())
     }
 }
 
@@ -1050,7 +2314,7 @@ impl<'a> Interpreter<'a> {
 mod tests {
     use super::*;
     use crate::compiler::bytecode::Bytecode;
-    use crate::module::ModuleSpec;
+    use crate::module::{ModuleSpec, RecordSpec};
     use pretty_assertions::assert_eq;
 
     macro_rules! get_agent {
@@ -1067,8 +2331,8 @@ mod tests {
 
     #[test]
     fn test_halt() {
-        let mut agent = get_agent!();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let agent = get_agent!();
+        let mut interpreter = Interpreter::new(agent);
 
         let mut bytecode = Bytecode::new();
         bytecode.init_module(0).halt().const_true().end_module();
@@ -1079,8 +2343,8 @@ mod tests {
 
     #[test]
     fn test_const_int() {
-        let mut agent = get_agent!();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let agent = get_agent!();
+        let mut interpreter = Interpreter::new(agent);
 
         let mut bytecode = Bytecode::new();
         bytecode.init_module(0).const_int(123).end_module();
@@ -1093,8 +2357,8 @@ mod tests {
 
     #[test]
     fn test_const_double() {
-        let mut agent = get_agent!();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let agent = get_agent!();
+        let mut interpreter = Interpreter::new(agent);
 
         let mut bytecode = Bytecode::new();
         bytecode.init_module(0).const_double(1.23).end_module();
@@ -1107,8 +2371,8 @@ mod tests {
 
     #[test]
     fn test_const_true() {
-        let mut agent = get_agent!();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let agent = get_agent!();
+        let mut interpreter = Interpreter::new(agent);
 
         let mut bytecode = Bytecode::new();
         bytecode.init_module(0).const_true().end_module();
@@ -1119,8 +2383,8 @@ mod tests {
 
     #[test]
     fn test_const_false() {
-        let mut agent = get_agent!();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let agent = get_agent!();
+        let mut interpreter = Interpreter::new(agent);
 
         let mut bytecode = Bytecode::new();
         bytecode.init_module(0).const_false().end_module();
@@ -1131,8 +2395,8 @@ mod tests {
 
     #[test]
     fn test_const_null() {
-        let mut agent = get_agent!();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let agent = get_agent!();
+        let mut interpreter = Interpreter::new(agent);
 
         let mut bytecode = Bytecode::new();
         bytecode.init_module(0).const_null().end_module();
@@ -1151,7 +2415,7 @@ mod tests {
             .const_string(agent.intern_string("hello world"))
             .end_module();
 
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let code = bytecode.into();
 
         let result = interpreter._evaluate(code);
@@ -1159,9 +2423,62 @@ mod tests {
     }
 
     #[test]
-    fn test_add() {
+    fn test_const_atom() {
         let mut agent = get_agent!();
-        let mut interpreter = Interpreter::new(&mut agent);
+
+        let mut bytecode = Bytecode::new();
+        let hello = agent.intern_string("hello");
+        bytecode
+            .init_module(0)
+            .const_atom(hello)
+            .const_atom(hello)
+            .equal()
+            .end_module();
+
+        let mut interpreter = Interpreter::new(agent);
+        let code = bytecode.into();
+
+        let result = interpreter._evaluate(code);
+        assert_eq!(result, Ok(Value::from(true)));
+    }
+
+    #[test]
+    fn test_record() {
+        let mut agent = get_agent!();
+        let point = agent.intern_string("Point");
+        let x = agent.intern_string("x");
+        let y = agent.intern_string("y");
+        let spec = agent.add_record(RecordSpec::new(point, vec![x, y]));
+
+        let mut bytecode = Bytecode::new();
+        bytecode
+            .init_module(0)
+            .const_int(1)
+            .const_int(2)
+            .new_record(spec)
+            .dup()
+            .dup()
+            .const_int(3)
+            .rot3()
+            .pop()
+            .const_int(0)
+            .array_set()
+            .pop()
+            .const_int(0)
+            .array_get()
+            .end_module();
+
+        let mut interpreter = Interpreter::new(agent);
+        let code = bytecode.into();
+
+        let result = interpreter._evaluate(code);
+        assert_eq!(result, Ok(Value::from(3)));
+    }
+
+    #[test]
+    fn test_add() {
+        let agent = get_agent!();
+        let mut interpreter = Interpreter::new(agent);
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -1179,8 +2496,8 @@ mod tests {
 
     #[test]
     fn test_sub() {
-        let mut agent = get_agent!();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let agent = get_agent!();
+        let mut interpreter = Interpreter::new(agent);
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -1198,8 +2515,8 @@ mod tests {
 
     #[test]
     fn test_mul() {
-        let mut agent = get_agent!();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let agent = get_agent!();
+        let mut interpreter = Interpreter::new(agent);
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -1217,8 +2534,8 @@ mod tests {
 
     #[test]
     fn test_div() {
-        let mut agent = get_agent!();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let agent = get_agent!();
+        let mut interpreter = Interpreter::new(agent);
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -1236,8 +2553,8 @@ mod tests {
 
     #[test]
     fn test_mod() {
-        let mut agent = get_agent!();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let agent = get_agent!();
+        let mut interpreter = Interpreter::new(agent);
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -1255,8 +2572,8 @@ mod tests {
 
     #[test]
     fn test_exp() {
-        let mut agent = get_agent!();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let agent = get_agent!();
+        let mut interpreter = Interpreter::new(agent);
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -1274,8 +2591,8 @@ mod tests {
 
     #[test]
     fn test_jump() {
-        let mut agent = get_agent!();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let agent = get_agent!();
+        let mut interpreter = Interpreter::new(agent);
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -1295,115 +2612,516 @@ mod tests {
         let code = bytecode.into();
         let result = interpreter._evaluate(code);
 
-        assert_eq!(result, Ok(Value::from(48)));
+        assert_eq!(result, Ok(Value::from(48)));
+    }
+
+    #[test]
+    fn test_jump_if_true() {
+        let agent = get_agent!();
+
+        let mut bytecode = Bytecode::new();
+        bytecode
+            .init_module(0)
+            .const_int(123)
+            .const_int(234)
+            .const_int(1)
+            .op(OpCode::JumpIfTrue)
+            .address_of("one")
+            .mul()
+            .halt()
+            .label("one")
+            .const_false()
+            .op(OpCode::JumpIfTrue)
+            .address_of("two")
+            .add()
+            .halt()
+            .label("two")
+            .sub()
+            .end_module();
+
+        let code = bytecode.into();
+        let mut interpreter = Interpreter::new(agent);
+        let result = interpreter._evaluate(code);
+
+        assert_eq!(result, Ok(Value::from(357)));
+    }
+
+    #[test]
+    fn test_jump_if_false() {
+        let mut agent = get_agent!();
+
+        let mut bytecode = Bytecode::new();
+        bytecode
+            .init_module(0)
+            .const_int(10)
+            .const_int(2)
+            .const_true()
+            .op(OpCode::JumpIfFalse)
+            .address_of("one")
+            .const_string(agent.intern_string(""))
+            .op(OpCode::JumpIfFalse)
+            .address_of("two")
+            .label("one")
+            .add()
+            .halt()
+            .label("two")
+            .mul()
+            .halt()
+            .end_module();
+
+        let code = bytecode.into();
+        let mut interpreter = Interpreter::new(agent);
+        let result = interpreter._evaluate(code);
+
+        assert_eq!(result, Ok(Value::from(20)));
+    }
+
+    #[test]
+    fn test_user_function() {
+        let mut agent = get_agent!();
+
+        let name = agent.intern_string("ret123");
+        let ret123 = Value::from(FunctionValue::User {
+            name: Some(name),
+            address: 18,
+            arity: 0,
+            variadic: false,
+            module: 0,
+            upvalues: Vec::new(),
+        });
+
+        let mut global = HashMap::new();
+        global.insert(name, ret123);
+
+        let mut bytecode = Bytecode::new();
+        bytecode
+            .init_module(0)
+            .op(OpCode::Jump)
+            .address_of("main")
+            .const_int(123)
+            .ret()
+            .label("main")
+            .load_global(name)
+            .call(0)
+            .end_module();
+
+        let code: Vec<u8> = bytecode.into();
+        crate::compiler::disassemble::disassemble(&agent, &code).unwrap();
+        let mut interpreter = Interpreter::with_intrinsics(agent, global);
+        let result = interpreter._evaluate(code);
+
+        assert_eq!(result, Ok(Value::from(123)));
+    }
+
+    #[test]
+    fn test_variadic_function() {
+        let agent = get_agent!();
+
+        let mut bytecode = Bytecode::new();
+        bytecode
+            .init_module(0)
+            .const_int(3)
+            .const_int(2)
+            .const_int(1)
+            .op(OpCode::NewVariadicFunction)
+            .usize(usize::MAX)
+            .usize(1)
+            .address_of("start")
+            .call(3)
+            .op(OpCode::Jump)
+            .address_of("end")
+            .label("start")
+            .load_argument(0)
+            .load_argument(1)
+            .new_array_with_values(2)
+            .ret()
+            .label("end")
+            .end_module();
+
+        let code = bytecode.into();
+        let mut interpreter = Interpreter::new(agent);
+        let result = interpreter._evaluate(code);
+
+        assert_eq!(
+            result,
+            Ok(Value::from(vec![
+                Value::from(1),
+                Value::from(vec![Value::from(2), Value::from(3)]),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_call_spread() {
+        let agent = get_agent!();
+
+        let mut bytecode = Bytecode::new();
+        bytecode
+            .init_module(0)
+            .const_int(1)
+            .const_int(2)
+            .new_array_with_values(2)
+            .op(OpCode::NewFunction)
+            .usize(usize::MAX)
+            .usize(2)
+            .address_of("start")
+            .call_spread()
+            .op(OpCode::Jump)
+            .address_of("end")
+            .label("start")
+            .load_argument(0)
+            .load_argument(1)
+            .sub()
+            .ret()
+            .label("end")
+            .end_module();
+
+        let code = bytecode.into();
+        let mut interpreter = Interpreter::new(agent);
+        let result = interpreter._evaluate(code);
+
+        assert_eq!(result, Ok(Value::from(-1)));
+    }
+
+    #[test]
+    fn test_catch_thrown_value() {
+        let agent = get_agent!();
+
+        let mut bytecode = Bytecode::new();
+        bytecode
+            .init_module(0)
+            .const_int(7)
+            .op(OpCode::PushHandler)
+            .address_of("catch")
+            .const_int(1)
+            .op(OpCode::NewFunction)
+            .usize(usize::MAX)
+            .usize(0)
+            .address_of("start")
+            .call(0)
+            .pop_handler()
+            .halt()
+            .label("start")
+            .const_int(2)
+            .const_int(3)
+            .throw()
+            .label("catch")
+            .add()
+            .end_module();
+
+        let code = bytecode.into();
+        let mut interpreter = Interpreter::new(agent);
+        let result = interpreter._evaluate(code);
+
+        // the stack and call stack are unwound to where the try started
+        assert_eq!(result, Ok(Value::from(10)));
+        assert!(interpreter.call_stack.is_empty());
+        assert!(interpreter.handlers.is_empty());
     }
 
     #[test]
-    fn test_jump_if_true() {
+    fn test_catch_vm_error() {
         let mut agent = get_agent!();
+        let missing = agent.intern_string("missing");
+        let reference_error = agent.intern_string("reference_error");
 
         let mut bytecode = Bytecode::new();
         bytecode
             .init_module(0)
-            .const_int(123)
-            .const_int(234)
-            .const_int(1)
-            .op(OpCode::JumpIfTrue)
-            .address_of("one")
-            .mul()
-            .halt()
-            .label("one")
-            .const_false()
-            .op(OpCode::JumpIfTrue)
-            .address_of("two")
-            .add()
-            .halt()
-            .label("two")
-            .sub()
+            .op(OpCode::PushHandler)
+            .address_of("catch")
+            .load_global(missing)
+            .pop_handler()
+            .label("catch")
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
-        assert_eq!(result, Ok(Value::from(357)));
+        assert_eq!(
+            result,
+            Ok(Value::from(vec![
+                Value::Atom(reference_error),
+                Value::from("missing is not defined"),
+            ]))
+        );
     }
 
     #[test]
-    fn test_jump_if_false() {
+    fn test_catch_division_by_zero() {
         let mut agent = get_agent!();
+        let runtime_error = agent.intern_string("runtime_error");
+        let mut interpreter = Interpreter::new(agent);
+
+        for op in [OpCode::Div, OpCode::Mod] {
+            let mut bytecode = Bytecode::new();
+            bytecode
+                .init_module(0)
+                .op(OpCode::PushHandler)
+                .address_of("catch")
+                .const_int(1)
+                .const_int(0)
+                .op(op)
+                .pop_handler()
+                .label("catch")
+                .end_module();
+
+            let result = interpreter.evaluate_from(bytecode.into(), 0);
+            assert_eq!(
+                result,
+                Ok(Value::from(vec![
+                    Value::Atom(runtime_error),
+                    Value::from("division by zero"),
+                ]))
+            );
+        }
+    }
+
+    #[test]
+    fn test_call_function_from_builtin() {
+        fn apply(interpreter: &mut Interpreter, mut args: Vec<Value>) -> Result<Value, VmError> {
+            let function = args.remove(0);
+            interpreter.call_function(function, args)
+        }
+
+        let mut agent = Agent::new();
+        let name = agent.intern_string("apply");
+        let mut intrinsics = HashMap::new();
+        intrinsics.insert(
+            name,
+            Value::from(FunctionValue::Builtin {
+                name: Some(name),
+                arity: 1,
+                variadic: true,
+                function: apply,
+            }),
+        );
+
+        let source = r#"
+let results = [null, null, null, null, null];
+function add(a, b) { return a + b; }
+results[0] = apply(add, 1, 2);
+results[1] = apply(apply, add, 3, 4);
+results[2] = apply(function() {
+    try { throw 5; } catch (e) { return e; }
+});
+try {
+    apply(function() { throw 6; });
+} catch (e) {
+    results[3] = e;
+}
+results[4] = apply(function(f) { return f(7); }, function(x) { return apply(add, x, x); });
+results;
+"#;
+        let mut compiler = crate::compiler::Compiler::new(&mut agent);
+        compiler.set_interactive();
+        compiler
+            .compile(".", "test.rbcvm".to_string(), source)
+            .unwrap();
+        let (code, _) = compiler.end();
+
+        let mut interpreter = Interpreter::with_intrinsics(agent, intrinsics);
+        let result = interpreter._evaluate(code.unwrap());
+
+        assert_eq!(
+            result,
+            Ok(Value::from(vec![
+                Value::from(3),
+                Value::from(7),
+                Value::from(5),
+                Value::from(6),
+                Value::from(14),
+            ]))
+        );
+        assert!(interpreter.stack.is_empty());
+    }
+
+    #[test]
+    fn test_uncaught_throw() {
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
-        bytecode
-            .init_module(0)
-            .const_true()
-            .op(OpCode::JumpIfFalse)
-            .address_of("one")
-            .const_int(10)
-            .const_int(2)
-            .const_string(agent.intern_string(""))
-            .op(OpCode::JumpIfFalse)
-            .address_of("two")
-            .label("one")
-            .add()
-            .halt()
-            .label("two")
-            .mul()
-            .halt()
-            .end_module();
+        bytecode.init_module(0).const_int(1).throw().end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
-        assert_eq!(result, Ok(Value::from(20)));
+        match result {
+            Err(VmError::Thrown(value, info)) => {
+                assert_eq!(value, Value::from(1));
+                assert_eq!(info.message, "1");
+            }
+            result => panic!("Expected thrown value, got {:?}", result),
+        }
     }
 
     #[test]
-    fn test_user_function() {
-        let mut agent = get_agent!();
+    fn test_stacktrace() {
+        let mut agent = Agent::new();
+        let source = "function get(a, i) {\n  return a[i];\n}\n\nget([1], 3);\n";
 
-        let name = agent.intern_string("ret123");
-        let ret123 = Value::from(FunctionValue::User {
-            name: Some(name),
-            address: 18,
-            arity: 0,
-            module: 0,
-            upvalues: Vec::new(),
+        let mut compiler = crate::compiler::Compiler::new(&mut agent);
+        compiler
+            .compile(".", "trace.rbcvm".to_string(), source)
+            .unwrap();
+        let (code, debuginfo) = compiler.end();
+
+        let mut interpreter = Interpreter::new(agent);
+        interpreter.set_debuginfo(debuginfo);
+        let result = interpreter._evaluate(code.unwrap());
+
+        assert_eq!(
+            result.unwrap_err().position(),
+            Some(Position {
+                line: 2,
+                column: 10
+            })
+        );
+        assert_eq!(
+            interpreter.print_stacktrace(),
+            "Traceback (most recent call last):
+  trace.rbcvm:5:1, in <toplevel>
+    get([1], 3);
+    ^
+  trace.rbcvm:2:10, in <main>.get
+    return a[i];
+           ^
+"
+        );
+    }
+
+    #[test]
+    fn test_stacktrace_recursion() {
+        let mut agent = Agent::new();
+        let source = "function down(n) {\n  return down(n + 1);\n}\n\ndown(0);\n";
+
+        let mut compiler = crate::compiler::Compiler::new(&mut agent);
+        compiler
+            .compile(".", "trace.rbcvm".to_string(), source)
+            .unwrap();
+        let (code, debuginfo) = compiler.end();
+
+        let mut interpreter = Interpreter::new(agent);
+        interpreter.set_debuginfo(debuginfo);
+        interpreter.set_limits(Limits {
+            max_call_depth: 1000,
+            ..Limits::default()
         });
+        let result = interpreter._evaluate(code.unwrap());
 
-        let mut global = HashMap::new();
-        global.insert(name, ret123);
+        assert_eq!(
+            result.unwrap_err().message(),
+            "Stack overflow at down (trace.rbcvm:2:10)"
+        );
+        assert_eq!(
+            interpreter.print_stacktrace(),
+            "Traceback (most recent call last):
+  trace.rbcvm:5:1, in <toplevel>
+    down(0);
+    ^
+  trace.rbcvm:2:10, in <main>.down
+    return down(n + 1);
+           ^
+  ... previous frame repeated 999 times
+"
+        );
+    }
+
+    #[test]
+    fn test_stacktrace_deep() {
+        let mut agent = Agent::new();
+        let source = "function a(n) { return b(n); }\nfunction b(n) { return a(n); }\na(0);\n";
+
+        let mut compiler = crate::compiler::Compiler::new(&mut agent);
+        compiler
+            .compile(".", "trace.rbcvm".to_string(), source)
+            .unwrap();
+        let (code, debuginfo) = compiler.end();
+
+        let mut interpreter = Interpreter::new(agent);
+        interpreter.set_debuginfo(debuginfo);
+        interpreter.set_limits(Limits {
+            max_call_depth: 1000,
+            ..Limits::default()
+        });
+        assert!(interpreter._evaluate(code.unwrap()).is_err());
+
+        let traceback = interpreter.print_stacktrace();
+        assert!(traceback.contains("  ... 961 more frames\n"));
+        assert_eq!(traceback.lines().count(), 1 + 3 * 40 + 1);
+    }
+
+    #[test]
+    fn test_evaluate_from() {
+        use crate::compiler::{Compiler, Program};
+
+        let agent = Agent::new();
+        let mut interpreter = Interpreter::new(agent);
+        let mut program = Program::new();
+
+        let inputs = [
+            "let a = 2; function f(x) { return x * a; }",
+            "f(3);",
+            "a = f(null);",
+            "let b = f(5); b;",
+        ];
+        let mut results = Vec::new();
+        for (i, input) in inputs.iter().enumerate() {
+            let start = program.code.len();
+            let mut compiler = Compiler::resume(&mut interpreter.agent, program);
+            compiler.set_interactive();
+            compiler.compile(".", i.to_string(), input).unwrap();
+            program = compiler.into_program();
+
+            let result = interpreter.evaluate_from(program.code.clone(), start);
+            if result.is_err() {
+                interpreter.unwind();
+            }
+            results.push(result.map_err(|e| e.kind()));
+            assert!(interpreter.stack.is_empty());
+        }
+
+        assert_eq!(
+            results,
+            vec![
+                Ok(Value::Null),
+                Ok(Value::from(6)),
+                Err("TypeError"),
+                Ok(Value::from(10))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_call_spread_non_array() {
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode
             .init_module(0)
-            .op(OpCode::Jump)
-            .address_of("main")
-            .const_int(123)
-            .ret()
-            .label("main")
-            .load_global(name)
-            .call(0)
+            .const_int(1)
+            .const_null()
+            .call_spread()
             .end_module();
 
-        let code: Vec<u8> = bytecode.into();
-        crate::compiler::disassemble::disassemble(&agent, &code).unwrap();
-        let mut interpreter = Interpreter::with_intrinsics(&mut agent, global);
+        let code = bytecode.into();
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
-        assert_eq!(result, Ok(Value::from(123)));
+        assert!(result.is_err());
     }
 
     #[test]
     fn test_pop() {
-        let mut agent = get_agent!();
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode.init_module(0).const_int(123).pop().end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(result, Ok(Value::Null));
@@ -1411,7 +3129,7 @@ mod tests {
 
     #[test]
     fn test_load_local() {
-        let mut agent = get_agent!();
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -1422,7 +3140,7 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(result, Ok(Value::from(123)));
@@ -1430,7 +3148,7 @@ mod tests {
 
     #[test]
     fn test_store_local() {
-        let mut agent = get_agent!();
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -1443,7 +3161,7 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(result, Ok(Value::from(234)));
@@ -1463,7 +3181,7 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::with_intrinsics(&mut agent, global);
+        let mut interpreter = Interpreter::with_intrinsics(agent, global);
         let result = interpreter._evaluate(code);
 
         assert_eq!(result, Ok(Value::from("test")));
@@ -1481,7 +3199,7 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(result, Ok(Value::Null));
@@ -1503,7 +3221,7 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::with_intrinsics(&mut agent, global);
+        let mut interpreter = Interpreter::with_intrinsics(agent, global);
         let result = interpreter._evaluate(code);
 
         assert_eq!(result, Ok(Value::from(27)));
@@ -1511,7 +3229,7 @@ mod tests {
 
     #[test]
     fn test_new_function() {
-        let mut agent = get_agent!();
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -1523,13 +3241,14 @@ mod tests {
             .ret()
             .label("main")
             .op(OpCode::NewFunction)
+            .usize(usize::MAX)
             .usize(0)
             .address_of("func")
             .call(0)
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(result, Ok(Value::from(999)));
@@ -1537,7 +3256,7 @@ mod tests {
 
     #[test]
     fn test_bind_local() {
-        let mut agent = get_agent!();
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -1550,6 +3269,7 @@ mod tests {
             .label("main")
             .const_int(123)
             .op(OpCode::NewFunction)
+            .usize(usize::MAX)
             .usize(0)
             .address_of("func")
             .bind_local(0)
@@ -1557,7 +3277,7 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(result, Ok(Value::from(123)));
@@ -1565,7 +3285,7 @@ mod tests {
 
     #[test]
     fn test_bind_upvalue() {
-        let mut agent = get_agent!();
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -1574,6 +3294,7 @@ mod tests {
             .address_of("main")
             .label("func1")
             .op(OpCode::NewFunction)
+            .usize(usize::MAX)
             .usize(0)
             .address_of("func2")
             .bind_upvalue(0)
@@ -1584,6 +3305,7 @@ mod tests {
             .label("main")
             .const_int(2334)
             .op(OpCode::NewFunction)
+            .usize(usize::MAX)
             .usize(0)
             .address_of("func1")
             .bind_local(0)
@@ -1592,7 +3314,7 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(result, Ok(Value::from(2334)));
@@ -1600,7 +3322,7 @@ mod tests {
 
     #[test]
     fn test_bind_argument() {
-        let mut agent = get_agent!();
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -1609,6 +3331,7 @@ mod tests {
             .address_of("main")
             .label("func1")
             .op(OpCode::NewFunction)
+            .usize(usize::MAX)
             .usize(0)
             .address_of("func2")
             .bind_argument(0)
@@ -1619,6 +3342,7 @@ mod tests {
             .label("main")
             .const_int(2334)
             .op(OpCode::NewFunction)
+            .usize(usize::MAX)
             .usize(1)
             .address_of("func1")
             .call(1)
@@ -1626,7 +3350,7 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(result, Ok(Value::from(2334)));
@@ -1647,6 +3371,7 @@ mod tests {
             .label("main")
             .const_string(agent.intern_string("hello"))
             .op(OpCode::NewFunction)
+            .usize(usize::MAX)
             .usize(0)
             .address_of("test")
             .bind_local(0)
@@ -1654,7 +3379,7 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(result, Ok(Value::from("hello")));
@@ -1675,11 +3400,13 @@ mod tests {
             .label("test")
             .const_int(0)
             .op(OpCode::NewFunction)
+            .usize(usize::MAX)
             .usize(0)
             .address_of("func_a")
             .bind_local(0)
             .store_global(a)
             .op(OpCode::NewFunction)
+            .usize(usize::MAX)
             .usize(0)
             .address_of("func_b")
             .bind_local(0)
@@ -1699,6 +3426,7 @@ mod tests {
             .ret()
             .label("main")
             .op(OpCode::NewFunction)
+            .usize(usize::MAX)
             .usize(0)
             .address_of("test")
             .call(0)
@@ -1718,7 +3446,7 @@ mod tests {
         global.insert(a, Value::Null);
         global.insert(b, Value::Null);
 
-        let mut interpreter = Interpreter::with_intrinsics(&mut agent, global);
+        let mut interpreter = Interpreter::with_intrinsics(agent, global);
         let result = interpreter._evaluate(code);
 
         assert_eq!(result, Ok(Value::from(2)));
@@ -1739,13 +3467,14 @@ mod tests {
             .label("main")
             .const_string(agent.intern_string("hullo"))
             .op(OpCode::NewFunction)
+            .usize(usize::MAX)
             .usize(1)
             .address_of("func")
             .call(1)
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(result, Ok(Value::from("hullo")));
@@ -1753,7 +3482,7 @@ mod tests {
 
     #[test]
     fn test_store_argument() {
-        let mut agent = get_agent!();
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -1770,27 +3499,65 @@ mod tests {
             .label("main")
             .const_int(1)
             .op(OpCode::NewFunction)
+            .usize(usize::MAX)
             .usize(1)
             .address_of("func")
             .call(1)
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(result, Ok(Value::from(3)));
     }
 
+    #[test]
+    fn test_store_argument_second() {
+        let agent = get_agent!();
+
+        let mut bytecode = Bytecode::new();
+        bytecode
+            .init_module(0)
+            .op(OpCode::Jump)
+            .address_of("main")
+            .label("func")
+            .const_int(5)
+            .store_argument(1)
+            .pop()
+            .load_argument(0)
+            .load_argument(1)
+            .new_array_with_values(2)
+            .ret()
+            .label("main")
+            .const_int(2)
+            .const_int(1)
+            .op(OpCode::NewFunction)
+            .usize(usize::MAX)
+            .usize(2)
+            .address_of("func")
+            .call(2)
+            .end_module();
+
+        let code = bytecode.into();
+        let mut interpreter = Interpreter::new(agent);
+        let result = interpreter._evaluate(code);
+
+        assert_eq!(
+            result,
+            Ok(Value::from(vec![Value::from(1), Value::from(5)]))
+        );
+    }
+
     #[test]
     fn test_new_array() {
-        let mut agent = get_agent!();
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode.init_module(0).new_array(10).end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(result, Ok(Value::from(vec![Value::Null; 10])));
@@ -1814,12 +3581,82 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::with_intrinsics(&mut agent, global);
+        let mut interpreter = Interpreter::with_intrinsics(agent, global);
         let result = interpreter._evaluate(code);
 
         assert_eq!(result, Ok(Value::from(123)));
     }
 
+    #[test]
+    fn test_array_get_out_of_bounds() {
+        let mut agent = get_agent!();
+
+        let array = agent.intern_string("array");
+
+        let mut global = HashMap::new();
+        global.insert(array, Value::from(vec![Value::Null]));
+
+        let mut bytecode = Bytecode::new();
+        bytecode
+            .init_module(0)
+            .load_global(array)
+            .const_int(3)
+            .array_get()
+            .end_module();
+
+        let code = bytecode.into();
+        let mut interpreter = Interpreter::with_intrinsics(agent, global);
+        let result = interpreter._evaluate(code);
+
+        match result {
+            Err(VmError::IndexError(info)) => {
+                assert_eq!(info.message, "Index 3 is out of bounds")
+            }
+            result => panic!("Expected IndexError, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_unknown_global() {
+        let mut agent = get_agent!();
+
+        let missing = agent.intern_string("missing");
+
+        let mut bytecode = Bytecode::new();
+        bytecode.init_module(0).load_global(missing).end_module();
+
+        let code = bytecode.into();
+        let mut interpreter = Interpreter::with_intrinsics(agent, HashMap::new());
+        let result = interpreter._evaluate(code);
+
+        match result {
+            Err(VmError::ReferenceError(info)) => {
+                assert_eq!(info.message, "missing is not defined")
+            }
+            result => panic!("Expected ReferenceError, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_unverified_code() {
+        let agent = get_agent!();
+
+        let mut bytecode = Bytecode::new();
+        bytecode.init_module(0).const_int(1).add().end_module();
+
+        let code = bytecode.into();
+        let mut interpreter = Interpreter::new(agent);
+        let result = interpreter._evaluate(code);
+
+        match result {
+            Err(VmError::VerifyError(info)) => {
+                assert_eq!(info.message, "Stack underflow in Add at offset 18")
+            }
+            result => panic!("Expected VerifyError, got {:?}", result),
+        }
+        assert!(interpreter.stack.is_empty());
+    }
+
     #[test]
     fn test_array_set() {
         let mut agent = get_agent!();
@@ -1841,7 +3678,7 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::with_intrinsics(&mut agent, global);
+        let mut interpreter = Interpreter::with_intrinsics(agent, global);
         let result = interpreter._evaluate(code);
 
         assert_eq!(
@@ -1852,7 +3689,7 @@ mod tests {
 
     #[test]
     fn test_equal() {
-        let mut agent = get_agent!();
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -1867,7 +3704,7 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(
@@ -1878,7 +3715,7 @@ mod tests {
 
     #[test]
     fn test_not_equal() {
-        let mut agent = get_agent!();
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -1893,7 +3730,7 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(
@@ -1904,7 +3741,7 @@ mod tests {
 
     #[test]
     fn test_less_than() {
-        let mut agent = get_agent!();
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -1922,7 +3759,7 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(
@@ -1937,7 +3774,7 @@ mod tests {
 
     #[test]
     fn test_less_than_equal() {
-        let mut agent = get_agent!();
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -1955,7 +3792,7 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(
@@ -1970,7 +3807,7 @@ mod tests {
 
     #[test]
     fn test_greater_than() {
-        let mut agent = get_agent!();
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -1988,7 +3825,7 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(
@@ -2003,7 +3840,7 @@ mod tests {
 
     #[test]
     fn test_greater_than_equal() {
-        let mut agent = get_agent!();
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -2021,7 +3858,7 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(
@@ -2036,7 +3873,7 @@ mod tests {
 
     #[test]
     fn test_bitwise_and() {
-        let mut agent = get_agent!();
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -2057,7 +3894,7 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(
@@ -2073,7 +3910,7 @@ mod tests {
 
     #[test]
     fn test_bitwise_or() {
-        let mut agent = get_agent!();
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -2094,7 +3931,7 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(
@@ -2110,7 +3947,7 @@ mod tests {
 
     #[test]
     fn test_bitwise_xor() {
-        let mut agent = get_agent!();
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -2131,7 +3968,7 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(
@@ -2147,7 +3984,7 @@ mod tests {
 
     #[test]
     fn test_bitwise_not() {
-        let mut agent = get_agent!();
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -2157,7 +3994,7 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(result, Ok(Value::from(-1)));
@@ -2165,13 +4002,13 @@ mod tests {
 
     #[test]
     fn test_not() {
-        let mut agent = get_agent!();
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode.init_module(0).const_true().not().end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(result, Ok(Value::from(false)));
@@ -2179,7 +4016,7 @@ mod tests {
 
     #[test]
     fn test_shift_left() {
-        let mut agent = get_agent!();
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -2190,7 +4027,7 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(result, Ok(Value::from(16)));
@@ -2198,7 +4035,7 @@ mod tests {
 
     #[test]
     fn test_shift_right() {
-        let mut agent = get_agent!();
+        let agent = get_agent!();
 
         let mut bytecode = Bytecode::new();
         bytecode
@@ -2209,9 +4046,65 @@ mod tests {
             .end_module();
 
         let code = bytecode.into();
-        let mut interpreter = Interpreter::new(&mut agent);
+        let mut interpreter = Interpreter::new(agent);
         let result = interpreter._evaluate(code);
 
         assert_eq!(result, Ok(Value::from(2)));
     }
+
+    #[test]
+    fn test_dup2() {
+        let agent = get_agent!();
+
+        let mut bytecode = Bytecode::new();
+        bytecode
+            .init_module(0)
+            .const_int(1)
+            .const_int(2)
+            .dup2()
+            .new_array_with_values(4)
+            .end_module();
+
+        let code = bytecode.into();
+        let mut interpreter = Interpreter::new(agent);
+        let result = interpreter._evaluate(code);
+
+        assert_eq!(
+            result,
+            Ok(Value::from(vec![
+                Value::from(1),
+                Value::from(2),
+                Value::from(1),
+                Value::from(2),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_rot3() {
+        let agent = get_agent!();
+
+        let mut bytecode = Bytecode::new();
+        bytecode
+            .init_module(0)
+            .const_int(1)
+            .const_int(2)
+            .const_int(3)
+            .rot3()
+            .new_array_with_values(3)
+            .end_module();
+
+        let code = bytecode.into();
+        let mut interpreter = Interpreter::new(agent);
+        let result = interpreter._evaluate(code);
+
+        assert_eq!(
+            result,
+            Ok(Value::from(vec![
+                Value::from(3),
+                Value::from(1),
+                Value::from(2),
+            ]))
+        );
+    }
 }
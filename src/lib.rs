@@ -0,0 +1,40 @@
+//! A bytecode VM for the rbcvm language. Programs are compiled and run with
+//! a [`Vm`]:
+//!
+//! ```
+//! use rust_bytecode_vm::{Value, Vm};
+//!
+//! let mut vm = Vm::new();
+//! vm.eval("example", "function square(x) { return x * x; }")
+//!     .unwrap();
+//! assert_eq!(vm.eval("example", "square(7);"), Ok(Value::from(49)));
+//! ```
+#![allow(dead_code)] // FIXME: enable this again once things are stable
+
+mod agent;
+mod builtins;
+mod compiler;
+mod debuginfo;
+mod error;
+mod heap;
+mod interpreter;
+mod module;
+mod opcode;
+mod permissions;
+mod repl;
+mod value;
+mod verifier;
+mod vm;
+
+pub use agent::Agent;
+pub use compiler::parser::Position;
+pub use error::{ErrorInfo, VmError};
+pub use heap::HeapStats;
+pub use interpreter::{Interpreter, Limits};
+pub use module::NativeModule;
+pub use permissions::Permissions;
+pub use value::{BuiltinFunction, FunctionValue, Value};
+pub use vm::{Script, Vm};
+
+/// Everything that can go wrong while compiling or running a program.
+pub type Error = VmError;
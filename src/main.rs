@@ -1,185 +1,65 @@
-#![allow(dead_code)] // FIXME: enable this again once things are stable
+use std::path::{Path, PathBuf};
 
-mod agent;
-mod compiler;
-mod debuginfo;
-mod interpreter;
-mod module;
-mod opcode;
-mod value;
+use rust_bytecode_vm::{Permissions, Vm};
 
-use std::collections::HashMap;
-use std::rc::Rc;
-use std::io::{self, Write};
-
-use agent::Agent;
-use compiler::Compiler;
-use interpreter::Interpreter;
-use value::{FunctionValue, Value};
-
-fn tostring(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
-    Ok(Value::from(format!("{}", args[0])))
-}
-
-fn type_of(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
-    Ok(Value::from(args[0].type_of()))
-}
-
-fn print(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
-    print!("{}", args[0]);
-    io::stdout().flush().map_err(|_| "Failed to flush stdout")?;
-    Ok(Value::Null)
-}
-
-fn println(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
-    let mut s = String::new();
-
-    for (i, v) in args.iter().map(|v| format!("{}", v)).enumerate() {
-        s.push_str(&v);
-        if i < args.len() - 1 {
-            s.push(' ');
-        }
-    }
-
-    println!("{}", s);
-
-    Ok(Value::Null)
-}
-
-fn array_new(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
-    if let Some(Value::Integer(n)) = args.get(0) {
-        Ok(Value::from(vec![Value::Null; *n as usize]))
-    } else {
-        Err("array_new: Expected int".to_string())
-    }
-}
-
-fn string_chars(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
-    if let Some(Value::String(s)) = args.get(0) {
-        Ok(Value::from(
-            s.chars()
-                .map(|c| Value::from(c.to_string()))
-                .collect::<Vec<_>>(),
-        ))
-    } else {
-        Err("string_chars: Expected string".to_string())
-    }
-}
-
-fn string_bytes(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
-    if let Some(Value::String(s)) = args.get(0) {
-        Ok(Value::from(
-            s.bytes()
-                .map(|b| Value::from(i64::from(b)))
-                .collect::<Vec<_>>(),
-        ))
-    } else {
-        Err("string_bytes: Expected string".to_string())
-    }
-}
-
-fn string_concat(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
-    let mut buf = String::new();
-
-    for arg in args {
-        if let Value::String(s) = arg {
-            buf += &s;
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = std::env::args().collect::<Vec<_>>();
+    let usage = || -> ! {
+        eprintln!("Usage: {} [<permissions>] [<file>]", args[0]);
+        eprintln!("       {} --compile <file> [<output>]", args[0]);
+        eprintln!();
+        eprintln!("Scripts can import the files below the directory <file> is in. Any other");
+        eprintln!("file they read or import has to be allowed with");
+        eprintln!("  --allow-read=<dir>   read and import files below <dir>");
+        eprintln!("which can be given more than once.");
+        std::process::exit(2);
+    };
+
+    let mut permissions = Permissions::new();
+    let mut rest = Vec::new();
+    for arg in &args[1..] {
+        if let Some(dir) = arg.strip_prefix("--allow-read=") {
+            permissions = permissions.allow_read(dir);
+        } else if arg.starts_with("--allow") {
+            usage();
         } else {
-            return Err("string_concat: Expected string".to_string());
+            rest.push(arg);
         }
     }
 
-    Ok(Value::String(Rc::new(buf)))
-}
-
-fn ord(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
-    if let Some(Value::String(s)) = args.get(0) {
-        if let Some(c) = s.chars().next() {
-            Ok(Value::from(c as i64))
-        } else {
-            Err(format!("ord: Expected string with length 1, got {:?}", s))
+    let mut vm = Vm::new();
+    vm.set_permissions(permissions);
+
+    let (filename, output) = match rest.first().map(|arg| arg.as_str()) {
+        Some("--compile") => match (rest.get(1), rest.get(2)) {
+            (Some(filename), Some(output)) => (*filename, Some(PathBuf::from(output))),
+            (Some(filename), None) => (*filename, Some(Path::new(filename).with_extension("rbc"))),
+            _ => usage(),
+        },
+        Some(_) => (rest[0], None),
+        None => {
+            vm.repl()?;
+            return Ok(());
         }
-    } else {
-        Err("ord: Expected string with length 1".to_string())
-    }
-}
-
-fn chr(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
-    if let Some(Value::Integer(n)) = args.get(0) {
-        Ok(Value::from((*n as u8 as char).to_string()))
-    } else {
-        Err("chr: Expected integer".to_string())
-    }
-}
-
-fn array_length(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
-    if let Some(Value::Array(vs)) = args.get(0) {
-        Ok(Value::from(vs.borrow().len() as i64))
-    } else {
-        Err(format!("array_length: Expected array, get {:#?}", args))
-    }
-}
+    };
 
-fn truncate32(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
-    if let Some(Value::Integer(i)) = args.get(0) {
-        Ok(Value::from(i64::from(*i as u32)))
-    } else {
-        Err("truncate32: Expected integer".to_string())
-    }
-}
+    let script = match vm.compile_file(filename) {
+        Ok(script) => script,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
-fn read_file(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
-    if let Some(Value::String(s)) = args.first() {
-        Ok(Value::from(
-            std::fs::read_to_string(&**s).map_err(|_| "Failed to read file".to_string())?,
-        ))
-    } else {
-        Err("read_file: Expected string".to_string())
+    if let Some(output) = output {
+        std::fs::write(output, vm.artifact())?;
+        return Ok(());
     }
-}
-
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut agent = Agent::new();
-    let mut global = HashMap::new();
 
-    macro_rules! add_global {
-        ($name:ident, $arity:expr) => {{
-            global.insert(
-                agent.intern_string(stringify!($name)),
-                Value::Function(Rc::new(FunctionValue::Builtin {
-                    name: Some(agent.intern_string(stringify!($name))),
-                    arity: $arity,
-                    function: $name,
-                })),
-            );
-        }};
+    if let Err(e) = vm.run(script) {
+        eprintln!("{}{}", vm.traceback().unwrap_or_default(), e);
+        std::process::exit(1);
     }
 
-    add_global!(print, 1);
-    add_global!(println, 1);
-    add_global!(tostring, 1);
-    add_global!(type_of, 1);
-    add_global!(array_new, 1);
-    add_global!(array_length, 1);
-    add_global!(string_chars, 1);
-    add_global!(string_bytes, 1);
-    add_global!(string_concat, 0);
-    add_global!(chr, 1);
-    add_global!(ord, 1);
-    add_global!(truncate32, 1);
-    add_global!(read_file, 1);
-
-    let mut compiler = Compiler::new(&mut agent);
-
-    let args = std::env::args().collect::<Vec<_>>();
-    let filename = args.get(1).expect("Expected filename");
-
-    compiler.compile_file(std::env::current_dir()?, filename)?;
-    let (code, debuginfo) = compiler.end();
-
-    let mut interpreter = Interpreter::with_intrinsics(&mut agent, global);
-    interpreter.set_debuginfo(&debuginfo);
-    interpreter.evaluate(code.unwrap());
-
     Ok(())
 }
@@ -1,5 +1,6 @@
 use crate::agent::Agent;
-use crate::value::Value;
+use crate::error::VmError;
+use crate::value::{BuiltinFunction, FunctionValue, Value};
 use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Clone)]
@@ -23,6 +24,109 @@ impl ModuleSpec {
     pub fn has_export(&self, name: usize) -> bool {
         self.exports.contains(&name)
     }
+
+    pub fn exports(&self) -> impl Iterator<Item = &usize> {
+        self.exports.iter()
+    }
+}
+
+/// The compile-time layout of a `record` declaration. Record values store
+/// their fields in declaration order, so a field's slot is its index here.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordSpec {
+    pub name: usize,
+    pub fields: Vec<usize>,
+}
+
+impl RecordSpec {
+    pub fn new(name: usize, fields: Vec<usize>) -> Self {
+        Self { name, fields }
+    }
+
+    pub fn field_index(&self, field: usize) -> Option<usize> {
+        self.fields.iter().position(|f| *f == field)
+    }
+}
+
+enum NativeExport {
+    Function {
+        arity: usize,
+        variadic: bool,
+        function: BuiltinFunction,
+    },
+    Value(Value),
+}
+
+/// A module implemented in Rust. Scripts import it with
+/// `import "native:<path>";`, using the path it was registered under, and
+/// then refer to it by its name like any other module.
+pub struct NativeModule {
+    name: String,
+    exports: Vec<(String, NativeExport)>,
+}
+
+impl NativeModule {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            exports: Vec::new(),
+        }
+    }
+
+    /// Exports a builtin. A variadic one also accepts more than `arity`
+    /// arguments.
+    pub fn function(
+        mut self,
+        name: &str,
+        arity: usize,
+        variadic: bool,
+        function: BuiltinFunction,
+    ) -> Self {
+        self.exports.push((
+            name.to_string(),
+            NativeExport::Function {
+                arity,
+                variadic,
+                function,
+            },
+        ));
+        self
+    }
+
+    /// Exports a constant.
+    pub fn value(mut self, name: &str, value: Value) -> Self {
+        self.exports
+            .push((name.to_string(), NativeExport::Value(value)));
+        self
+    }
+
+    /// Declares the module to the compiler and builds its global scope.
+    pub(crate) fn build(self, agent: &mut Agent) -> Module {
+        let mut spec = ModuleSpec::new(agent.intern_string(&self.name));
+        let mut global_scope = HashMap::new();
+
+        for (name, export) in self.exports {
+            let name = agent.intern_string(&name);
+            let value = match export {
+                NativeExport::Function {
+                    arity,
+                    variadic,
+                    function,
+                } => Value::from(FunctionValue::Builtin {
+                    name: Some(name),
+                    arity,
+                    variadic,
+                    function,
+                }),
+                NativeExport::Value(value) => value,
+            };
+            spec.add_export(name);
+            global_scope.insert(name, value);
+        }
+
+        agent.modules.insert(spec.name, spec.clone());
+        Module::new(spec, global_scope)
+    }
 }
 
 #[derive(Debug)]
@@ -40,13 +144,16 @@ impl Module {
         self.spec.name
     }
 
-    pub fn resolve_export(&self, agent: &Agent, name: usize) -> Result<Value, String> {
+    pub fn resolve_export(&self, agent: &Agent, name: usize) -> Result<Value, VmError> {
         if let Some(val) = self.global_scope.get(&name).cloned() {
             Ok(val)
         } else {
-            Err(format!(
-                "Module {} has no export {}",
-                agent.string_table[self.spec.name], agent.string_table[name]
+            Err(VmError::ReferenceError(
+                format!(
+                    "Module {} has no export {}",
+                    agent.string_table[self.spec.name], agent.string_table[name]
+                )
+                .into(),
             ))
         }
     }
@@ -56,16 +56,113 @@ pub enum OpCode {
     EndModule,
     Dup,
     AllocateLocals,
+    Dup2,
+    Rot3,
+    ConstAtom,
+    NewRecord,
+    NewVariadicFunction,
+    ArrayConcat,
+    CallSpread,
+    PushHandler,
+    PopHandler,
+    Throw,
+    NewMap,
+    GetIter,
+    IterNext,
+    NewRange,
+    NewInclusiveRange,
+    Contains,
+    Interpolate,
+    MatchArray,
+    MatchMap,
+    MatchRecord,
+    ArrayRest,
+    NoMatch,
+    Pick,
 }
 
-impl Into<u8> for OpCode {
-    fn into(self) -> u8 {
-        self as u8
+impl From<OpCode> for u8 {
+    fn from(val: OpCode) -> Self {
+        val as u8
     }
 }
 
-impl std::convert::From<u8> for OpCode {
-    fn from(val: u8) -> Self {
-        unsafe { std::mem::transmute(val) }
+impl OpCode {
+    // must stay the last variant
+    const LAST: OpCode = OpCode::Pick;
+
+    /// The number of usize operands following the opcode in the code.
+    pub fn operand_count(self) -> usize {
+        match self {
+            OpCode::ConstInt
+            | OpCode::ConstDouble
+            | OpCode::ConstString
+            | OpCode::ConstAtom
+            | OpCode::Jump
+            | OpCode::JumpIfTrue
+            | OpCode::JumpIfFalse
+            | OpCode::Call
+            | OpCode::LoadLocal
+            | OpCode::StoreLocal
+            | OpCode::LoadGlobal
+            | OpCode::DeclareGlobal
+            | OpCode::StoreGlobal
+            | OpCode::BindLocal
+            | OpCode::BindUpvalue
+            | OpCode::BindArgument
+            | OpCode::LoadUpvalue
+            | OpCode::StoreUpvalue
+            | OpCode::LoadArgument
+            | OpCode::StoreArgument
+            | OpCode::NewArray
+            | OpCode::NewArrayWithValues
+            | OpCode::NewMap
+            | OpCode::InitModule
+            | OpCode::AllocateLocals
+            | OpCode::NewRecord
+            | OpCode::PushHandler
+            | OpCode::IterNext
+            | OpCode::Interpolate
+            | OpCode::MatchMap
+            | OpCode::MatchRecord
+            | OpCode::ArrayRest
+            | OpCode::Pick => 1,
+            OpCode::LoadFromModule | OpCode::MatchArray => 2,
+            OpCode::NewFunction | OpCode::NewVariadicFunction => 3,
+            _ => 0,
+        }
+    }
+}
+
+/// Fails with the offending byte if it isn't an opcode.
+impl std::convert::TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(val: u8) -> Result<Self, Self::Error> {
+        if val <= OpCode::LAST as u8 {
+            // OpCode is a fieldless repr(u8) enum numbered from 0, so every
+            // value up to the last variant is valid
+            Ok(unsafe { std::mem::transmute::<u8, OpCode>(val) })
+        } else {
+            Err(val)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_try_from_u8() {
+        for byte in 0..=OpCode::LAST as u8 {
+            assert_eq!(u8::from(OpCode::try_from(byte).unwrap()), byte);
+        }
+        assert_eq!(
+            OpCode::try_from(OpCode::LAST as u8 + 1),
+            Err(OpCode::LAST as u8 + 1)
+        );
+        assert_eq!(OpCode::try_from(u8::MAX), Err(u8::MAX));
     }
 }
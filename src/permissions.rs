@@ -0,0 +1,172 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::VmError;
+
+/// What the builtins may do on behalf of a script. Nothing is allowed unless
+/// the host grants it, so a script can't get at anything the host didn't
+/// mean to share.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Permissions {
+    read: Vec<PathBuf>,
+    write: Vec<PathBuf>,
+    env: bool,
+    run: bool,
+    clock: bool,
+}
+
+impl Permissions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows reading files in `dir` and everything below it.
+    pub fn allow_read<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.read.push(root(dir.as_ref()));
+        self
+    }
+
+    /// Allows writing files in `dir` and everything below it.
+    pub fn allow_write<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.write.push(root(dir.as_ref()));
+        self
+    }
+
+    /// Allows reading environment variables.
+    pub fn allow_env(mut self) -> Self {
+        self.env = true;
+        self
+    }
+
+    /// Allows starting other processes.
+    pub fn allow_run(mut self) -> Self {
+        self.run = true;
+        self
+    }
+
+    /// Allows reading the time.
+    pub fn allow_clock(mut self) -> Self {
+        self.clock = true;
+        self
+    }
+
+    /// Resolves a path a builtin wants to read from, if it's below one of
+    /// the directories that may be read. The resolved path is the one to
+    /// use, since it has no symlinks left that could lead somewhere else.
+    pub fn check_read<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf, VmError> {
+        check_path(&self.read, path.as_ref(), "read")
+    }
+
+    /// Resolves a path a builtin wants to write to, like
+    /// [`Permissions::check_read`].
+    pub fn check_write<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf, VmError> {
+        check_path(&self.write, path.as_ref(), "write")
+    }
+
+    pub fn check_env(&self) -> Result<(), VmError> {
+        check(self.env, "No permission to read the environment")
+    }
+
+    pub fn check_run(&self) -> Result<(), VmError> {
+        check(self.run, "No permission to run processes")
+    }
+
+    pub fn check_clock(&self) -> Result<(), VmError> {
+        check(self.clock, "No permission to read the clock")
+    }
+}
+
+fn check(allowed: bool, message: &str) -> Result<(), VmError> {
+    if allowed {
+        Ok(())
+    } else {
+        Err(VmError::PermissionError(message.into()))
+    }
+}
+
+// A directory that doesn't exist yet can't be resolved until it does, so it's
+// only made absolute, against the directory the permission was granted in.
+fn root(dir: &Path) -> PathBuf {
+    fs::canonicalize(dir).unwrap_or_else(|_| match std::env::current_dir() {
+        Ok(pwd) => pwd.join(dir),
+        Err(_) => dir.to_path_buf(),
+    })
+}
+
+fn check_path(roots: &[PathBuf], path: &Path, access: &str) -> Result<PathBuf, VmError> {
+    let denied = || {
+        VmError::PermissionError(
+            format!("No permission to {} {}", access, path.to_string_lossy()).into(),
+        )
+    };
+
+    let resolved = resolve(path).ok_or_else(denied)?;
+    // a root that still doesn't exist can't contain anything
+    if roots
+        .iter()
+        .filter_map(|root| fs::canonicalize(root).ok())
+        .any(|root| resolved.starts_with(root))
+    {
+        Ok(resolved)
+    } else {
+        Err(denied())
+    }
+}
+
+// A file that's about to be created doesn't exist yet, so only its directory
+// can be resolved.
+fn resolve(path: &Path) -> Option<PathBuf> {
+    match fs::canonicalize(path) {
+        Ok(path) => Some(path),
+        Err(_) => {
+            let name = path.file_name()?;
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            fs::canonicalize(dir).ok().map(|dir| dir.join(name))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_check_path() {
+        let dir = std::env::temp_dir().join(format!("rbcvm-permissions-{}", std::process::id()));
+        fs::create_dir_all(dir.join("allowed")).unwrap();
+        fs::write(dir.join("allowed/file"), "").unwrap();
+        fs::write(dir.join("secret"), "").unwrap();
+
+        let permissions = Permissions::new()
+            .allow_read(dir.join("allowed"))
+            .allow_write(dir.join("allowed"));
+        let allowed = fs::canonicalize(dir.join("allowed/file")).unwrap();
+
+        assert_eq!(
+            permissions.check_read(dir.join("allowed/file")),
+            Ok(allowed)
+        );
+        assert!(permissions.check_read(dir.join("secret")).is_err());
+        assert!(permissions
+            .check_read(dir.join("allowed/../secret"))
+            .is_err());
+        assert!(permissions.check_write(dir.join("allowed/new")).is_ok());
+        assert!(permissions.check_write(dir.join("new")).is_err());
+        assert!(permissions.check_env().is_err());
+        assert!(permissions.check_run().is_err());
+
+        // granted before the directory exists
+        let permissions = Permissions::new().allow_read(dir.join("later"));
+        assert!(permissions.check_read(dir.join("later/file")).is_err());
+        fs::create_dir_all(dir.join("later")).unwrap();
+        fs::write(dir.join("later/file"), "").unwrap();
+        assert!(permissions.check_read(dir.join("later/file")).is_ok());
+        assert!(permissions.check_read(dir.join("secret")).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,87 @@
+use std::io::{self, BufRead, Write};
+
+use crate::compiler::parser::{Lexer, TokenType};
+use crate::value::Value;
+use crate::vm::Vm;
+
+// Every input is evaluated on top of everything that came before it, so
+// globals and imported modules stay around.
+pub(crate) fn run(vm: &mut Vm) -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut input = String::new();
+    let mut count = 0;
+
+    loop {
+        print!("{}", if input.is_empty() { ">> " } else { ".. " });
+        io::stdout().flush()?;
+
+        match lines.next() {
+            Some(line) => {
+                input += &line?;
+                input.push('\n');
+            }
+            None => {
+                println!();
+                return Ok(());
+            }
+        }
+
+        if input.trim().is_empty() {
+            input.clear();
+            continue;
+        }
+        if is_incomplete(&input) {
+            continue;
+        }
+
+        count += 1;
+        match vm.eval(&format!("<repl:{}>", count), &input) {
+            Ok(Value::Null) => {}
+            Ok(value) => println!("{}", vm.display(&value)),
+            Err(e) => eprintln!("{}{}", vm.traceback().unwrap_or_default(), e),
+        }
+        input.clear();
+    }
+}
+
+// More lines are needed while a bracket is still open. Anything the lexer
+// rejects is left for the compiler to report.
+fn is_incomplete(input: &str) -> bool {
+    let mut depth = 0;
+
+    for token in Lexer::new("<repl>", input) {
+        match token.map(|token| token.typ()) {
+            Ok(TokenType::LeftBrace | TokenType::LeftParen | TokenType::LeftBracket) => depth += 1,
+            Ok(TokenType::RightBrace | TokenType::RightParen | TokenType::RightBracket) => {
+                depth -= 1
+            }
+            Ok(_) => {}
+            Err(_) => return false,
+        }
+    }
+
+    depth > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_incomplete() {
+        assert!(!is_incomplete("let a = 1;"));
+        assert!(is_incomplete("function f() {"));
+        assert!(is_incomplete("function f() {\n  return [1,\n"));
+        assert!(!is_incomplete("function f() {\n  return [1, 2];\n}"));
+        assert!(!is_incomplete(r#"let a = "{";"#));
+        assert!(!is_incomplete("}"));
+    }
+
+    #[test]
+    fn test_module_declaration() {
+        let mut vm = Vm::new();
+        assert_eq!(vm.eval("<repl:1>", "module X;\n"), Ok(Value::Null));
+        assert_eq!(vm.eval("<repl:2>", "1 + 1;\n"), Ok(Value::Integer(2)));
+    }
+}
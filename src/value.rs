@@ -1,9 +1,13 @@
+use crate::agent::Agent;
+use crate::error::VmError;
 use crate::interpreter::Interpreter;
 use std::cell::RefCell;
 use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::rc::Rc;
 
-type BuiltinFunction = fn(&mut Interpreter, Vec<Value>) -> Result<Value, String>;
+pub type BuiltinFunction = fn(&mut Interpreter, Vec<Value>) -> Result<Value, VmError>;
 
 #[derive(Debug, PartialEq)]
 enum UpvalueValue {
@@ -24,11 +28,7 @@ impl Upvalue {
     }
 
     pub fn is_open(&self) -> bool {
-        if let UpvalueValue::Open(_) = self.value {
-            true
-        } else {
-            false
-        }
+        matches!(self.value, UpvalueValue::Open(_))
     }
 
     pub fn close(&mut self, value: Value) {
@@ -55,6 +55,23 @@ impl Upvalue {
         }
     }
 
+    pub fn closed_value(&self) -> Option<&Value> {
+        if let UpvalueValue::Closed(value) = &self.value {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    // breaks a cycle through a closed upvalue, handing back what it held
+    pub(crate) fn clear(&mut self) -> Option<Value> {
+        if let UpvalueValue::Closed(value) = &mut self.value {
+            Some(std::mem::replace(value, Value::Null))
+        } else {
+            None
+        }
+    }
+
     pub fn stack_index(&self) -> usize {
         if let UpvalueValue::Open(index) = self.value {
             index
@@ -64,16 +81,22 @@ impl Upvalue {
     }
 }
 
+/// `arity` is the minimum number of arguments a function accepts. A variadic
+/// user function gets any further arguments collected into an array in the
+/// argument slot after the required ones, while builtins simply receive all of
+/// them.
 #[derive(Clone)]
 pub enum FunctionValue {
     Builtin {
         name: Option<usize>,
         arity: usize,
+        variadic: bool,
         function: BuiltinFunction,
     },
     User {
         name: Option<usize>,
         arity: usize,
+        variadic: bool,
         address: usize,
         module: usize,
         upvalues: Vec<Rc<RefCell<Upvalue>>>,
@@ -86,22 +109,24 @@ impl std::fmt::Debug for FunctionValue {
             FunctionValue::Builtin {
                 name,
                 arity,
+                variadic,
                 function,
             } => write!(
                 f,
-                "FunctionValue::Builtin(name: {:?}, arity: {:?}, function: {:p})",
-                name, arity, function,
+                "FunctionValue::Builtin(name: {:?}, arity: {:?}, variadic: {:?}, function: {:p})",
+                name, arity, variadic, function,
             ),
             FunctionValue::User {
                 name,
                 arity,
+                variadic,
                 address,
                 module,
                 ..
             } => write!(
                 f,
-                "FunctionValue::User(name: {:?}, arity: {:?}, address: {:?}, module: {})",
-                name, arity, address, module
+                "FunctionValue::User(name: {:?}, arity: {:?}, variadic: {:?}, address: {:?}, module: {})",
+                name, arity, variadic, address, module
             ),
         }
     }
@@ -113,18 +138,20 @@ impl PartialEq for FunctionValue {
             FunctionValue::Builtin {
                 name,
                 arity,
+                variadic,
                 function,
             } => {
                 if let FunctionValue::Builtin {
                     name: other_name,
                     arity: other_arity,
+                    variadic: other_variadic,
                     function: other_function,
                 } = other
                 {
                     name == other_name
                         && arity == other_arity
-                        && function as *const BuiltinFunction
-                            == other_function as *const BuiltinFunction
+                        && variadic == other_variadic
+                        && std::ptr::eq(function, other_function)
                 } else {
                     false
                 }
@@ -132,6 +159,7 @@ impl PartialEq for FunctionValue {
             FunctionValue::User {
                 name,
                 arity,
+                variadic,
                 address,
                 upvalues,
                 module,
@@ -139,6 +167,7 @@ impl PartialEq for FunctionValue {
                 if let FunctionValue::User {
                     name: other_name,
                     arity: other_arity,
+                    variadic: other_variadic,
                     address: other_address,
                     upvalues: other_upvalues,
                     module: other_module,
@@ -146,6 +175,7 @@ impl PartialEq for FunctionValue {
                 {
                     name == other_name
                         && arity == other_arity
+                        && variadic == other_variadic
                         && address == other_address
                         && upvalues == other_upvalues
                         && module == other_module
@@ -157,6 +187,123 @@ impl PartialEq for FunctionValue {
     }
 }
 
+#[derive(Debug)]
+pub struct RecordValue {
+    pub spec: usize, // index into agent.records
+    pub fields: RefCell<Box<[Value]>>,
+}
+
+impl RecordValue {
+    pub fn new(spec: usize, fields: Vec<Value>) -> RecordValue {
+        RecordValue {
+            spec,
+            fields: RefCell::new(fields.into_boxed_slice()),
+        }
+    }
+}
+
+/// The values that can be used as map keys. Other values either can't be
+/// hashed, like doubles, or could change while they're in a map.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum MapKey {
+    Null,
+    Boolean(bool),
+    Integer(i64),
+    Atom(usize),
+    String(Rc<String>),
+}
+
+impl MapKey {
+    pub fn from_value(value: &Value) -> Option<MapKey> {
+        match value {
+            Value::Null => Some(MapKey::Null),
+            Value::Boolean(b) => Some(MapKey::Boolean(*b)),
+            Value::Integer(n) => Some(MapKey::Integer(*n)),
+            Value::Atom(id) => Some(MapKey::Atom(*id)),
+            Value::String(s) => Some(MapKey::String(s.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl From<MapKey> for Value {
+    fn from(key: MapKey) -> Value {
+        match key {
+            MapKey::Null => Value::Null,
+            MapKey::Boolean(b) => Value::Boolean(b),
+            MapKey::Integer(n) => Value::Integer(n),
+            MapKey::Atom(id) => Value::Atom(id),
+            MapKey::String(s) => Value::String(s),
+        }
+    }
+}
+
+/// The integers from `start` up to `end`, which is only included if the range
+/// is inclusive. The integers aren't stored, they're worked out as needed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RangeValue {
+    pub start: i64,
+    pub end: i64,
+    pub inclusive: bool,
+}
+
+impl RangeValue {
+    pub fn new(start: i64, end: i64, inclusive: bool) -> Self {
+        Self {
+            start,
+            end,
+            inclusive,
+        }
+    }
+
+    // the end as if the range were exclusive, which doesn't fit an i64 for
+    // an inclusive range up to i64::MAX
+    fn exclusive_end(&self) -> i128 {
+        i128::from(self.end) + i128::from(self.inclusive)
+    }
+
+    pub fn len(&self) -> usize {
+        let len = (self.exclusive_end() - i128::from(self.start)).max(0);
+        usize::try_from(len).unwrap_or(usize::MAX)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<i64> {
+        if index < self.len() {
+            i64::try_from(i128::from(self.start) + index as i128).ok()
+        } else {
+            None
+        }
+    }
+
+    pub fn contains(&self, n: i64) -> bool {
+        self.start <= n && i128::from(n) < self.exclusive_end()
+    }
+
+    /// The range as the start and end of a slice of `len` elements, if it's
+    /// within them.
+    pub fn slice_bounds(&self, len: usize) -> Option<(usize, usize)> {
+        let start = usize::try_from(self.start).ok()?;
+        let end = usize::try_from(self.exclusive_end()).ok()?;
+        if start <= end && end <= len {
+            Some((start, end))
+        } else {
+            None
+        }
+    }
+}
+
+/// The entries of a map sorted by key, so they come out in the same order
+/// every time.
+pub fn sorted_entries(map: &HashMap<MapKey, Value>) -> Vec<(&MapKey, &Value)> {
+    let mut entries = map.iter().collect::<Vec<_>>();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
 #[derive(Debug, Clone)]
 pub enum Value {
     Integer(i64),
@@ -164,8 +311,12 @@ pub enum Value {
     Boolean(bool),
     Null,
     String(Rc<String>),
-    Array(Rc<RefCell<Box<[Value]>>>),
+    Array(Rc<RefCell<Vec<Value>>>),
     Function(Rc<FunctionValue>),
+    Atom(usize),
+    Record(Rc<RecordValue>),
+    Map(Rc<RefCell<HashMap<MapKey, Value>>>),
+    Range(RangeValue),
 }
 
 impl Value {
@@ -178,6 +329,10 @@ impl Value {
             Value::String(_) => "string",
             Value::Array(_) => "array",
             Value::Function(_) => "function",
+            Value::Atom(_) => "atom",
+            Value::Record(_) => "record",
+            Value::Map(_) => "map",
+            Value::Range(_) => "range",
         }
     }
 
@@ -189,13 +344,26 @@ impl Value {
             Value::String(s) => !s.is_empty(),
             Value::Array(vs) => !vs.borrow().is_empty(),
             Value::Function(_) => true,
+            Value::Atom(_) => true,
+            Value::Record(_) => true,
+            Value::Map(map) => !map.borrow().is_empty(),
+            Value::Range(range) => !range.is_empty(),
             Value::Null => false,
         }
     }
 }
 
-impl std::fmt::Display for Value {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+impl Value {
+    /// Format this value using the agent's string table, so that things like
+    /// atoms print as their names.
+    pub fn display<'a>(&'a self, agent: &'a Agent) -> DisplayValue<'a> {
+        DisplayValue {
+            value: self,
+            agent: Some(agent),
+        }
+    }
+
+    fn fmt_with(&self, agent: Option<&Agent>, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             Value::String(s) => write!(f, "{}", s),
             Value::Integer(n) => write!(f, "{}", n),
@@ -206,18 +374,86 @@ impl std::fmt::Display for Value {
                 let len = vs.borrow().len();
                 write!(f, "[")?;
                 for (i, val) in vs.borrow().iter().enumerate() {
-                    write!(f, "{}", val)?;
+                    val.fmt_with(agent, f)?;
                     if i < len - 1 {
                         write!(f, ", ")?;
                     }
                 }
                 write!(f, "]")
             }
+            Value::Map(map) => {
+                let map = map.borrow();
+                write!(f, "{{")?;
+                for (i, (key, val)) in sorted_entries(&map).into_iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    Value::from(key.clone()).fmt_with(agent, f)?;
+                    write!(f, ": ")?;
+                    val.fmt_with(agent, f)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Range(range) => write!(
+                f,
+                "{}{}{}",
+                range.start,
+                if range.inclusive { "..=" } else { ".." },
+                range.end
+            ),
             Value::Function(func) => write!(f, "{:?}", func),
+            Value::Atom(id) => match agent {
+                Some(agent) => write!(f, "{}", agent.string_table[*id]),
+                None => write!(f, "<atom {}>", id),
+            },
+            Value::Record(record) => {
+                let fields = record.fields.borrow();
+                match agent {
+                    Some(agent) => {
+                        let spec = &agent.records[record.spec];
+                        write!(f, "{} {{", agent.string_table[spec.name])?;
+                        for (i, (name, val)) in spec.fields.iter().zip(fields.iter()).enumerate() {
+                            write!(
+                                f,
+                                "{} {}: ",
+                                if i == 0 { "" } else { "," },
+                                agent.string_table[*name]
+                            )?;
+                            val.fmt_with(Some(agent), f)?;
+                        }
+                        write!(f, " }}")
+                    }
+                    None => {
+                        write!(f, "<record {}> {{", record.spec)?;
+                        for (i, val) in fields.iter().enumerate() {
+                            write!(f, "{} ", if i == 0 { "" } else { "," })?;
+                            val.fmt_with(None, f)?;
+                        }
+                        write!(f, " }}")
+                    }
+                }
+            }
         }
     }
 }
 
+pub struct DisplayValue<'a> {
+    value: &'a Value,
+    agent: Option<&'a Agent>,
+}
+
+impl<'a> std::fmt::Display for DisplayValue<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.value.fmt_with(self.agent, f)
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.fmt_with(None, f)
+    }
+}
+
 impl PartialEq for Value {
     fn eq(&self, other: &Value) -> bool {
         match self {
@@ -242,13 +478,7 @@ impl PartialEq for Value {
                     false
                 }
             }
-            Value::Null => {
-                if let Value::Null = other {
-                    true
-                } else {
-                    false
-                }
-            }
+            Value::Null => matches!(other, Value::Null),
             Value::String(a) => {
                 if let Value::String(b) = other {
                     if a.len() != b.len() {
@@ -280,6 +510,34 @@ impl PartialEq for Value {
                     false
                 }
             }
+            Value::Atom(a) => {
+                if let Value::Atom(b) = other {
+                    a == b
+                } else {
+                    false
+                }
+            }
+            Value::Record(a) => {
+                if let Value::Record(b) = other {
+                    a.spec == b.spec && *a.fields.borrow() == *b.fields.borrow()
+                } else {
+                    false
+                }
+            }
+            Value::Map(a) => {
+                if let Value::Map(b) = other {
+                    *a.borrow() == *b.borrow()
+                } else {
+                    false
+                }
+            }
+            Value::Range(a) => {
+                if let Value::Range(b) = other {
+                    a == b
+                } else {
+                    false
+                }
+            }
         }
     }
 }
@@ -332,7 +590,13 @@ impl From<String> for Value {
 
 impl From<Vec<Value>> for Value {
     fn from(vs: Vec<Value>) -> Value {
-        Value::Array(Rc::new(RefCell::new(vs.into_boxed_slice())))
+        Value::Array(Rc::new(RefCell::new(vs)))
+    }
+}
+
+impl From<HashMap<MapKey, Value>> for Value {
+    fn from(map: HashMap<MapKey, Value>) -> Value {
+        Value::Map(Rc::new(RefCell::new(map)))
     }
 }
 
@@ -345,6 +609,7 @@ impl From<FunctionValue> for Value {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::module::RecordSpec;
     use pretty_assertions::{assert_eq, assert_ne};
 
     #[test]
@@ -362,6 +627,27 @@ mod tests {
         assert_eq!(Value::from(true), Value::Boolean(true));
     }
 
+    #[test]
+    fn test_range() {
+        let range = RangeValue::new(2, 5, false);
+        assert_eq!(range.len(), 3);
+        assert_eq!(range.get(2), Some(4));
+        assert_eq!(range.get(3), None);
+        assert!(range.contains(2) && !range.contains(5));
+        assert_eq!(range.slice_bounds(5), Some((2, 5)));
+        assert_eq!(range.slice_bounds(4), None);
+
+        let range = RangeValue::new(i64::MAX - 1, i64::MAX, true);
+        assert_eq!(range.len(), 2);
+        assert!(range.contains(i64::MAX));
+        assert!(RangeValue::new(3, 1, true).is_empty());
+        assert_eq!(RangeValue::new(-1, 1, false).slice_bounds(2), None);
+
+        let range = RangeValue::new(i64::MIN, i64::MAX, false);
+        assert_eq!(range.len(), usize::MAX);
+        assert_eq!(range.get(1 << 63), Some(0));
+    }
+
     #[test]
     fn test_from_str() {
         let s = "hello world";
@@ -383,12 +669,12 @@ mod tests {
             Value::from(3.21),
             Value::from("hwhwhwh"),
         ]);
-        let v2 = Value::Array(Rc::new(RefCell::new(vs.into_boxed_slice())));
+        let v2 = Value::Array(Rc::new(RefCell::new(vs)));
 
         assert_eq!(v1, v2);
     }
 
-    fn builtin_function(_: &mut Interpreter, _: Vec<Value>) -> Result<Value, String> {
+    fn builtin_function(_: &mut Interpreter, _: Vec<Value>) -> Result<Value, VmError> {
         Ok(Value::Null)
     }
 
@@ -397,11 +683,13 @@ mod tests {
         let a = FunctionValue::Builtin {
             name: Some(123),
             arity: 1,
+            variadic: false,
             function: builtin_function,
         };
         let b = FunctionValue::User {
             name: Some(123),
             arity: 1,
+            variadic: false,
             address: 123,
             module: 0,
             upvalues: Vec::new(),
@@ -409,6 +697,7 @@ mod tests {
         let d = FunctionValue::User {
             name: Some(123),
             arity: 1,
+            variadic: false,
             address: 123,
             module: 0,
             upvalues: Vec::new(),
@@ -478,6 +767,56 @@ mod tests {
         assert_ne!(a, c);
     }
 
+    #[test]
+    fn test_atom_equality() {
+        let a = Value::Atom(1);
+        let b = Value::Atom(1);
+        let c = Value::Atom(2);
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_ne!(a, Value::from(1));
+    }
+
+    #[test]
+    fn test_atom_display() {
+        let mut agent = Agent::new();
+        let ok = agent.intern_string("ok");
+        let value = Value::from(vec![Value::Atom(ok), Value::from(1)]);
+
+        assert_eq!(format!("{}", value.display(&agent)), "[ok, 1]");
+    }
+
+    #[test]
+    fn test_record_display() {
+        let mut agent = Agent::new();
+        let point = agent.intern_string("Point");
+        let x = agent.intern_string("x");
+        let y = agent.intern_string("y");
+        let spec = agent.add_record(RecordSpec::new(point, vec![x, y]));
+        let value = Value::Record(Rc::new(RecordValue::new(
+            spec,
+            vec![Value::from(1), Value::from(vec![Value::from(2)])],
+        )));
+
+        assert_eq!(
+            format!("{}", value.display(&agent)),
+            "Point { x: 1, y: [2] }"
+        );
+    }
+
+    #[test]
+    fn test_record_equality() {
+        let a = Value::Record(Rc::new(RecordValue::new(0, vec![Value::from(1)])));
+        let b = Value::Record(Rc::new(RecordValue::new(0, vec![Value::from(1)])));
+        let c = Value::Record(Rc::new(RecordValue::new(1, vec![Value::from(1)])));
+        let d = Value::from(vec![Value::from(1)]);
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_ne!(a, d);
+    }
+
     #[test]
     fn test_array_equality() {
         let a = Value::from(vec![Value::from(true), Value::Null, Value::from("abc")]);
@@ -538,11 +877,17 @@ mod tests {
         assert!(!b.is_truthy());
     }
 
+    #[test]
+    fn test_atom_truthiness() {
+        assert!(Value::Atom(0).is_truthy());
+    }
+
     #[test]
     fn test_function_truthiness() {
         let a = Value::from(FunctionValue::Builtin {
             name: Some(123),
             arity: 3,
+            variadic: false,
             function: builtin_function,
         });
 
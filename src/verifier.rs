@@ -0,0 +1,533 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+
+use crate::agent::Agent;
+use crate::error::VmError;
+use crate::opcode::OpCode;
+
+const USIZE_SIZE: usize = std::mem::size_of::<usize>();
+
+struct Instruction {
+    opcode: OpCode,
+    operands: Vec<usize>,
+    // offset of the instruction right after this one
+    next: usize,
+}
+
+// Code is either run at the top level, between the start and end of a
+// module, or as the body of a function, which can also use its arguments and
+// must end with a return. Functions always run in the module they were
+// created in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Region {
+    TopLevel { in_module: bool },
+    Function { parameters: usize },
+}
+
+#[inline]
+fn error<T>(offset: usize, message: String) -> Result<T, VmError> {
+    Err(VmError::VerifyError(
+        format!("{} at offset {}", message, offset).into(),
+    ))
+}
+
+/// Checks that code is safe to hand to the interpreter: every instruction
+/// decodes, every operand refers to something that exists, every jump lands
+/// on an instruction, and the stack has the same depth however an
+/// instruction is reached and never underflows. Only the code reachable from
+/// `start` is checked for flow, the rest was verified when it was added.
+pub(crate) fn verify(agent: &Agent, code: &[u8], start: usize) -> Result<(), VmError> {
+    let instructions = decode(agent, code)?;
+
+    let mut depths = HashMap::new();
+    let mut worklist = vec![(start, 0, Region::TopLevel { in_module: false })];
+
+    while let Some((offset, depth, region)) = worklist.pop() {
+        if offset == code.len() {
+            match region {
+                Region::TopLevel { in_module: false } => continue,
+                Region::TopLevel { in_module: true } => {
+                    return error(offset, "Module is not ended".to_string());
+                }
+                Region::Function { .. } => {
+                    return error(offset, "Function runs past the end of the code".to_string());
+                }
+            }
+        }
+
+        let instruction = match instructions.get(&offset) {
+            Some(instruction) => instruction,
+            None => return error(offset, "Jump into the middle of an instruction".to_string()),
+        };
+
+        match depths.entry(offset) {
+            Entry::Occupied(entry) => {
+                let (known_depth, known_region) = *entry.get();
+                if known_region != region {
+                    return error(offset, "Inconsistent module state".to_string());
+                }
+                if known_depth != depth {
+                    return error(offset, "Inconsistent stack depth".to_string());
+                }
+                continue;
+            }
+            Entry::Vacant(entry) => {
+                entry.insert((depth, region));
+            }
+        }
+
+        let Instruction {
+            opcode,
+            operands,
+            next,
+        } = instruction;
+        let (pops, pushes) = stack_effect(agent, *opcode, operands);
+
+        if depth < pops {
+            return error(offset, format!("Stack underflow in {:?}", opcode));
+        }
+        let after = depth - pops + pushes;
+
+        match opcode {
+            // locals live at the bottom of the frame
+            OpCode::LoadLocal | OpCode::StoreLocal | OpCode::BindLocal if operands[0] >= depth => {
+                return error(offset, format!("Local {} is out of bounds", operands[0]));
+            }
+            OpCode::LoadArgument | OpCode::StoreArgument | OpCode::BindArgument => match region {
+                Region::Function { parameters } if operands[0] < parameters => {}
+                _ => return error(offset, format!("Argument {} is out of bounds", operands[0])),
+            },
+            OpCode::LoadUpvalue | OpCode::StoreUpvalue | OpCode::BindUpvalue
+                if matches!(region, Region::TopLevel { .. }) =>
+            {
+                return error(offset, "Upvalue outside of function".to_string());
+            }
+            OpCode::LoadGlobal
+            | OpCode::DeclareGlobal
+            | OpCode::StoreGlobal
+            | OpCode::NewFunction
+            | OpCode::NewVariadicFunction
+                if region == (Region::TopLevel { in_module: false }) =>
+            {
+                return error(offset, format!("{:?} outside of module", opcode));
+            }
+            _ => {}
+        }
+
+        match opcode {
+            OpCode::Return if matches!(region, Region::TopLevel { .. }) => {
+                return error(offset, "Return outside of function".to_string());
+            }
+            OpCode::InitModule => match region {
+                Region::TopLevel { in_module: false } => {
+                    worklist.push((*next, after, Region::TopLevel { in_module: true }));
+                }
+                Region::TopLevel { in_module: true } => {
+                    return error(offset, "Nested module".to_string());
+                }
+                Region::Function { .. } => {
+                    return error(offset, "Module inside of function".to_string());
+                }
+            },
+            OpCode::EndModule => match region {
+                Region::TopLevel { in_module: true } => {
+                    worklist.push((*next, after, Region::TopLevel { in_module: false }));
+                }
+                _ => return error(offset, "EndModule outside of module".to_string()),
+            },
+            OpCode::Halt | OpCode::Throw | OpCode::Return | OpCode::NoMatch => {}
+            OpCode::Jump => worklist.push((operands[0], after, region)),
+            OpCode::JumpIfTrue | OpCode::JumpIfFalse => {
+                worklist.push((operands[0], after, region));
+                worklist.push((*next, after, region));
+            }
+            OpCode::PushHandler => {
+                // the catch block starts with the error pushed
+                worklist.push((operands[0], after + 1, region));
+                worklist.push((*next, after, region));
+            }
+            OpCode::IterNext => {
+                // a finished iterator jumps without pushing anything
+                worklist.push((operands[0], depth, region));
+                worklist.push((*next, after, region));
+            }
+            OpCode::NewFunction | OpCode::NewVariadicFunction => {
+                let variadic = *opcode == OpCode::NewVariadicFunction;
+                let parameters = operands[1] + variadic as usize;
+                worklist.push((operands[2], 0, Region::Function { parameters }));
+                worklist.push((*next, after, region));
+            }
+            _ => worklist.push((*next, after, region)),
+        }
+    }
+
+    Ok(())
+}
+
+fn decode(agent: &Agent, code: &[u8]) -> Result<HashMap<usize, Instruction>, VmError> {
+    let mut instructions = HashMap::new();
+    let mut offset = 0;
+
+    while offset < code.len() {
+        let opcode = match OpCode::try_from(code[offset]) {
+            Ok(opcode) => opcode,
+            Err(byte) => return error(offset, format!("Invalid opcode {}", byte)),
+        };
+
+        let mut operands = Vec::with_capacity(opcode.operand_count());
+        let mut position = offset + 1;
+        for _ in 0..opcode.operand_count() {
+            match code.get(position..position + USIZE_SIZE) {
+                Some(bytes) => {
+                    operands.push(usize::from_le_bytes(bytes.try_into().unwrap()));
+                    position += USIZE_SIZE;
+                }
+                None => return error(offset, format!("Truncated operand for {:?}", opcode)),
+            }
+        }
+
+        check_operands(agent, opcode, &operands).or_else(|message| error(offset, message))?;
+
+        instructions.insert(
+            offset,
+            Instruction {
+                opcode,
+                operands,
+                next: position,
+            },
+        );
+        offset = position;
+    }
+
+    Ok(instructions)
+}
+
+// operands that index into the agent's tables
+fn check_operands(agent: &Agent, opcode: OpCode, operands: &[usize]) -> Result<(), String> {
+    let string = |id: usize| {
+        if id < agent.string_table.len() {
+            Ok(())
+        } else {
+            Err(format!("Unknown string {} in {:?}", id, opcode))
+        }
+    };
+
+    match opcode {
+        OpCode::ConstString
+        | OpCode::ConstAtom
+        | OpCode::LoadGlobal
+        | OpCode::DeclareGlobal
+        | OpCode::StoreGlobal => string(operands[0]),
+        OpCode::LoadFromModule => string(operands[0]).and(string(operands[1])),
+        OpCode::NewFunction | OpCode::NewVariadicFunction if operands[0] != usize::MAX => {
+            string(operands[0])
+        }
+        OpCode::InitModule if !agent.modules.contains_key(&operands[0]) => {
+            Err(format!("Unknown module {}", operands[0]))
+        }
+        OpCode::NewRecord | OpCode::MatchRecord if operands[0] >= agent.records.len() => {
+            Err(format!("Unknown record {}", operands[0]))
+        }
+        _ => Ok(()),
+    }
+}
+
+// (values popped, values pushed) with values that are only looked at counted
+// as both
+fn stack_effect(agent: &Agent, opcode: OpCode, operands: &[usize]) -> (usize, usize) {
+    match opcode {
+        OpCode::Halt
+        | OpCode::Jump
+        | OpCode::DeclareGlobal
+        | OpCode::InitModule
+        | OpCode::EndModule
+        | OpCode::PushHandler
+        | OpCode::PopHandler => (0, 0),
+
+        OpCode::ConstInt
+        | OpCode::ConstDouble
+        | OpCode::ConstString
+        | OpCode::ConstAtom
+        | OpCode::ConstTrue
+        | OpCode::ConstFalse
+        | OpCode::ConstNull
+        | OpCode::LoadLocal
+        | OpCode::LoadGlobal
+        | OpCode::LoadUpvalue
+        | OpCode::LoadArgument
+        | OpCode::LoadFromModule
+        | OpCode::NewFunction
+        | OpCode::NewVariadicFunction
+        | OpCode::NewArray => (0, 1),
+
+        OpCode::JumpIfTrue
+        | OpCode::JumpIfFalse
+        | OpCode::Pop
+        | OpCode::Return
+        | OpCode::Throw
+        | OpCode::NoMatch => (1, 0),
+
+        OpCode::StoreLocal
+        | OpCode::StoreGlobal
+        | OpCode::StoreUpvalue
+        | OpCode::StoreArgument
+        | OpCode::BindLocal
+        | OpCode::BindUpvalue
+        | OpCode::BindArgument
+        | OpCode::Not
+        | OpCode::Neg
+        | OpCode::BitwiseNot
+        | OpCode::MatchArray
+        | OpCode::MatchRecord
+        | OpCode::ArrayRest => (1, 1),
+
+        OpCode::Add
+        | OpCode::Sub
+        | OpCode::Mul
+        | OpCode::Div
+        | OpCode::Mod
+        | OpCode::Exp
+        | OpCode::Equal
+        | OpCode::NotEqual
+        | OpCode::LessThan
+        | OpCode::LessThanEqual
+        | OpCode::GreaterThan
+        | OpCode::GreaterThanEqual
+        | OpCode::BitwiseAnd
+        | OpCode::BitwiseOr
+        | OpCode::BitwiseXor
+        | OpCode::LeftShift
+        | OpCode::RightShift
+        | OpCode::ArrayGet
+        | OpCode::ArrayConcat
+        | OpCode::NewRange
+        | OpCode::NewInclusiveRange
+        | OpCode::Contains
+        | OpCode::CallSpread => (2, 1),
+
+        OpCode::ArraySet => (3, 1),
+        // an iterator is the value being iterated and its position
+        OpCode::GetIter => (1, 2),
+        OpCode::IterNext => (2, 4),
+        OpCode::Dup => (1, 2),
+        OpCode::Dup2 => (2, 4),
+        OpCode::Rot3 => (3, 3),
+        // copies the value `operands[0]` below the top
+        OpCode::Pick => (operands[0].saturating_add(1), operands[0].saturating_add(2)),
+
+        OpCode::Call => (operands[0].saturating_add(1), 1),
+        OpCode::NewArrayWithValues | OpCode::Interpolate => (operands[0], 1),
+        // the value and the keys it must have
+        OpCode::MatchMap => (operands[0].saturating_add(1), 1),
+        // a key and a value for every entry
+        OpCode::NewMap => (operands[0].saturating_mul(2), 1),
+        OpCode::NewRecord => (agent.records[operands[0]].fields.len(), 1),
+        OpCode::AllocateLocals => (0, operands[0]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::bytecode::Bytecode;
+    use crate::compiler::Compiler;
+    use crate::module::ModuleSpec;
+
+    macro_rules! get_agent {
+        () => {{
+            let mut agent = Agent::new();
+            let name = agent.intern_string("test");
+            agent.modules.insert(name, ModuleSpec::new(name));
+            agent
+        }};
+    }
+
+    fn message(result: Result<(), VmError>) -> String {
+        match result {
+            Err(VmError::VerifyError(info)) => info.message,
+            result => panic!("Expected VerifyError, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_compiled_code() {
+        let mut agent = Agent::new();
+        let mut compiler = Compiler::new(&mut agent);
+        compiler
+            .compile(
+                ".",
+                "test".to_string(),
+                r#"
+function f(a, ...rest) {
+    let total = a;
+    for let i = 0; i < 3; i += 1 {
+        try {
+            if i == 1 { continue; }
+            total += g(...rest);
+        } catch (e) {
+            throw e;
+        }
+    }
+    let g = function(x) { return total + x; };
+    return g;
+}
+"#,
+            )
+            .unwrap();
+        let (code, _) = compiler.end();
+
+        assert_eq!(verify(&agent, &code.unwrap(), 0), Ok(()));
+    }
+
+    #[test]
+    fn test_invalid_opcode() {
+        let agent = get_agent!();
+        let mut bytecode = Bytecode::new();
+        bytecode.init_module(0).end_module();
+        let mut code: Vec<u8> = bytecode.into();
+        code.push(0xff);
+
+        assert_eq!(
+            message(verify(&agent, &code, 0)),
+            "Invalid opcode 255 at offset 10"
+        );
+    }
+
+    #[test]
+    fn test_truncated_operand() {
+        let agent = get_agent!();
+        let mut bytecode = Bytecode::new();
+        bytecode.init_module(0).const_int(1);
+        let mut code: Vec<u8> = bytecode.into();
+        code.pop();
+
+        assert_eq!(
+            message(verify(&agent, &code, 0)),
+            "Truncated operand for ConstInt at offset 9"
+        );
+    }
+
+    #[test]
+    fn test_jump_into_instruction() {
+        let agent = get_agent!();
+        let mut bytecode = Bytecode::new();
+        bytecode.init_module(0).jump(12).const_int(1).end_module();
+        let code: Vec<u8> = bytecode.into();
+
+        assert_eq!(
+            message(verify(&agent, &code, 0)),
+            "Jump into the middle of an instruction at offset 12"
+        );
+    }
+
+    #[test]
+    fn test_unknown_string() {
+        let agent = get_agent!();
+        let mut bytecode = Bytecode::new();
+        bytecode.init_module(0).load_global(1).end_module();
+        let code: Vec<u8> = bytecode.into();
+
+        assert_eq!(
+            message(verify(&agent, &code, 0)),
+            "Unknown string 1 in LoadGlobal at offset 9"
+        );
+    }
+
+    #[test]
+    fn test_stack_underflow() {
+        let agent = get_agent!();
+        let mut bytecode = Bytecode::new();
+        bytecode.init_module(0).const_int(1).add().end_module();
+        let code: Vec<u8> = bytecode.into();
+
+        assert_eq!(
+            message(verify(&agent, &code, 0)),
+            "Stack underflow in Add at offset 18"
+        );
+    }
+
+    #[test]
+    fn test_inconsistent_stack_depth() {
+        let agent = get_agent!();
+        let mut bytecode = Bytecode::new();
+        bytecode
+            .init_module(0)
+            .label("start")
+            .const_true()
+            .op(OpCode::JumpIfFalse)
+            .address_of("end")
+            .const_int(1)
+            .op(OpCode::Jump)
+            .address_of("start")
+            .label("end")
+            .end_module();
+        let code: Vec<u8> = bytecode.into();
+
+        assert_eq!(
+            message(verify(&agent, &code, 0)),
+            "Inconsistent stack depth at offset 9"
+        );
+    }
+
+    #[test]
+    fn test_return_outside_function() {
+        let agent = get_agent!();
+        let mut bytecode = Bytecode::new();
+        bytecode.init_module(0).const_null().ret();
+        let code: Vec<u8> = bytecode.into();
+
+        assert_eq!(
+            message(verify(&agent, &code, 0)),
+            "Return outside of function at offset 10"
+        );
+    }
+
+    #[test]
+    fn test_end_module_outside_module() {
+        let agent = get_agent!();
+        let mut bytecode = Bytecode::new();
+        bytecode.end_module();
+        let code: Vec<u8> = bytecode.into();
+
+        assert_eq!(
+            message(verify(&agent, &code, 0)),
+            "EndModule outside of module at offset 0"
+        );
+    }
+
+    #[test]
+    fn test_global_outside_module() {
+        let agent = get_agent!();
+        let mut bytecode = Bytecode::new();
+        bytecode.load_global(0);
+        let code: Vec<u8> = bytecode.into();
+
+        assert_eq!(
+            message(verify(&agent, &code, 0)),
+            "LoadGlobal outside of module at offset 0"
+        );
+    }
+
+    #[test]
+    fn test_unbalanced_module() {
+        let agent = get_agent!();
+        let mut bytecode = Bytecode::new();
+        bytecode.init_module(0).init_module(0);
+        let code: Vec<u8> = bytecode.into();
+
+        assert_eq!(
+            message(verify(&agent, &code, 0)),
+            "Nested module at offset 9"
+        );
+
+        let mut bytecode = Bytecode::new();
+        bytecode.init_module(0).const_null();
+        let code: Vec<u8> = bytecode.into();
+
+        assert_eq!(
+            message(verify(&agent, &code, 0)),
+            "Module is not ended at offset 10"
+        );
+    }
+}
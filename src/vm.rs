@@ -0,0 +1,1088 @@
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::agent::Agent;
+use crate::builtins;
+use crate::compiler::artifact::Artifact;
+use crate::compiler::{Compiler, Program};
+use crate::error::VmError;
+use crate::heap::HeapStats;
+use crate::interpreter::{Interpreter, Limits};
+use crate::module::NativeModule;
+use crate::opcode::OpCode;
+use crate::permissions::Permissions;
+use crate::repl;
+use crate::value::{BuiltinFunction, FunctionValue, Value};
+
+/// Code that was compiled into a [`Vm`] and can be run with [`Vm::run`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Script {
+    start: usize,
+}
+
+struct Native {
+    name: String,
+    arity: usize,
+    variadic: bool,
+    function: BuiltinFunction,
+}
+
+/// Compiles and runs programs. Everything compiled into a VM becomes part of
+/// the same program, so a script can use the modules and globals of the
+/// scripts that ran before it.
+pub struct Vm {
+    interpreter: Interpreter,
+    program: Program,
+    // registered functions and modules that aren't installed yet. Their names
+    // are only interned when compiling, since an artifact has to be loaded
+    // into an empty string table.
+    natives: Vec<Native>,
+    native_modules: Vec<(String, NativeModule)>,
+    traceback: Option<String>,
+}
+
+impl Vm {
+    /// A VM with the builtins every program expects, like `println`.
+    pub fn new() -> Self {
+        let mut vm = Self::bare();
+        builtins::register(&mut vm);
+        vm
+    }
+
+    /// A VM without any builtins.
+    pub fn bare() -> Self {
+        Self {
+            interpreter: Interpreter::new(Agent::new()),
+            program: Program::new(),
+            natives: Vec::new(),
+            native_modules: Vec::new(),
+            traceback: None,
+        }
+    }
+
+    /// Makes a Rust function available as a global to the scripts compiled
+    /// after it. A variadic function also accepts more than `arity`
+    /// arguments.
+    pub fn register_function(
+        &mut self,
+        name: &str,
+        arity: usize,
+        variadic: bool,
+        function: BuiltinFunction,
+    ) {
+        self.natives.push(Native {
+            name: name.to_string(),
+            arity,
+            variadic,
+            function,
+        });
+    }
+
+    /// Makes a native module available to the scripts compiled after it, as
+    /// `import "native:<path>";`.
+    pub fn register_module(&mut self, path: &str, module: NativeModule) {
+        self.native_modules.push((path.to_string(), module));
+    }
+
+    /// Compiles source code. `name` shows up in errors and stack traces, and
+    /// imports are resolved from the working directory.
+    pub fn compile_str(&mut self, name: &str, source: &str) -> Result<Script, VmError> {
+        let pwd = working_directory()?;
+        self.compile_with(false, |compiler| {
+            compiler.compile(pwd, name.to_string(), source)
+        })
+    }
+
+    /// Compiles a source file, or loads it if it's a compiled artifact.
+    pub fn compile_file<P: AsRef<Path>>(&mut self, path: P) -> Result<Script, VmError> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|e| {
+            VmError::CompileError(format!("Could not read file: {}", e).into())
+                .with_module(path.to_string_lossy())
+        })?;
+
+        if Artifact::is_artifact(&bytes) {
+            return self
+                .load_artifact(&bytes)
+                .map_err(|e| e.with_module(path.to_string_lossy()));
+        }
+
+        let pwd = working_directory()?;
+        self.compile_with(false, |compiler| compiler.compile_file(pwd, path))
+    }
+
+    /// Loads an artifact written by [`Vm::artifact`]. It brings its own
+    /// string table, so nothing may have been compiled into the VM before.
+    pub fn load_artifact(&mut self, bytes: &[u8]) -> Result<Script, VmError> {
+        self.traceback = None;
+        if !self.interpreter.agent.string_table.is_empty() {
+            return Err(VmError::CompileError(
+                "Artifacts can only be loaded into a fresh VM".into(),
+            ));
+        }
+
+        let artifact = Artifact::try_from(bytes)?;
+        let (mut code, debuginfo) = artifact.load(&mut self.interpreter.agent);
+        // the same as into_program, for anything compiled after it
+        code.push(OpCode::Halt.into());
+
+        self.program = Program::from_code(code, debuginfo);
+        self.interpreter
+            .set_debuginfo(self.program.debuginfo.clone());
+        self.install_natives();
+
+        Ok(Script { start: 0 })
+    }
+
+    /// Everything compiled into the VM so far, as an artifact that can be
+    /// run without its sources.
+    pub fn artifact(&self) -> Box<[u8]> {
+        Artifact::new(
+            &self.interpreter.agent,
+            self.program.code.clone(),
+            self.program.debuginfo.clone(),
+        )
+        .into()
+    }
+
+    /// Runs a script. Returns null unless it was compiled by [`Vm::eval`].
+    /// A run that ran out of fuel and wasn't resumed is abandoned.
+    pub fn run(&mut self, script: Script) -> Result<Value, VmError> {
+        self.traceback = None;
+        if self.interpreter.is_suspended() {
+            self.interpreter.unwind();
+        }
+
+        let result = self
+            .interpreter
+            .evaluate_from(self.program.code.clone(), script.start);
+        self.finish(result)
+    }
+
+    /// Limits how much the scripts can run before they stop with
+    /// [`VmError::OutOfFuel`]. Every instruction costs a unit of fuel, and
+    /// calls and allocations cost more. `None` removes the limit.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.interpreter.set_fuel(fuel);
+    }
+
+    /// Changes how deep the scripts can recurse and how much they can
+    /// allocate.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.interpreter.set_limits(limits);
+    }
+
+    /// Grants the builtins access to files, the environment and so on. A
+    /// new VM isn't allowed any of it.
+    pub fn set_permissions(&mut self, permissions: Permissions) {
+        self.interpreter.set_permissions(permissions);
+    }
+
+    /// Adds to the fuel that's left, if there is a limit.
+    pub fn add_fuel(&mut self, fuel: u64) {
+        self.interpreter.add_fuel(fuel);
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.interpreter.fuel()
+    }
+
+    /// Continues the last run after it ran out of fuel. Fuel that runs out
+    /// inside a function called from Rust, like with [`Vm::call`], ends that
+    /// call instead, since it can't be suspended halfway.
+    pub fn resume(&mut self) -> Result<Value, VmError> {
+        self.traceback = None;
+        let result = self.interpreter.resume();
+        self.finish(result)
+    }
+
+    fn finish(&mut self, result: Result<Value, VmError>) -> Result<Value, VmError> {
+        if result.is_err() {
+            self.traceback = Some(self.interpreter.print_stacktrace());
+            // a suspended run keeps its frames until it's resumed
+            if !self.interpreter.is_suspended() {
+                self.interpreter.unwind();
+            }
+        }
+
+        result
+    }
+
+    /// Compiles and runs source code, and returns the value of its last
+    /// statement if that's an expression.
+    pub fn eval(&mut self, name: &str, source: &str) -> Result<Value, VmError> {
+        let pwd = working_directory()?;
+        let script = self.compile_with(true, |compiler| {
+            compiler.compile(pwd, name.to_string(), source)
+        })?;
+
+        self.run(script)
+    }
+
+    /// Calls a function, like one read with [`Vm::export`].
+    pub fn call(&mut self, function: &Value, args: Vec<Value>) -> Result<Value, VmError> {
+        self.traceback = None;
+        self.interpreter.call_function(function.clone(), args)
+    }
+
+    /// Reads an export of a module that has run.
+    pub fn export(&self, module: &str, name: &str) -> Result<Value, VmError> {
+        let agent = &self.interpreter.agent;
+        let running = agent
+            .find_string(module)
+            .and_then(|id| self.interpreter.module(id));
+        let module = match running {
+            Some(module) => module,
+            None => {
+                return Err(VmError::ReferenceError(
+                    format!("Module {} has not run", module).into(),
+                ))
+            }
+        };
+
+        match agent.find_string(name) {
+            Some(id) if agent.modules[&module.name()].has_export(id) => {
+                module.resolve_export(agent, id)
+            }
+            _ => Err(VmError::ReferenceError(
+                format!(
+                    "Module {} has no export {}",
+                    agent.string_table[module.name()],
+                    name
+                )
+                .into(),
+            )),
+        }
+    }
+
+    /// How much memory the values the scripts allocated are using.
+    pub fn heap_stats(&self) -> HeapStats {
+        self.interpreter.heap_stats()
+    }
+
+    /// Frees the reference cycles nothing refers to anymore, and returns how
+    /// many objects that was. Running scripts does this on its own too.
+    pub fn collect_garbage(&mut self) -> usize {
+        self.interpreter.collect_garbage()
+    }
+
+    /// Formats a value the way `println` would.
+    pub fn display(&self, value: &Value) -> String {
+        value.display(&self.interpreter.agent).to_string()
+    }
+
+    /// The stack trace of the error returned by the last run, if it failed.
+    pub fn traceback(&self) -> Option<&str> {
+        self.traceback.as_deref()
+    }
+
+    /// Reads code from stdin and prints what it evaluates to, until the end
+    /// of the input.
+    pub fn repl(&mut self) -> io::Result<()> {
+        repl::run(self)
+    }
+
+    fn compile_with<F>(&mut self, interactive: bool, compile: F) -> Result<Script, VmError>
+    where
+        F: FnOnce(&mut Compiler) -> Result<(), VmError>,
+    {
+        self.traceback = None;
+        self.install_natives();
+
+        let start = self.program.code.len();
+        let permissions = self.interpreter.permissions().clone();
+        // a failed compile may have added some of its code already, so it
+        // works on a copy
+        let mut compiler = Compiler::resume(&mut self.interpreter.agent, self.program.clone());
+        if interactive {
+            compiler.set_interactive();
+        }
+        compiler.set_permissions(permissions);
+        compile(&mut compiler)?;
+
+        self.program = compiler.into_program();
+        self.interpreter
+            .set_debuginfo(self.program.debuginfo.clone());
+
+        Ok(Script { start })
+    }
+
+    fn install_natives(&mut self) {
+        for native in self.natives.drain(..) {
+            let name = self.interpreter.agent.intern_string(&native.name);
+            self.interpreter.add_intrinsic(
+                name,
+                Value::from(FunctionValue::Builtin {
+                    name: Some(name),
+                    arity: native.arity,
+                    variadic: native.variadic,
+                    function: native.function,
+                }),
+            );
+        }
+
+        for (path, module) in self.native_modules.drain(..) {
+            let module = module.build(&mut self.interpreter.agent);
+            self.interpreter
+                .agent
+                .native_modules
+                .insert(path, module.name());
+            self.interpreter.add_module(module);
+        }
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn working_directory() -> Result<PathBuf, VmError> {
+    std::env::current_dir().map_err(|e| {
+        VmError::CompileError(format!("Could not read working directory: {}", e).into())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn double(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, VmError> {
+        match args[0] {
+            Value::Integer(n) => Ok(Value::from(n * 2)),
+            _ => Err(VmError::TypeError("double: Expected integer".into())),
+        }
+    }
+
+    // evaluates source that must succeed and shows its value
+    fn display(vm: &mut Vm, source: &str) -> String {
+        let value = vm.eval("test", source).unwrap();
+        vm.display(&value)
+    }
+
+    #[test]
+    fn test_eval_keeps_globals() {
+        let mut vm = Vm::new();
+
+        assert_eq!(vm.eval("1", "let a = 20;"), Ok(Value::Null));
+        assert_eq!(vm.eval("2", "a + 1;"), Ok(Value::from(21)));
+        assert_eq!(
+            vm.eval("3", "a(1);").map_err(|e| e.kind()),
+            Err("TypeError")
+        );
+        assert!(vm.traceback().unwrap().contains("3:1:1"));
+        assert_eq!(vm.eval("4", "a * 2;"), Ok(Value::from(40)));
+        assert_eq!(vm.traceback(), None);
+    }
+
+    #[test]
+    fn test_register_function() {
+        let mut vm = Vm::bare();
+        vm.register_function("double", 1, false, double);
+
+        assert_eq!(vm.eval("test", "double(21);"), Ok(Value::from(42)));
+    }
+
+    #[test]
+    fn test_register_module() {
+        let mut vm = Vm::bare();
+        vm.register_module(
+            "test",
+            NativeModule::new("Test")
+                .function("double", 1, false, double)
+                .value("answer", Value::from(42)),
+        );
+
+        assert_eq!(
+            vm.eval("1", r#"import "native:test"; Test.double(Test.answer);"#),
+            Ok(Value::from(84))
+        );
+        assert_eq!(
+            vm.eval("2", r#"import "native:test"; Test.hidden;"#)
+                .map_err(|e| e.kind()),
+            Err("CompileError")
+        );
+        assert_eq!(
+            vm.eval("3", r#"import "native:other";"#)
+                .map_err(|e| e.kind()),
+            Err("CompileError")
+        );
+    }
+
+    #[test]
+    fn test_math_module() {
+        let mut vm = Vm::new();
+
+        assert_eq!(
+            vm.eval(
+                "test",
+                r#"import "native:math"; Math.floor(Math.sqrt(17));"#
+            ),
+            Ok(Value::from(4))
+        );
+    }
+
+    #[test]
+    fn test_export() {
+        let mut vm = Vm::new();
+        let script = vm
+            .compile_str(
+                "test",
+                "module Test; export let answer = 42; let hidden = 1;",
+            )
+            .unwrap();
+
+        assert!(vm.export("Test", "answer").is_err());
+        assert_eq!(vm.run(script), Ok(Value::Null));
+        assert_eq!(vm.export("Test", "answer"), Ok(Value::from(42)));
+        assert!(vm.export("Test", "hidden").is_err());
+        assert!(vm.export("Other", "answer").is_err());
+    }
+
+    #[test]
+    fn test_call() {
+        let mut vm = Vm::new();
+        vm.eval(
+            "test",
+            r#"
+module Test;
+let count = 0;
+export function add(a, b) { count += 1; return a + b + count; }
+export function fail() { throw :failed; }
+"#,
+        )
+        .unwrap();
+
+        let add = vm.export("Test", "add").unwrap();
+        let args = vec![Value::from(1), Value::from(2)];
+        assert_eq!(vm.call(&add, args.clone()), Ok(Value::from(4)));
+        assert_eq!(vm.call(&add, args), Ok(Value::from(5)));
+
+        let fail = vm.export("Test", "fail").unwrap();
+        match vm.call(&fail, vec![]) {
+            Err(VmError::Thrown(value, _)) => assert_eq!(vm.display(&value), "failed"),
+            result => panic!("Expected thrown value, got {:?}", result),
+        }
+
+        let length = vm.eval("length", "array_length;").unwrap();
+        let array = Value::from(vec![Value::Null; 3]);
+        assert_eq!(vm.call(&length, vec![array]), Ok(Value::from(3)));
+        assert_eq!(
+            vm.call(&Value::from(1), vec![]).map_err(|e| e.kind()),
+            Err("TypeError")
+        );
+    }
+
+    #[test]
+    fn test_run_scripts_separately() {
+        let mut vm = Vm::new();
+        let first = vm
+            .compile_str("first", "module A; export let n = 1;")
+            .unwrap();
+        let second = vm
+            .compile_str("second", "module B; export let n = 2;")
+            .unwrap();
+
+        vm.run(first).unwrap();
+        assert!(vm.export("B", "n").is_err());
+        vm.run(second).unwrap();
+        assert_eq!(vm.export("B", "n"), Ok(Value::from(2)));
+    }
+
+    #[test]
+    fn test_collect_cycles() {
+        let mut vm = Vm::new();
+        let script = vm
+            .compile_str(
+                "test",
+                r#"
+function cycles() {
+  let array = [null, 1, 2];
+  array[0] = array;
+
+  function recurse(n) {
+    if (n > 0) {
+      return recurse(n - 1);
+    }
+    return array;
+  }
+  return recurse;
+}
+
+let i = 0;
+while (i < 10000) {
+  cycles()(3);
+  i += 1;
+}
+"#,
+            )
+            .unwrap();
+
+        vm.run(script).unwrap();
+        let stats = vm.heap_stats();
+        assert!(stats.collections > 0);
+        assert!(stats.objects < 5000, "{:?}", stats);
+
+        vm.collect_garbage();
+        assert_eq!(vm.heap_stats().objects, 0);
+        assert!(vm.heap_stats().collected >= 20000);
+    }
+
+    #[test]
+    fn test_fuel() {
+        let mut vm = Vm::new();
+        let script = vm
+            .compile_str(
+                "test",
+                r#"
+module Test;
+export let count = 0;
+function step() { count += 1; }
+while (count < 100) {
+  try { step(); } catch (e) { count = -1000; }
+}
+"#,
+            )
+            .unwrap();
+
+        vm.set_fuel(Some(500));
+        assert_eq!(vm.run(script).map_err(|e| e.kind()), Err("OutOfFuel"));
+        assert!(vm.fuel().unwrap() < 10);
+
+        let mut stops = 1;
+        loop {
+            vm.add_fuel(500);
+            match vm.resume() {
+                Ok(_) => break,
+                Err(VmError::OutOfFuel(_)) => stops += 1,
+                Err(e) => panic!("{}", e),
+            }
+        }
+        assert!(stops > 1);
+        assert_eq!(vm.export("Test", "count"), Ok(Value::from(100)));
+        assert!(vm.resume().is_err());
+
+        vm.set_fuel(None);
+        assert_eq!(vm.eval("more", "1 + 1;"), Ok(Value::from(2)));
+    }
+
+    #[test]
+    fn test_fuel_in_iterator() {
+        let mut vm = Vm::new();
+        let script = vm
+            .compile_str(
+                "test",
+                r#"
+module Test;
+export let total = 0;
+let n = 0;
+function next() {
+  n += 1;
+  if n > 50 { return null; }
+  let square = 0;
+  for let i = 0; i < n; i += 1 { square += n; }
+  return square;
+}
+for x in next { total += x; }
+"#,
+            )
+            .unwrap();
+
+        vm.set_fuel(Some(200));
+        assert_eq!(vm.run(script).map_err(|e| e.kind()), Err("OutOfFuel"));
+
+        let mut stops = 1;
+        loop {
+            vm.add_fuel(200);
+            match vm.resume() {
+                Ok(_) => break,
+                Err(VmError::OutOfFuel(_)) => stops += 1,
+                Err(e) => panic!("{}", e),
+            }
+        }
+        assert!(stops > 10);
+        assert_eq!(vm.export("Test", "total"), Ok(Value::from(42925)));
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut vm = Vm::new();
+        vm.set_limits(Limits {
+            max_call_depth: 100,
+            max_allocation: 1000,
+            max_heap_bytes: 1 << 20,
+            ..Limits::default()
+        });
+
+        let error = vm
+            .eval("deep", "function down(n) { return down(n + 1); } down(0);")
+            .unwrap_err();
+        assert_eq!(error.kind(), "LimitError");
+        assert_eq!(error.message(), "Stack overflow at down (deep:1:27)");
+        assert!(error.position().is_some());
+
+        assert_eq!(
+            vm.eval(
+                "caught",
+                "let kind = null; try { down(0); } catch (e) { kind = e[0]; } kind;"
+            ),
+            vm.eval("atom", ":limit_error;")
+        );
+
+        for source in &["array_new(1001);", "array_new(-1);"] {
+            assert!(vm.eval("alloc", source).is_err());
+        }
+        assert_eq!(
+            vm.eval("alloc", "array_length(array_new(1000));"),
+            Ok(Value::from(1000))
+        );
+        vm.eval("alloc", r#"let s = repeat("a", 600);"#).unwrap();
+        for source in &["s + s;", r#""${s}${s}";"#] {
+            assert_eq!(
+                vm.eval("alloc", source).map_err(|e| e.kind()),
+                Err("LimitError")
+            );
+        }
+
+        let error = vm
+            .eval(
+                "heap",
+                "let kept = array_new(1000); let i = 0; while (true) { kept[i] = array_new(1000); i += 1; }",
+            )
+            .unwrap_err();
+        assert_eq!(error.message(), "Out of memory");
+
+        for source in &[
+            "let grown = []; while (true) { array_push(grown, 1); }",
+            "let grown = []; while (true) { array_insert(grown, 0, 1); }",
+            "let grown = array_new(100); while (true) { array_extend(grown, array_new(100)); }",
+            "let grown = {}; let i = 0; while (true) { grown[i] = i; i += 1; }",
+        ] {
+            let mut vm = Vm::new();
+            vm.set_limits(Limits {
+                max_heap_bytes: 1 << 16,
+                ..Limits::default()
+            });
+            let error = vm.eval("growth", source).unwrap_err();
+            assert_eq!(error.message(), "Out of memory");
+        }
+    }
+
+    // what a host function that reads the environment would check first
+    fn env(interpreter: &mut Interpreter, _: Vec<Value>) -> Result<Value, VmError> {
+        interpreter.permissions().check_env()?;
+        Ok(Value::Null)
+    }
+
+    #[test]
+    fn test_permissions() {
+        let mut vm = Vm::new();
+        vm.register_function("env", 0, false, env);
+        let source = "let kind = null; try { env(); } catch (e) { kind = e[0]; } kind;";
+
+        assert_eq!(
+            vm.eval("denied", source),
+            vm.eval("atom", ":permission_error;")
+        );
+        assert_eq!(
+            vm.eval("read", r#"read_file("Cargo.toml");"#)
+                .map_err(|e| e.kind()),
+            Err("PermissionError")
+        );
+
+        vm.set_permissions(Permissions::new().allow_env());
+        assert_eq!(vm.eval("allowed", source), Ok(Value::Null));
+    }
+
+    #[test]
+    fn test_import_permissions() {
+        let dir = std::env::temp_dir().join(format!("rbcvm-imports-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("lib.rbcvm"),
+            "module Lib;\nexport let answer = 42;",
+        )
+        .unwrap();
+        fs::write(dir.join("main.rbcvm"), r#"import "lib.rbcvm"; Lib.answer;"#).unwrap();
+        let source = format!(
+            r#"import "{}"; Lib.answer;"#,
+            dir.join("lib.rbcvm").to_string_lossy()
+        );
+
+        let mut vm = Vm::new();
+        assert_eq!(
+            vm.eval("denied", &source).map_err(|e| e.kind()),
+            Err("PermissionError")
+        );
+        // files next to the entry file can always be imported
+        let script = vm.compile_file(dir.join("main.rbcvm")).unwrap();
+        assert!(vm.run(script).is_ok());
+
+        let mut vm = Vm::new();
+        vm.set_permissions(Permissions::new().allow_read(&dir));
+        assert_eq!(vm.eval("allowed", &source), Ok(Value::from(42)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_array_builtins() {
+        let mut vm = Vm::new();
+        vm.eval(
+            "setup",
+            "let a = []; array_push(a, 1); array_push(a, 3); array_insert(a, 1, 2);",
+        )
+        .unwrap();
+
+        assert_eq!(display(&mut vm, "a;"), "[1, 2, 3]");
+        assert_eq!(display(&mut vm, "array_slice(a, 1);"), "[2, 3]");
+        assert_eq!(
+            display(&mut vm, "array_concat(a, [4], []);"),
+            "[1, 2, 3, 4]"
+        );
+        assert_eq!(
+            display(&mut vm, "array_extend(a, a); a;"),
+            "[1, 2, 3, 1, 2, 3]"
+        );
+        assert_eq!(display(&mut vm, "array_remove(a, 0);"), "1");
+        assert_eq!(display(&mut vm, "array_pop(a);"), "3");
+        assert_eq!(display(&mut vm, "a;"), "[2, 3, 1, 2]");
+
+        for source in &[
+            "array_remove(a, 4);",
+            "array_insert(a, 5, 0);",
+            "array_slice(a, 3, 1);",
+        ] {
+            assert_eq!(
+                vm.eval("error", source).map_err(|e| e.kind()),
+                Err("IndexError")
+            );
+        }
+        assert_eq!(vm.eval("empty", "array_pop([]);"), Ok(Value::Null));
+
+        for source in &["a[-1];", "a[-1] = 0;"] {
+            let error = vm.eval("error", source).unwrap_err();
+            assert_eq!(error.message(), "Index -1 is out of bounds");
+        }
+    }
+
+    #[test]
+    fn test_maps() {
+        let mut vm = Vm::new();
+        vm.eval("setup", r#"let m = { b: 2, "a": 1, 3: :three };"#)
+            .unwrap();
+
+        assert_eq!(display(&mut vm, "m;"), "{3: three, a: 1, b: 2}");
+        assert_eq!(display(&mut vm, "m[4];"), "null");
+        assert_eq!(display(&mut vm, r#"m["c"] = 3; m["c"];"#), "3");
+        assert_eq!(display(&mut vm, "keys(m);"), "[3, a, b, c]");
+        assert_eq!(display(&mut vm, "values(m);"), "[three, 1, 2, 3]");
+        assert_eq!(display(&mut vm, r#"has(m, "b");"#), "true");
+        assert_eq!(display(&mut vm, r#"delete(m, "b");"#), "true");
+        assert_eq!(display(&mut vm, r#"has(m, "b");"#), "false");
+        assert_eq!(display(&mut vm, "entries({ x: 1 });"), "[[x, 1]]");
+        assert_eq!(display(&mut vm, "{ a: 1, a: 2 };"), "{a: 2}");
+        assert_eq!(
+            display(&mut vm, r#"let x = 5; {"a":x, b:x, c::x};"#),
+            "{a: 5, b: 5, c: x}"
+        );
+
+        assert_eq!(
+            vm.eval("error", "m[[]] = 1;").map_err(|e| e.kind()),
+            Err("TypeError")
+        );
+    }
+
+    #[test]
+    fn test_for_in() {
+        let mut vm = Vm::new();
+        let source = r#"
+            let seen = [];
+            for i, x in [10, 20] { array_push(seen, [i, x]); }
+            for c in "ab" { array_push(seen, c); }
+            for k, v in { b: 2, a: 1 } { array_push(seen, [k, v]); }
+            function countdown(n) {
+              return function() {
+                if n == 0 { return null; }
+                n = n - 1;
+                return n;
+              };
+            }
+            for x in countdown(5) {
+              if x == 3 { continue; }
+              if x == 1 { break; }
+              array_push(seen, x);
+            }
+            seen;
+        "#;
+        let value = vm.eval("test", source).unwrap();
+        assert_eq!(
+            vm.display(&value),
+            "[[0, 10], [1, 20], a, b, [a, 1], [b, 2], 4, 2]"
+        );
+
+        assert_eq!(
+            vm.eval("error", "for x in 1 {}").map_err(|e| e.kind()),
+            Err("TypeError")
+        );
+
+        // iterator functions are called without leaving the interpreter loop
+        let source = r#"
+            function gen() { for x in gen {} return null; }
+            let kind = null;
+            try { gen(); } catch (e) { kind = e[0]; }
+            kind;
+        "#;
+        assert_eq!(display(&mut vm, source), "limit_error");
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut vm = Vm::new();
+        assert_eq!(display(&mut vm, "let n = 3; 0..n - 1;"), "0..2");
+        assert_eq!(
+            display(
+                &mut vm,
+                "let a = []; for i in 1..=n { array_push(a, i); } a;"
+            ),
+            "[1, 2, 3]"
+        );
+        assert_eq!(display(&mut vm, "a[1..3];"), "[2, 3]");
+        assert_eq!(
+            display(
+                &mut vm,
+                r#"[3 in 1..3, 3 in 1..=3, 2 in a, "b" in { b: 1 }];"#
+            ),
+            "[false, true, true, true]"
+        );
+
+        for (source, kind) in &[
+            ("a[2..4];", "IndexError"),
+            ("1..2.5;", "TypeError"),
+            ("1 in 2;", "TypeError"),
+            (
+                "length((-9223372036854775807 - 1)..9223372036854775807);",
+                "RuntimeError",
+            ),
+        ] {
+            assert_eq!(vm.eval("error", source).map_err(|e| e.kind()), Err(*kind));
+        }
+    }
+
+    #[test]
+    fn test_strings() {
+        let mut vm = Vm::new();
+        vm.eval("setup", r#"let s = "héllo wörld";"#).unwrap();
+
+        assert_eq!(
+            display(&mut vm, "[s[1], s[6..11], length(s)];"),
+            "[é, wörld, 11]"
+        );
+        assert_eq!(display(&mut vm, r#"split(s, " ");"#), "[héllo, wörld]");
+        assert_eq!(
+            display(&mut vm, r#"join(split("a,b", ","), "; ");"#),
+            "a; b"
+        );
+        assert_eq!(
+            display(&mut vm, r#"[find(s, "w"), find(s, "x")];"#),
+            "[6, null]"
+        );
+        assert_eq!(
+            display(&mut vm, r#"replace(upper(trim(" ab ")), "B", lower("C"));"#),
+            "Ac"
+        );
+        assert_eq!(
+            display(&mut vm, r#"[starts_with(s, "hé"), ends_with(s, "o")];"#),
+            "[true, false]"
+        );
+        assert_eq!(display(&mut vm, r#"repeat("ab", 2);"#), "abab");
+
+        for (source, kind) in &[
+            ("s[11];", "IndexError"),
+            ("s[10..12];", "IndexError"),
+            ("s[null];", "TypeError"),
+            (r#"s[0] = "x";"#, "TypeError"),
+            (r#"join([1], "");"#, "TypeError"),
+        ] {
+            assert_eq!(vm.eval("error", source).map_err(|e| e.kind()), Err(*kind));
+        }
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let mut vm = Vm::new();
+        let source = r#"
+            let x = 1;
+            let s = "a" + "b";
+            s += "c";
+            "${s}: ${x + 1} ${[x, "y"]} ${"${x}"}";
+        "#;
+        let value = vm.eval("test", source).unwrap();
+        assert_eq!(value, Value::from("abc: 2 [1, y] 1"));
+
+        let error = vm.eval("error", r#""a" + 1;"#).unwrap_err();
+        assert_eq!(error.kind(), "TypeError");
+        assert_eq!(error.message(), r#"Got unexpected string "a" in addition"#);
+    }
+
+    #[test]
+    fn test_match() {
+        let mut vm = Vm::new();
+        let source = r#"
+            let OK = :ok;
+            let ERROR = :error;
+            record Point { x, y }
+
+            function point(x, y) {
+              return Point { x = x, y = y };
+            }
+
+            function describe(value) {
+              return match value {
+                [OK, data] => "ok ${data}",
+                [ERROR, _, code] if code >= 500 => "server error ${code}",
+                [ERROR, data, code] => "error ${code}: ${data}",
+                0..10 => "digit",
+                -3..=-1 => "negative",
+                [first, ...rest] => "${first} then ${rest}",
+                Point { x = 0, y } => "on the y axis at ${y}",
+                Point { x, y } if x == y => "diagonal",
+                { name: n, "age": age } => "${n} is ${age}",
+                null => "nothing",
+                _ => "something else",
+              };
+            }
+        "#;
+        vm.eval("setup", source).unwrap();
+
+        for (value, expected) in &[
+            ("[:ok, 1]", "ok 1"),
+            ("[:error, :timeout, 504]", "server error 504"),
+            ("[:error, :not_found, 404]", "error 404: not_found"),
+            ("7", "digit"),
+            ("10", "something else"),
+            ("-3", "negative"),
+            ("[1, 2, 3]", "1 then [2, 3]"),
+            ("[1]", "1 then []"),
+            ("[]", "something else"),
+            ("point(0, 2)", "on the y axis at 2"),
+            ("point(2, 2)", "diagonal"),
+            ("point(1, 2)", "something else"),
+            (r#"{ name: "ann", age: 3, extra: 1 }"#, "ann is 3"),
+            (r#"{ name: "ann" }"#, "something else"),
+            ("null", "nothing"),
+        ] {
+            assert_eq!(
+                display(&mut vm, &format!("describe({});", value)),
+                *expected
+            );
+        }
+
+        // match statements take blocks
+        let source = r#"
+            let total = 0;
+            for pair in [[1, [2, 3]], [4, [5]]] {
+              match pair {
+                [a, [b, c]] => {
+                  total += a + b + c;
+                }
+                [a, [b]] => total += a * b,
+              }
+            }
+            total;
+        "#;
+        assert_eq!(vm.eval("test", source).unwrap(), Value::from(26));
+
+        let error = vm.eval("error", "match 3 { 1 => 2 }").unwrap_err();
+        assert_eq!(error.kind(), "RuntimeError");
+        assert_eq!(error.message(), "No pattern matches 3");
+
+        // arms testing the same parts share the tests, and the later arms
+        // are still tried when a guard fails
+        let source = r#"
+            function shared(value) {
+              return match value {
+                [1, [2, 4]] => :a,
+                [1, [2, y]] if y > 5 => :b,
+                [1, [z, 3]] => z,
+                [1, _, ...rest] => rest,
+                [x, y] => y,
+                _ => :none,
+              };
+            }
+            [shared([1, [2, 4]]), shared([1, [2, 6]]), shared([1, [2, 3]]),
+             shared([1, [7, 3]]), shared([1, 2, 3]), shared([3, 4]), shared([1, [2, 5]]),
+             match 1 { _ => :first, 1 => :second }];
+        "#;
+        assert_eq!(display(&mut vm, source), "[a, b, 2, 7, [3], 4, [], first]");
+    }
+
+    #[test]
+    fn test_match_scope() {
+        let mut vm = Vm::new();
+
+        // an arm's variables only exist within the arm, whether its guard
+        // passes or not
+        let source = r#"
+            let x = "original";
+            let failed = match [2] { [x] if false => 0, _ => 1 };
+            let passed = match [3] { [x] if x == 3 => x, _ => 0 };
+            match [4] {
+              [x] => {
+                x += 1;
+                failed += x;
+              }
+            }
+            [x, failed, passed];
+        "#;
+        assert_eq!(display(&mut vm, source), "[original, 6, 3]");
+
+        let source = r#"
+            function local() {
+              let x = "original";
+              let y = match [2] { [x] if false => 0, [x] => x };
+              return [x, y];
+            }
+            local();
+        "#;
+        assert_eq!(display(&mut vm, source), "[original, 2]");
+
+        // functions in an arm see its variables, and a variable declared in
+        // an arm's block hides them for the rest of it
+        let source = r#"
+            let getter = match [5] { [x] => function() { return x; } };
+            function nested() {
+              return match [6] { [x] => function() { return x; } };
+            }
+            let shadowed = null;
+            match [7] {
+              [x] => {
+                let x = 8;
+                shadowed = x;
+              }
+            }
+            [getter(), nested()(), shadowed, x];
+        "#;
+        assert_eq!(display(&mut vm, source), "[5, 6, 8, 8]");
+    }
+
+    #[test]
+    fn test_artifact() {
+        let mut vm = Vm::new();
+        vm.compile_str("test", "module Test; export let answer = 6 * 7;")
+            .unwrap();
+        let bytes = vm.artifact();
+
+        let mut vm = Vm::new();
+        let script = vm.load_artifact(&bytes).unwrap();
+        vm.run(script).unwrap();
+        assert_eq!(vm.export("Test", "answer"), Ok(Value::from(42)));
+        assert_eq!(vm.eval("more", "println;").map(|_| ()), Ok(()));
+
+        assert!(vm.load_artifact(&bytes).is_err());
+    }
+}
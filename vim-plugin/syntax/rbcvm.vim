@@ -29,7 +29,7 @@ syntax match   jsDot            /\./ skipwhite skipempty nextgroup=jsFuncCall
 syntax match   jsFuncCall       /\<\K\k*\ze\s*(/
 
 " Program Keywords
-syntax keyword jsStorageClass   let skipwhite skipempty nextgroup=jsVariableDef
+syntax keyword jsStorageClass   let record skipwhite skipempty nextgroup=jsVariableDef
 syntax match   jsVariableDef    contained /\<\K\k*/ skipwhite skipempty nextgroup=jsFlowDefinition
 syntax match   jsOperator       "[-!|&+<>=%/*~^]" skipwhite skipempty nextgroup=@jsExpression
 syntax match   jsOperator       /::/ skipwhite skipempty nextgroup=@jsExpression
@@ -60,6 +60,7 @@ syntax keyword jsStatement     contained break continue skipwhite skipempty
 syntax keyword jsConditional            if                     skipwhite skipempty nextgroup=jsParenIfElse
 syntax keyword jsConditional            else                   skipwhite skipempty nextgroup=jsCommentIfElse,jsIfElseBlock
 syntax keyword jsRepeat                 while for              skipwhite skipempty nextgroup=jsParenRepeat,jsForAwait
+syntax keyword jsException              try catch throw
 
 " Keywords
 syntax keyword jsBuiltins           print println tostring type_of array_new array_length string_chars string_bytes string_concat chr ord truncate32 read_file
@@ -97,7 +98,7 @@ if exists("javascript_plugin_jsdoc")
 endif
 
 syntax cluster jsExpression  contains=jsBracket,jsParen,jsString,jsNumber,jsFloat,jsOperator,jsBooleanTrue,jsBooleanFalse,jsNull,jsFunction,jsFuncCall,jsNan,jsBuiltins,jsNoise,jsComment,jsStatement,jsDot
-syntax cluster jsAll         contains=@jsExpression,jsStorageClass,jsConditional,jsRepeat,jsReturn,jsNoise
+syntax cluster jsAll         contains=@jsExpression,jsStorageClass,jsConditional,jsRepeat,jsException,jsReturn,jsNoise
 
 " Define the default highlighting.
 " For version 5.7 and earlier: only when not done already
@@ -120,6 +121,7 @@ if version >= 508 || !exists("did_javascript_syn_inits")
   HiLink jsBranch               Conditional
   HiLink jsReturn               Statement
   HiLink jsRepeat               Repeat
+  HiLink jsException            Exception
   HiLink jsStatement            Statement
   HiLink jsFunction             Type
   HiLink jsFuncName             Function